@@ -1,28 +1,587 @@
-use std::collections::HashMap;
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
 
-pub struct Instruction {
-    address: usize,
-    line: usize,
-    opcode: ,
-    u_argument: ,
-    m_argument: ,
-    comment: Option<String>,
+//! Two pass VCPU16 assembler
+//!
+//! Each line holds an optional label (`:name` or `name:`), an optional instruction or `DAT`
+//! directive and an optional `;` comment. Operands are registers, `[reg]`, `[reg + expr]`,
+//! `[expr]`, `PUSH`, `POP`, `PEEK`, `PICK expr` or expressions. Expressions add and subtract
+//! decimal, `0x` hex, `0b` binary or `'c'` character numbers and labels. Constants from -1 to
+//! 30 in the upper operand use short literals, labels always use the next Word.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use system2::{Symbolizer, Word};
+
+/// Error assembling or parsing a source line
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AssemblerError {
+    /// Source line, starting at 1
+    pub line: usize,
+    /// Description of the problem
+    pub message: String,
 }
 
-pub struct Context {
-    source: String,
-    output: Vec<u16>,
-    labels: HashMap<String, usize>,
+impl AssemblerError {
+    fn new(line: usize, message: String) -> AssemblerError {
+        AssemblerError { line, message }
+    }
 }
 
-impl Context {
-    pub fn assemble(source: String) -> Vec<u16> {
-        let lines: Vec<&str> = source.split('\n').collect();
-        let labels: HashMap<String, usize> = HashMap::new();
-        let output: Vec<u16> = Vec::new();
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
 
-        for line in lines {
+/// Labels and source lines of an assembled Program
+///
+/// Saved as text, one `label 0xADDR name` or `line 0xADDR number` entry per line.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Symbols {
+    labels: BTreeMap<String, Word>,
+    lines: BTreeMap<Word, usize>,
+}
 
+impl Symbols {
+    /// Create empty Symbols
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+    /// Are there no labels or lines
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty() && self.lines.is_empty()
+    }
+    /// Define label at address
+    pub fn add_label(&mut self, name: &str, address: Word) {
+        self.labels.insert(name.to_string(), address);
+    }
+    /// Record that the Words at address were assembled from source line
+    pub fn add_line(&mut self, address: Word, line: usize) {
+        self.lines.insert(address, line);
+    }
+    /// Address of label
+    pub fn label(&self, name: &str) -> Option<Word> {
+        self.labels.get(name).cloned()
+    }
+    /// All labels by name
+    pub fn labels(&self) -> &BTreeMap<String, Word> {
+        &self.labels
+    }
+    /// Source line the Word at address was assembled from
+    pub fn line(&self, address: Word) -> Option<usize> {
+        self.lines.range(..=address).next_back().map(|(_, &line)| line)
+    }
+    /// Does a source statement start at address
+    pub fn starts_line(&self, address: Word) -> bool {
+        self.lines.contains_key(&address)
+    }
+    /// First address assembled from line, or from the next line with code
+    pub fn address(&self, line: usize) -> Option<Word> {
+        self.lines.iter()
+            .filter(|&(_, &source)| source >= line)
+            .min_by_key(|&(&address, &source)| (source, address))
+            .map(|(&address, _)| address)
+    }
+    /// Nearest label at or below address and the offset from it
+    pub fn lookup(&self, address: Word) -> Option<(&str, Word)> {
+        self.labels.iter()
+            .filter(|&(_, &label)| label <= address)
+            .max_by_key(|&(_, &label)| label)
+            .map(|(name, &label)| (name.as_str(), address - label))
+    }
+}
+
+impl Symbolizer for Symbols {
+    fn symbolize(&self, address: Word) -> Option<String> {
+        self.lookup(address).map(|(name, offset)| match offset {
+            0 => name.to_string(),
+            offset => format!("{}+{}", name, offset),
+        })
+    }
+}
+
+impl fmt::Display for Symbols {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, address) in &self.labels {
+            writeln!(f, "label 0x{:04X} {}", address, name)?;
         }
+        for (address, line) in &self.lines {
+            writeln!(f, "line 0x{:04X} {}", address, line)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Symbols {
+    type Err = AssemblerError;
+    fn from_str(text: &str) -> Result<Symbols, AssemblerError> {
+        let mut symbols = Symbols::new();
+        for (index, entry) in text.lines().enumerate() {
+            let fields: Vec<&str> = entry.split_whitespace().collect();
+            let error = || AssemblerError::new(index + 1, format!("invalid symbol entry '{}'", entry));
+            match fields.as_slice() {
+                [] => {}
+                ["label", address, name] => symbols.add_label(name, number(address).ok_or_else(error)? as Word),
+                ["line", address, line] => {
+                    let line = line.parse().map_err(|_| error())?;
+                    symbols.add_line(number(address).ok_or_else(error)? as Word, line);
+                }
+                _ => return Err(error()),
+            }
+        }
+        Ok(symbols)
     }
-}
\ No newline at end of file
+}
+
+/// Assembled Words and their Symbols, starting at address 0
+#[derive(Clone, Debug)]
+pub struct Program {
+    /// Machine code
+    pub words: Vec<Word>,
+    /// Labels and source lines
+    pub symbols: Symbols,
+}
+
+/// Assemble source into a Program
+pub fn assemble(source: &str) -> Result<Program, AssemblerError> {
+    let mut statements = Vec::new();
+    let mut symbols = Symbols::new();
+    let mut address: usize = 0;
+
+    // First pass sizes each statement and places labels
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let error = |message: String| AssemblerError::new(line, message);
+        let mut rest = strip_comment(text).trim();
+        if rest.starts_with(':') || rest.split_whitespace().next().is_some_and(|token| token.ends_with(':')) {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let name = rest[..end].trim_matches(':');
+            if name.is_empty() || !name.chars().all(|ch| ch.is_alphanumeric() || ch == '_' || ch == '.') {
+                return Err(error(format!("invalid label '{}'", name)));
+            }
+            if symbols.label(name).is_some() {
+                return Err(error(format!("duplicate label '{}'", name)));
+            }
+            symbols.add_label(name, address as Word);
+            rest = rest[end..].trim();
+        }
+        if rest.is_empty() {
+            continue;
+        }
+        let statement = Statement::parse(rest).map_err(error)?;
+        let size = statement.size();
+        if address + size > 0x10000 {
+            return Err(error(String::from("program exceeds memory")));
+        }
+        symbols.add_line(address as Word, line);
+        statements.push((line, statement));
+        address += size;
+    }
+
+    // Second pass resolves labels and encodes
+    let mut words = Vec::with_capacity(address);
+    for (line, statement) in &statements {
+        statement.encode(&symbols, &mut words).map_err(|message| AssemblerError::new(*line, message))?;
+    }
+    Ok(Program { words, symbols })
+}
+
+/// Remove a trailing comment outside of quotes
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    for (index, ch) in text.char_indices() {
+        match (quote, ch) {
+            (None, ';') => return &text[..index],
+            (None, '"') | (None, '\'') => quote = Some(ch),
+            (Some(open), _) if open == ch => quote = None,
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Split on commas outside of quotes
+fn split_operands(text: &str) -> Vec<&str> {
+    let mut operands = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (index, ch) in text.char_indices() {
+        match (quote, ch) {
+            (None, ',') => {
+                operands.push(text[start..index].trim());
+                start = index + 1;
+            }
+            (None, '"') | (None, '\'') => quote = Some(ch),
+            (Some(open), _) if open == ch => quote = None,
+            _ => {}
+        }
+    }
+    operands.push(text[start..].trim());
+    operands
+}
+
+/// Parse a decimal, `0x` hex, `0b` binary or `'c'` character number
+fn number(text: &str) -> Option<i32> {
+    let lower = text.to_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i32::from_str_radix(binary, 2).ok()
+    } else if text.len() >= 3 && text.starts_with('\'') && text.ends_with('\'') {
+        let mut chars = text[1..text.len() - 1].chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) => Some(ch as i32),
+            _ => None,
+        }
+    } else {
+        text.parse().ok()
+    }
+}
+
+/// Sum of signed numbers and labels
+#[derive(Clone, Debug)]
+struct Expression {
+    terms: Vec<(bool, Term)>,
+}
+
+#[derive(Clone, Debug)]
+enum Term {
+    Number(i32),
+    Label(String),
+}
+
+impl Expression {
+    fn parse(text: &str) -> Result<Expression, String> {
+        let mut terms = Vec::new();
+        let mut negative = false;
+        let mut start = 0;
+        let bytes = text.as_bytes();
+        for index in 0..=bytes.len() {
+            let end = index == bytes.len();
+            // Signs inside character literals are part of the term
+            let sign = !end && (bytes[index] == b'+' || bytes[index] == b'-')
+                && !(index > 0 && bytes[index - 1] == b'\'' && index + 1 < bytes.len() && bytes[index + 1] == b'\'');
+            if end || sign {
+                let token = text[start..index].trim();
+                if token.is_empty() {
+                    // Only a leading sign may have no term before it
+                    if end || start != 0 {
+                        return Err(format!("invalid expression '{}'", text));
+                    }
+                } else {
+                    let term = match number(token) {
+                        Some(value) => Term::Number(value),
+                        None if token.chars().all(|ch| ch.is_alphanumeric() || ch == '_' || ch == '.') => {
+                            Term::Label(token.to_string())
+                        }
+                        None => return Err(format!("invalid expression '{}'", text)),
+                    };
+                    terms.push((negative, term));
+                }
+                if !end {
+                    negative = bytes[index] == b'-';
+                    start = index + 1;
+                }
+            }
+        }
+        if terms.is_empty() {
+            return Err(format!("invalid expression '{}'", text));
+        }
+        Ok(Expression { terms })
+    }
+    /// Value if the Expression has no labels
+    fn constant(&self) -> Option<i32> {
+        self.terms.iter().try_fold(0i32, |sum, &(negative, ref term)| match *term {
+            Term::Number(value) => Some(if negative { sum - value } else { sum + value }),
+            Term::Label(_) => None,
+        })
+    }
+    /// Value with labels resolved
+    fn evaluate(&self, symbols: &Symbols) -> Result<Word, String> {
+        let mut sum = 0i32;
+        for &(negative, ref term) in &self.terms {
+            let value = match *term {
+                Term::Number(value) => value,
+                Term::Label(ref name) => match symbols.label(name) {
+                    Some(address) => address as i32,
+                    None => return Err(format!("undefined label '{}'", name)),
+                },
+            };
+            sum = if negative { sum - value } else { sum + value };
+        }
+        if (-0x8000..=0xFFFF).contains(&sum) {
+            Ok(sum as Word)
+        } else {
+            Err(format!("value {} does not fit in a Word", sum))
+        }
+    }
+}
+
+/// Operand with its encoding and optional next Word
+#[derive(Clone, Debug)]
+struct Argument {
+    code: Word,
+    next: Option<Expression>,
+}
+
+impl Argument {
+    fn parse(text: &str, upper: bool) -> Result<Argument, String> {
+        let upper_text = text.to_uppercase();
+        let code = match upper_text.as_str() {
+            "PUSH" if !upper => Some(0x18),
+            "POP" if upper => Some(0x18),
+            "PUSH" | "POP" => return Err(format!("{} is not valid here", upper_text)),
+            "PEEK" | "[SP]" => Some(0x19),
+            "SP" => Some(0x1B),
+            "PC" => Some(0x1C),
+            "PS" | "EX" => Some(0x1D),
+            _ => general(&upper_text),
+        };
+        if let Some(code) = code {
+            return Ok(Argument { code, next: None });
+        }
+        if upper_text.starts_with("PICK ") {
+            return Ok(Argument { code: 0x1A, next: Some(Expression::parse(&text[5..])?) });
+        }
+        if text.starts_with('[') && text.ends_with(']') {
+            let inner = text[1..text.len() - 1].trim();
+            if let Some(code) = general(&inner.to_uppercase()) {
+                return Ok(Argument { code: code + 0x08, next: None });
+            }
+            // Find a register among the terms of [reg + expr] or [expr + reg]
+            for (index, part) in inner.split('+').enumerate() {
+                let name = part.trim().to_uppercase();
+                let code = match name.as_str() {
+                    "SP" => Some(0x1A),
+                    _ => general(&name).map(|code| code + 0x10),
+                };
+                if let Some(code) = code {
+                    let rest: Vec<&str> = inner.split('+')
+                        .enumerate()
+                        .filter(|&(other, _)| other != index)
+                        .map(|(_, part)| part)
+                        .collect();
+                    return Ok(Argument { code, next: Some(Expression::parse(&rest.join("+"))?) });
+                }
+            }
+            return Ok(Argument { code: 0x1E, next: Some(Expression::parse(inner)?) });
+        }
+        let expression = Expression::parse(text)?;
+        match expression.constant() {
+            Some(value) if upper && (-1..=30).contains(&value) => Ok(Argument { code: (value + 0x21) as Word, next: None }),
+            _ => Ok(Argument { code: 0x1F, next: Some(expression) }),
+        }
+    }
+}
+
+/// General purpose register code
+fn general(name: &str) -> Option<Word> {
+    ["A", "B", "C", "X", "Y", "Z", "I", "J"].iter().position(|&register| register == name).map(|code| code as Word)
+}
+
+/// Source statement
+#[derive(Clone, Debug)]
+enum Statement {
+    /// Nullary instruction Word
+    Nullary(Word),
+    /// Unary opcode and operand
+    Unary(Word, Argument),
+    /// Binary opcode, middle and upper operands
+    Binary(Word, Argument, Argument),
+    /// Data Words
+    Data(Vec<Expression>),
+}
+
+impl Statement {
+    fn parse(text: &str) -> Result<Statement, String> {
+        let end = text.find(char::is_whitespace).unwrap_or(text.len());
+        let mnemonic = text[..end].to_uppercase();
+        let rest = text[end..].trim();
+        let operands = if rest.is_empty() { Vec::new() } else { split_operands(rest) };
+        if mnemonic == "DAT" {
+            let mut values = Vec::new();
+            for operand in operands {
+                if operand.len() >= 2 && operand.starts_with('"') && operand.ends_with('"') {
+                    for ch in operand[1..operand.len() - 1].chars() {
+                        values.push(Expression { terms: vec![(false, Term::Number(ch as i32))] });
+                    }
+                } else {
+                    values.push(Expression::parse(operand)?);
+                }
+            }
+            return Ok(Statement::Data(values));
+        }
+        let count = |expected: usize| if operands.len() == expected {
+            Ok(())
+        } else {
+            Err(format!("{} takes {} operands", mnemonic, expected))
+        };
+        if let Some(word) = nullary(&mnemonic) {
+            count(0)?;
+            return Ok(Statement::Nullary(word));
+        }
+        if let Some(code) = unary(&mnemonic) {
+            count(1)?;
+            return Ok(Statement::Unary(code, Argument::parse(operands[0], true)?));
+        }
+        if let Some(code) = binary(&mnemonic) {
+            count(2)?;
+            let m = Argument::parse(operands[0], false)?;
+            let u = Argument::parse(operands[1], true)?;
+            return Ok(Statement::Binary(code, m, u));
+        }
+        Err(format!("unknown instruction '{}'", mnemonic))
+    }
+    /// Size in Words
+    fn size(&self) -> usize {
+        let next = |argument: &Argument| argument.next.is_some() as usize;
+        match *self {
+            Statement::Nullary(_) => 1,
+            Statement::Unary(_, ref u) => 1 + next(u),
+            Statement::Binary(_, ref m, ref u) => 1 + next(m) + next(u),
+            Statement::Data(ref values) => values.len(),
+        }
+    }
+    /// Append encoded Words, upper operand's next Word first
+    fn encode(&self, symbols: &Symbols, words: &mut Vec<Word>) -> Result<(), String> {
+        let mut arguments = Vec::new();
+        match *self {
+            Statement::Nullary(word) => words.push(word),
+            Statement::Unary(code, ref u) => {
+                words.push(u.code << 10 | code << 5);
+                arguments.push(u);
+            }
+            Statement::Binary(code, ref m, ref u) => {
+                words.push(u.code << 10 | m.code << 5 | code);
+                arguments.push(u);
+                arguments.push(m);
+            }
+            Statement::Data(ref values) => {
+                for value in values {
+                    words.push(value.evaluate(symbols)?);
+                }
+            }
+        }
+        for argument in arguments {
+            if let Some(ref next) = argument.next {
+                words.push(next.evaluate(symbols)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Nullary instruction Word
+fn nullary(mnemonic: &str) -> Option<Word> {
+    match mnemonic {
+        "NOP" => Some(0x0000),
+        "CLK" => Some(0x0400),
+        "ERR" => Some(0xFC00),
+        _ => None,
+    }
+}
+
+/// Unary opcode
+fn unary(mnemonic: &str) -> Option<Word> {
+    match mnemonic {
+        "JSR" => Some(0x01),
+        "INT" => Some(0x08),
+        "IAG" => Some(0x09),
+        "IAS" => Some(0x0A),
+        "RFI" => Some(0x0B),
+        "IAQ" => Some(0x0C),
+        "HWN" => Some(0x10),
+        "HWQ" => Some(0x11),
+        "HWI" => Some(0x12),
+        _ => None,
+    }
+}
+
+/// Binary opcode
+fn binary(mnemonic: &str) -> Option<Word> {
+    const NAMES: [&str; 31] = [
+        "", "SET", "ADD", "SUB", "MUL", "MLI", "DIV", "DVI", "MOD", "MDI", "AND", "BOR", "XOR", "LLS", "LRS", "ARS",
+        "IFB", "IFC", "IFE", "IFN", "IFG", "IFA", "IFL", "IFU", "", "", "ADX", "SBX", "", "", "STI",
+    ];
+    match mnemonic {
+        "" => None,
+        "STD" => Some(0x1F),
+        _ => NAMES.iter().position(|&name| name == mnemonic).map(|code| code as Word),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use system2::{Instruction, Memory};
+    use super::{assemble, Symbols};
+
+    #[test]
+    pub fn test_assemble() {
+        let program = assemble(
+            "; Count to ten\n\
+             :start  SET A, 0\n\
+             loop:   ADD A, 1          ; short literal\n\
+             \x20       SET [counter], A\n\
+             \x20       IFN A, 10\n\
+             \x20       SET PC, loop\n\
+             \x20       SET [B + 2], [0x1000 + 3]\n\
+             \x20       SET PUSH, PICK 1\n\
+             \x20       ERR\n\
+             counter: DAT 0, \"hi\", 'x', start - 1\n",
+        ).unwrap();
+
+        let mut mem = Memory::new();
+        mem.write(0, &program.words).unwrap();
+        let listing: Vec<String> = [0x0000, 0x0001, 0x0002, 0x0004, 0x0005, 0x0007, 0x000A, 0x000C]
+            .iter()
+            .map(|&address| Instruction::decode(&mem, address).to_string())
+            .collect();
+        assert_eq!(vec![
+            "SET A, 0x0000",
+            "ADD A, 0x0001",
+            "SET [0x000D], A",
+            "IFN A, 0x000A",
+            "SET PC, 0x0001",
+            "SET [B + 0x0002], [0x1003]",
+            "SET PUSH, [SP + 0x0001]",
+            "ERR",
+        ], listing);
+        assert_eq!(&[0x0000, 0x0068, 0x0069, 0x0078, 0xFFFF], &program.words[0x0D..]);
+
+        // Symbols map labels and lines both ways
+        let symbols = &program.symbols;
+        assert_eq!(Some(0x000D), symbols.label("counter"));
+        assert_eq!(Some(3), symbols.line(0x0001));
+        assert_eq!(Some(4), symbols.line(0x0003));
+        assert_eq!(Some(0x0001), symbols.address(3));
+        assert_eq!(Some(0x0000), symbols.address(1));
+        assert_eq!(Some(("loop", 2)), symbols.lookup(0x0003));
+        assert_eq!(*symbols, symbols.to_string().parse::<Symbols>().unwrap());
+    }
+
+    #[test]
+    pub fn test_errors() {
+        assert_eq!(2, assemble("NOP\nSET A").unwrap_err().line);
+        assert_eq!("line 1: undefined label 'nowhere'", assemble("SET PC, nowhere").unwrap_err().to_string());
+        assert_eq!("line 2: duplicate label 'a1'", assemble(":a1 NOP\n:a1 NOP").unwrap_err().to_string());
+        assert_eq!("line 1: POP is not valid here", assemble("SET POP, 1").unwrap_err().to_string());
+        assert_eq!("line 1: unknown instruction 'FOO'", assemble("FOO A").unwrap_err().to_string());
+        assert_eq!("line 1: invalid expression '1 +'", assemble("DAT 1 +").unwrap_err().to_string());
+        assert_eq!(vec![0xFFFF, 0x002D], assemble("DAT -1, '-'").unwrap().words);
+    }
+}
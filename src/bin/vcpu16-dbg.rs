@@ -0,0 +1,56 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Interactive VCPU16 debugger
+//!
+//! Usage: `vcpu16-dbg <program.asm | image.bin | core> [symbols.sym]`
+
+extern crate vcpu16;
+
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::process;
+use vcpu16::debug::Repl;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("usage: {} <program.asm | image.bin | core> [symbols.sym]", args[0]);
+        process::exit(2);
+    }
+    let mut repl = match Repl::open(&args[1], args.get(2).map(String::as_str)) {
+        Ok(repl) => repl,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(1);
+        }
+    };
+    println!("{}", repl.location());
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    while !repl.is_finished() {
+        print!("(vcpu16) ");
+        io::stdout().flush().unwrap();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        let output = repl.execute(&line);
+        if !output.is_empty() {
+            println!("{}", output);
+        }
+    }
+}
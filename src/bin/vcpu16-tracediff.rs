@@ -0,0 +1,86 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Find the first divergence between two text format execution traces
+//!
+//! Usage: `vcpu16-tracediff [--context N] [--cycles] <left.trace> <right.trace>`
+//!
+//! Exits 0 when the traces match, 1 when they diverge and 2 on bad input.
+
+extern crate vcpu16;
+
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::process;
+use vcpu16::system2::{Trace, TraceDiff};
+
+/// Read every line of a trace file, exiting on malformed input
+fn load(path: &str) -> Vec<Trace> {
+    let file = File::open(path).unwrap_or_else(|error| {
+        eprintln!("{}: {}", path, error);
+        process::exit(2);
+    });
+    let mut traces = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.unwrap_or_else(|error| {
+            eprintln!("{}: {}", path, error);
+            process::exit(2);
+        });
+        if line.trim().is_empty() {
+            continue;
+        }
+        match line.parse() {
+            Ok(trace) => traces.push(trace),
+            Err(error) => {
+                eprintln!("{}:{}: {}", path, number + 1, error);
+                process::exit(2);
+            }
+        }
+    }
+    traces
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let usage = || -> ! {
+        eprintln!("usage: {} [--context N] [--cycles] <left.trace> <right.trace>", args[0]);
+        process::exit(2);
+    };
+    let mut diff = TraceDiff::new();
+    let mut paths = Vec::new();
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--context" => match rest.next().and_then(|count| count.parse().ok()) {
+                Some(count) => diff = diff.context(count),
+                None => usage(),
+            },
+            "--cycles" => diff = diff.cycles(true),
+            path => paths.push(path.to_string()),
+        }
+    }
+    if paths.len() != 2 {
+        usage();
+    }
+    match diff.compare(load(&paths[0]), load(&paths[1])) {
+        Some(divergence) => {
+            print!("{}", divergence);
+            process::exit(1);
+        }
+        None => println!("Traces match"),
+    }
+}
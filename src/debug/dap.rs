@@ -0,0 +1,784 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Debug Adapter Protocol server
+//!
+//! Memory references are Word addresses written as `0x` hex and `readMemory` returns each
+//! Word low byte first. Source breakpoints and line stepping use the Symbols of the program,
+//! either assembled on `launch` of a `.asm` source or loaded from a symbol file.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use assembler::{assemble, Symbols};
+use system2::{Breakpoint, Condition, Event, Instruction, Register, Stop, System, SystemError, Trigger, Word};
+use super::json::Json;
+use super::Transport;
+
+/// Cycles run between checks for a request while continuing
+const BATCH: u64 = 10_000;
+
+/// Instructions stepped over a single source line before giving up
+const LINE_LIMIT: usize = 100_000;
+
+/// Cycles run by next and stepOut waiting for a call to return before giving up
+const CALL_LIMIT: u64 = 10_000_000;
+
+/// Variables reference of the Registers scope
+const REGISTERS: i64 = 1;
+
+/// Variables reference of the Labels scope
+const LABELS: i64 = 2;
+
+/// Words of memory, which bound the offsets and counts a client may ask for
+const SPACE: i64 = 0x10000;
+
+/// Debug Adapter Protocol server for a System
+pub struct DapServer<'sys> {
+    system: &'sys mut System,
+    symbols: Symbols,
+    /// Path of the source the Symbols describe
+    source: Option<String>,
+    /// System Breakpoint ids set from source lines
+    lines: Vec<usize>,
+    /// Stop with reason entry once configuration is done
+    stop_on_entry: bool,
+    /// Continuing, between requests which arrive while the System runs
+    running: bool,
+    /// Sequence number of the next message sent
+    seq: i64,
+}
+
+impl<'sys> DapServer<'sys> {
+    /// Create a server for system
+    pub fn new(system: &'sys mut System) -> DapServer<'sys> {
+        DapServer {
+            system,
+            symbols: Symbols::new(),
+            source: None,
+            lines: Vec::new(),
+            stop_on_entry: false,
+            running: false,
+            seq: 1,
+        }
+    }
+    /// Use Symbols describing the source at path when attaching
+    pub fn symbols(mut self, symbols: Symbols, source: &str) -> DapServer<'sys> {
+        self.symbols = symbols;
+        self.source = Some(source.to_string());
+        self
+    }
+    /// Serve requests until the client disconnects
+    ///
+    /// Continuing runs the System until a request arrives, which is handled before running on
+    /// unless it pauses or disconnects.
+    pub fn serve(&mut self, transport: &mut dyn Transport) -> io::Result<()> {
+        while let Some(message) = read_message(transport)? {
+            let request = match Json::parse(&message) {
+                Ok(request) => request,
+                Err(_) => continue,
+            };
+            let command = request.get("command").and_then(Json::as_str).unwrap_or("").to_string();
+            let seq = request.get("seq").and_then(Json::as_i64).unwrap_or(0);
+            let arguments = request.get("arguments").cloned().unwrap_or(Json::Null);
+            let result = self.handle(&command, &arguments);
+            let (success, body) = match result {
+                Ok(ref body) => (true, body.clone()),
+                Err(ref message) => (false, Json::object(vec![("error", Json::object(vec![
+                    ("id", Json::from(1u64)),
+                    ("format", Json::from(message.as_str())),
+                ]))])),
+            };
+            let mut response = vec![
+                ("type", Json::from("response")),
+                ("request_seq", Json::from(seq)),
+                ("success", Json::from(success)),
+                ("command", Json::from(command.as_str())),
+                ("body", body),
+            ];
+            if let Err(ref message) = result {
+                response.push(("message", Json::from(message.as_str())));
+            }
+            self.send(transport, response)?;
+            if !success {
+                continue;
+            }
+            match command.as_str() {
+                "initialize" => self.event(transport, "initialized", Json::object(vec![]))?,
+                "configurationDone" if self.stop_on_entry => self.stopped(transport, "entry", None)?,
+                "configurationDone" | "continue" => self.running = true,
+                "next" | "stepIn" => {
+                    let granularity = arguments.get("granularity").and_then(Json::as_str).unwrap_or("statement");
+                    let stop = self.step(granularity == "instruction", command == "next");
+                    self.report(transport, stop)?;
+                }
+                "stepOut" => {
+                    let stop = self.system.step_out(CALL_LIMIT);
+                    self.report(transport, stop)?;
+                }
+                "pause" => {
+                    self.running = false;
+                    self.stopped(transport, "pause", None)?;
+                }
+                "disconnect" => return Ok(()),
+                _ => {}
+            }
+            if self.running {
+                if let Some(stop) = self.resume(transport)? {
+                    self.running = false;
+                    self.report(transport, stop)?;
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Handle a request, returning the response body
+    fn handle(&mut self, command: &str, arguments: &Json) -> Result<Json, String> {
+        match command {
+            "initialize" => Ok(Json::object(vec![
+                ("supportsConfigurationDoneRequest", Json::from(true)),
+                ("supportsConditionalBreakpoints", Json::from(true)),
+                ("supportsHitConditionalBreakpoints", Json::from(true)),
+                ("supportsDisassembleRequest", Json::from(true)),
+                ("supportsReadMemoryRequest", Json::from(true)),
+                ("supportsSteppingGranularity", Json::from(true)),
+            ])),
+            "launch" => {
+                self.launch(arguments)?;
+                Ok(Json::Null)
+            }
+            "attach" => {
+                if let Some(path) = arguments.get("symbols").and_then(Json::as_str) {
+                    let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+                    self.symbols = text.parse().map_err(|error| format!("{}: {}", path, error))?;
+                }
+                if let Some(path) = arguments.get("source").and_then(Json::as_str) {
+                    self.source = Some(path.to_string());
+                }
+                self.stop_on_entry = true;
+                Ok(Json::Null)
+            }
+            "setBreakpoints" => Ok(self.set_breakpoints(arguments)),
+            "stepOut" if self.system.call_stack().depth() == 0 => Err(String::from("not in a subroutine")),
+            "configurationDone" | "next" | "stepIn" | "stepOut" | "pause" | "disconnect" => Ok(Json::Null),
+            "continue" => Ok(Json::object(vec![("allThreadsContinued", Json::from(true))])),
+            "threads" => Ok(Json::object(vec![("threads", Json::from(vec![
+                Json::object(vec![("id", Json::from(1u64)), ("name", Json::from("VCPU16"))]),
+            ]))])),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(Json::object(vec![("scopes", Json::from(vec![
+                scope("Registers", REGISTERS),
+                scope("Labels", LABELS),
+            ]))])),
+            "variables" => Ok(self.variables(arguments)),
+            "disassemble" => self.disassemble(arguments),
+            "readMemory" => self.read_memory(arguments),
+            _ => Err(format!("{} is not supported", command)),
+        }
+    }
+    /// Load and assemble the program, or load an image and optional symbols
+    fn launch(&mut self, arguments: &Json) -> Result<(), String> {
+        let path = arguments.get("program").and_then(Json::as_str).ok_or("launch requires a program")?;
+        self.stop_on_entry = arguments.get("stopOnEntry").and_then(Json::as_bool).unwrap_or(false);
+        let words = if path.ends_with(".asm") {
+            let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+            let program = assemble(&text).map_err(|error| format!("{}: {}", path, error))?;
+            self.symbols = program.symbols;
+            self.source = Some(path.to_string());
+            program.words
+        } else {
+            let bytes = fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+            if let Some(symbols) = arguments.get("symbols").and_then(Json::as_str) {
+                let text = fs::read_to_string(symbols).map_err(|error| format!("{}: {}", symbols, error))?;
+                self.symbols = text.parse().map_err(|error| format!("{}: {}", symbols, error))?;
+                self.source = arguments.get("source").and_then(Json::as_str).map(String::from);
+            }
+            bytes.chunks(2).map(|pair| u16::from_le_bytes([pair[0], *pair.get(1).unwrap_or(&0)])).collect()
+        };
+        if words.len() > 0x10000 {
+            return Err(format!("{} does not fit in memory", path));
+        }
+        self.system.memory_mut().write(0, &words).map_err(|error| error.to_string())
+    }
+    /// Replace source line breakpoints
+    fn set_breakpoints(&mut self, arguments: &Json) -> Json {
+        for id in self.lines.drain(..) {
+            self.system.remove_breakpoint(id);
+        }
+        let requested = arguments.get("breakpoints").and_then(Json::as_array).unwrap_or(&[]);
+        let mut results = Vec::new();
+        for requested in requested {
+            let line = requested.get("line").and_then(Json::as_i64).unwrap_or(0).max(0) as usize;
+            let address = self.symbols.address(line);
+            let condition = requested.get("condition").and_then(Json::as_str).map(str::parse::<Condition>);
+            let ignore = requested.get("hitCondition").and_then(Json::as_str).map(str::parse::<u64>);
+            let result = match (address, condition, ignore) {
+                (None, _, _) => Err("no code at or after this line"),
+                (_, Some(Err(_)), _) => Err("invalid condition"),
+                (_, _, Some(Err(_))) => Err("hit condition must be a count"),
+                (Some(address), condition, ignore) => {
+                    let mut breakpoint = Breakpoint::new(Trigger::Address(address));
+                    if let Some(Ok(condition)) = condition {
+                        breakpoint = breakpoint.when(condition);
+                    }
+                    if let Some(Ok(count)) = ignore {
+                        breakpoint = breakpoint.ignore(count.saturating_sub(1));
+                    }
+                    let id = self.system.add_breakpoint(breakpoint);
+                    self.lines.push(id);
+                    Ok((id, address))
+                }
+            };
+            results.push(match result {
+                Ok((id, address)) => Json::object(vec![
+                    ("id", Json::from(id)),
+                    ("verified", Json::from(true)),
+                    ("line", Json::from(self.symbols.line(address).unwrap_or(line))),
+                    ("instructionReference", Json::from(reference(address))),
+                ]),
+                Err(message) => Json::object(vec![
+                    ("verified", Json::from(false)),
+                    ("line", Json::from(line)),
+                    ("message", Json::from(message)),
+                ]),
+            });
+        }
+        Json::object(vec![("breakpoints", Json::from(results))])
+    }
+    /// Frames from PC out to the outermost caller
+    fn stack_trace(&self) -> Json {
+        let frames: Vec<Json> = self.system.backtrace(Some(&self.symbols)).into_iter().enumerate()
+            .map(|(id, frame)| {
+                let address = frame.address;
+                let mut name = frame.name.unwrap_or_else(|| reference(address));
+                if let Some(message) = frame.interrupt {
+                    name = format!("{} (interrupt 0x{:04X})", name, message);
+                }
+                let mut entry = vec![
+                    ("id", Json::from(id)),
+                    ("name", Json::from(name)),
+                    ("line", Json::from(0u64)),
+                    ("column", Json::from(0u64)),
+                    ("instructionPointerReference", Json::from(reference(address))),
+                ];
+                if let (Some(line), Some(path)) = (self.symbols.line(address), self.source.as_ref()) {
+                    entry[2].1 = Json::from(line);
+                    entry[3].1 = Json::from(1u64);
+                    entry.push(("source", Json::object(vec![("path", Json::from(path.as_str()))])));
+                }
+                Json::object(entry)
+            })
+            .collect();
+        let total = frames.len();
+        Json::object(vec![
+            ("stackFrames", Json::from(frames)),
+            ("totalFrames", Json::from(total)),
+        ])
+    }
+    /// Registers or the Word at each label
+    fn variables(&self, arguments: &Json) -> Json {
+        let variables = match arguments.get("variablesReference").and_then(Json::as_i64) {
+            Some(REGISTERS) => Register::ALL.iter()
+                .map(|&register| variable(register.name(), self.system.registers().get(register)))
+                .collect(),
+            Some(LABELS) => self.symbols.labels().iter()
+                .map(|(name, &address)| variable(&format!("[{}]", name), self.system.memory().get(address)))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Json::object(vec![("variables", Json::from(variables))])
+    }
+    /// Disassemble instructions around a memory reference
+    fn disassemble(&self, arguments: &Json) -> Result<Json, String> {
+        let base = arguments.get("memoryReference").and_then(Json::as_str).and_then(parse_reference)
+            .ok_or("invalid memory reference")?;
+        let base = base.wrapping_add(bounded(arguments, "offset", SPACE)? as Word);
+        let offset = bounded(arguments, "instructionOffset", SPACE)?;
+        let count = bounded(arguments, "instructionCount", SPACE)?.max(0) as usize;
+        let memory = self.system.memory();
+
+        // Walk forward from well before base to find instruction boundaries behind it
+        let mut address = base;
+        if offset < 0 {
+            let back = offset.unsigned_abs() as usize;
+            let mut starts = Vec::new();
+            let mut scan = (base as usize).saturating_sub(back * 3) as Word;
+            while scan < base {
+                starts.push(scan);
+                scan = scan.wrapping_add(Instruction::decode(memory, scan).size);
+            }
+            address = starts.len().checked_sub(back).map_or((base as usize).saturating_sub(back) as Word, |index| starts[index]);
+        } else {
+            for _ in 0..offset {
+                address = address.wrapping_add(Instruction::decode(memory, address).size);
+            }
+        }
+        let mut instructions = Vec::new();
+        for _ in 0..count {
+            let instruction = Instruction::decode(memory, address);
+            let bytes: String = (0..instruction.size)
+                .map(|index| format!("{:04X}", memory.get(address.wrapping_add(index))))
+                .collect();
+            let mut entry = vec![
+                ("address", Json::from(reference(address))),
+                ("instructionBytes", Json::from(bytes)),
+                ("instruction", Json::from(instruction.to_string())),
+                ("symbol", Json::from(self.describe(address))),
+            ];
+            if let (Some(line), Some(path)) = (self.symbols.line(address), self.source.as_ref()) {
+                entry.push(("line", Json::from(line)));
+                entry.push(("location", Json::object(vec![("path", Json::from(path.as_str()))])));
+            }
+            instructions.push(Json::object(entry));
+            address = address.wrapping_add(instruction.size);
+        }
+        Ok(Json::object(vec![("instructions", Json::from(instructions))]))
+    }
+    /// Read Words as base64 bytes, low byte first
+    fn read_memory(&self, arguments: &Json) -> Result<Json, String> {
+        let base = arguments.get("memoryReference").and_then(Json::as_str).and_then(parse_reference)
+            .ok_or("invalid memory reference")?;
+        let address = base as i64 + bounded(arguments, "offset", SPACE)?;
+        if !(0..SPACE).contains(&address) {
+            return Err(String::from("offset is outside memory"));
+        }
+        let count = bounded(arguments, "count", 2 * SPACE)?.max(0);
+        let words = ((count + 1) / 2).min(SPACE - address);
+        let mut bytes = Vec::new();
+        for index in 0..words {
+            bytes.extend_from_slice(&self.system.memory().get((address + index) as Word).to_le_bytes());
+        }
+        bytes.truncate(count as usize);
+        Ok(Json::object(vec![
+            ("address", Json::from(reference(address as Word))),
+            ("data", Json::from(base64(&bytes))),
+            ("unreadableBytes", Json::from((count as usize - bytes.len()) as u64)),
+        ]))
+    }
+    /// Run until a breakpoint or an error, or None once a request arrives
+    fn resume(&mut self, transport: &mut dyn Transport) -> io::Result<Option<Result<Option<Stop>, SystemError>>> {
+        loop {
+            match self.system.run(BATCH) {
+                Ok(Some(stop)) => return Ok(Some(Ok(Some(stop)))),
+                Ok(None) => {
+                    if transport.pending()? {
+                        return Ok(None);
+                    }
+                }
+                Err(error) => return Ok(Some(Err(error))),
+            }
+        }
+    }
+    /// Step one instruction, over any call it makes when over is set, then on to the next
+    /// source statement when stepping by line
+    fn step(&mut self, instruction: bool, over: bool) -> Result<Option<Stop>, SystemError> {
+        let by_line = !instruction && !self.symbols.is_empty();
+        for _ in 0..LINE_LIMIT {
+            let stop = if over { self.system.step_over(CALL_LIMIT)? } else { self.system.step_instruction()? };
+            if let Some(stop) = stop {
+                return Ok(Some(stop));
+            }
+            if !by_line || self.symbols.starts_line(self.system.registers().pc) {
+                break;
+            }
+        }
+        Ok(None)
+    }
+    /// Send the stopped event for the result of running
+    fn report(&mut self, transport: &mut dyn Transport, result: Result<Option<Stop>, SystemError>) -> io::Result<()> {
+        match result {
+            Ok(Some(stop)) => {
+                let reason = match stop.event {
+                    Event::Read(_) | Event::Write { .. } | Event::Register { .. } => "data breakpoint",
+                    _ => "breakpoint",
+                };
+                self.stopped(transport, reason, Some(stop))
+            }
+            Ok(None) => self.stopped(transport, "step", None),
+            Err(SystemError::StepLimit) => self.event(transport, "stopped", Json::object(vec![
+                ("reason", Json::from("pause")),
+                ("threadId", Json::from(1u64)),
+                ("allThreadsStopped", Json::from(true)),
+                ("description", Json::from(format!("call still running after {} cycles", CALL_LIMIT))),
+            ])),
+            Err(error) => self.event(transport, "stopped", Json::object(vec![
+                ("reason", Json::from("exception")),
+                ("threadId", Json::from(1u64)),
+                ("allThreadsStopped", Json::from(true)),
+                ("text", Json::from(error.to_string())),
+            ])),
+        }
+    }
+    /// Send a stopped event
+    fn stopped(&mut self, transport: &mut dyn Transport, reason: &str, stop: Option<Stop>) -> io::Result<()> {
+        let mut body = vec![
+            ("reason", Json::from(reason)),
+            ("threadId", Json::from(1u64)),
+            ("allThreadsStopped", Json::from(true)),
+        ];
+        if let Some(stop) = stop {
+            body.push(("description", Json::from(stop.to_string())));
+            body.push(("hitBreakpointIds", Json::from(vec![Json::from(stop.id)])));
+        }
+        self.event(transport, "stopped", Json::object(body))
+    }
+    /// Send an event
+    fn event(&mut self, transport: &mut dyn Transport, event: &str, body: Json) -> io::Result<()> {
+        self.send(transport, vec![("type", Json::from("event")), ("event", Json::from(event)), ("body", body)])
+    }
+    /// Send a message with the next sequence number
+    fn send(&mut self, transport: &mut dyn Transport, mut pairs: Vec<(&str, Json)>) -> io::Result<()> {
+        pairs.insert(0, ("seq", Json::from(self.seq)));
+        self.seq += 1;
+        let text = Json::object(pairs).to_string();
+        write!(transport, "Content-Length: {}\r\n\r\n{}", text.len(), text)?;
+        transport.flush()
+    }
+    /// Nearest label and offset, or the address
+    fn describe(&self, address: Word) -> String {
+        match self.symbols.lookup(address) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+{}", name, offset),
+            None => reference(address),
+        }
+    }
+}
+
+/// Integer argument no further than limit from zero, 0 when absent
+fn bounded(arguments: &Json, name: &str, limit: i64) -> Result<i64, String> {
+    match arguments.get(name).and_then(Json::as_i64).unwrap_or(0) {
+        value if (-limit..=limit).contains(&value) => Ok(value),
+        _ => Err(format!("{} is out of range", name)),
+    }
+}
+
+/// Scope with a variables reference
+fn scope(name: &str, reference: i64) -> Json {
+    Json::object(vec![
+        ("name", Json::from(name)),
+        ("variablesReference", Json::from(reference)),
+        ("expensive", Json::from(false)),
+    ])
+}
+
+/// Variable holding a Word
+fn variable(name: &str, value: Word) -> Json {
+    Json::object(vec![
+        ("name", Json::from(name)),
+        ("value", Json::from(format!("0x{:04X}", value))),
+        ("variablesReference", Json::from(0u64)),
+        ("memoryReference", Json::from(reference(value))),
+    ])
+}
+
+/// Memory reference for address
+fn reference(address: Word) -> String {
+    format!("0x{:04X}", address)
+}
+
+/// Parse a memory reference
+fn parse_reference(text: &str) -> Option<Word> {
+    let hex = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X"))?;
+    Word::from_str_radix(hex, 16).ok()
+}
+
+/// Standard base64 with padding
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let value = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for index in 0..4 {
+            if index <= chunk.len() {
+                text.push(ALPHABET[(value >> (18 - index * 6)) as usize & 0x3F] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// Read the next message, None when the client disconnects
+fn read_message(transport: &mut dyn Transport) -> io::Result<Option<String>> {
+    let mut reader = BufReader::with_capacity(1, transport);
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let mut body = vec![0u8; length.unwrap_or(0)];
+    reader.read_exact(&mut body)?;
+    Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::{self, Cursor, Read, Write};
+    use std::process;
+    use system2::System;
+    use super::super::json::Json;
+    use super::super::Transport;
+    use super::{base64, DapServer, BATCH};
+
+    /// Scripted client requests and the server output
+    struct Script {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Script {
+        fn new(requests: &[&str]) -> Script {
+            let mut input = Vec::new();
+            for (seq, request) in requests.iter().enumerate() {
+                let text = request.replacen('{', &format!("{{\"seq\":{},\"type\":\"request\",", seq + 1), 1);
+                write!(input, "Content-Length: {}\r\n\r\n{}", text.len(), text).unwrap();
+            }
+            Script { input: Cursor::new(input), output: Vec::new() }
+        }
+        /// Messages sent by the server
+        fn messages(&self) -> Vec<Json> {
+            let text = String::from_utf8(self.output.clone()).unwrap();
+            text.split("Content-Length: ")
+                .filter(|part| !part.is_empty())
+                .map(|part| Json::parse(&part[part.find("\r\n\r\n").unwrap() + 4..]).unwrap())
+                .collect()
+        }
+    }
+
+    impl Read for Script {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buffer)
+        }
+    }
+
+    impl Write for Script {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            self.output.write(buffer)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for Script {
+        fn pending(&mut self) -> io::Result<bool> {
+            // Every request is sent up front
+            Ok(true)
+        }
+    }
+
+    /// Follow a path of object keys and array indices
+    fn at<'a>(json: &'a Json, path: &[&str]) -> &'a Json {
+        path.iter().fold(json, |json, key| match key.parse::<usize>() {
+            Ok(index) => &json.as_array().unwrap()[index],
+            Err(_) => json.get(key).unwrap_or_else(|| panic!("missing {} in {}", key, json)),
+        })
+    }
+
+    #[test]
+    pub fn test_session() {
+        let path = env::temp_dir().join(format!("vcpu16-dap-{}.asm", process::id()));
+        fs::write(&path, "; counter\n\
+                          :start  SET A, 0\n\
+                          :loop   ADD A, 1\n\
+                          \x20       SET [count], A\n\
+                          \x20       SET PC, loop\n\
+                          :count  DAT 0\n").unwrap();
+        let path = path.to_str().unwrap().replace('\\', "/");
+        let launch = format!(r#"{{"command":"launch","arguments":{{"program":"{}"}}}}"#, path);
+        let breakpoints = format!(
+            r#"{{"command":"setBreakpoints","arguments":{{"source":{{"path":"{}"}},"breakpoints":[{{"line":4,"condition":"A == 3"}},{{"line":99}}]}}}}"#,
+            path
+        );
+        let mut script = Script::new(&[
+            r#"{"command":"initialize","arguments":{"adapterID":"vcpu16"}}"#,
+            &launch,
+            &breakpoints,
+            r#"{"command":"configurationDone"}"#,
+            r#"{"command":"stackTrace","arguments":{"threadId":1}}"#,
+            r#"{"command":"variables","arguments":{"variablesReference":1}}"#,
+            r#"{"command":"next","arguments":{"threadId":1}}"#,
+            r#"{"command":"stackTrace","arguments":{"threadId":1}}"#,
+            r#"{"command":"disassemble","arguments":{"memoryReference":"0x0002","instructionOffset":-1,"instructionCount":3}}"#,
+            r#"{"command":"readMemory","arguments":{"memoryReference":"0x0000","count":4}}"#,
+            r#"{"command":"stepOut","arguments":{"threadId":1}}"#,
+            r#"{"command":"disconnect"}"#,
+        ]);
+        let mut sys = System::new();
+        DapServer::new(&mut sys).serve(&mut script).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let messages = script.messages();
+        let kinds: Vec<String> = messages.iter()
+            .map(|message| {
+                let name = message.get("command").or_else(|| message.get("event")).unwrap();
+                name.as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(vec![
+            "initialize", "initialized", "launch", "setBreakpoints", "configurationDone", "stopped",
+            "stackTrace", "variables", "next", "stopped", "stackTrace", "disassemble", "readMemory",
+            "stepOut", "disconnect",
+        ], kinds);
+
+        // Line 4 is verified, line 99 has no code
+        assert_eq!(&Json::from(true), at(&messages[3], &["body", "breakpoints", "0", "verified"]));
+        assert_eq!(&Json::from(false), at(&messages[3], &["body", "breakpoints", "1", "verified"]));
+
+        // Stopped on the condition at line 4
+        assert_eq!(&Json::from("breakpoint"), at(&messages[5], &["body", "reason"]));
+        assert_eq!(&Json::from(4u64), at(&messages[6], &["body", "stackFrames", "0", "line"]));
+        assert_eq!(&Json::from("loop+1"), at(&messages[6], &["body", "stackFrames", "0", "name"]));
+        assert_eq!(&Json::from("0x0003"), at(&messages[7], &["body", "variables", "0", "value"]));
+
+        // Stepped to line 5
+        assert_eq!(&Json::from("step"), at(&messages[9], &["body", "reason"]));
+        assert_eq!(&Json::from(5u64), at(&messages[10], &["body", "stackFrames", "0", "line"]));
+
+        // Disassembly and memory
+        let instructions: Vec<&str> = at(&messages[11], &["body", "instructions"]).as_array().unwrap().iter()
+            .map(|instruction| at(instruction, &["instruction"]).as_str().unwrap())
+            .collect();
+        assert_eq!(vec!["ADD A, 0x0001", "SET [0x0006], A", "SET PC, 0x0001"], instructions);
+        assert_eq!(&Json::from("AYQCiA=="), at(&messages[12], &["body", "data"]));
+        assert_eq!(&Json::from(false), at(&messages[13], &["success"]));
+    }
+
+    #[test]
+    pub fn test_calls() {
+        let path = env::temp_dir().join(format!("vcpu16-dap-calls-{}.asm", process::id()));
+        fs::write(&path, "; calls\n\
+                          :start  JSR bump\n\
+                          \x20       SET B, A\n\
+                          \x20       JSR bump\n\
+                          :halt   SET PC, halt\n\
+                          :bump   ADD A, 1\n\
+                          \x20       SET PC, POP\n").unwrap();
+        let path = path.to_str().unwrap().replace('\\', "/");
+        let launch = format!(r#"{{"command":"launch","arguments":{{"program":"{}","stopOnEntry":true}}}}"#, path);
+        let mut script = Script::new(&[
+            r#"{"command":"initialize","arguments":{"adapterID":"vcpu16"}}"#,
+            &launch,
+            r#"{"command":"configurationDone"}"#,
+            r#"{"command":"stepIn","arguments":{"threadId":1}}"#,
+            r#"{"command":"stackTrace","arguments":{"threadId":1}}"#,
+            r#"{"command":"stepOut","arguments":{"threadId":1}}"#,
+            r#"{"command":"stackTrace","arguments":{"threadId":1}}"#,
+            r#"{"command":"next","arguments":{"threadId":1}}"#,
+            r#"{"command":"next","arguments":{"threadId":1}}"#,
+            r#"{"command":"variables","arguments":{"variablesReference":1}}"#,
+            r#"{"command":"stepOut","arguments":{"threadId":1}}"#,
+            r#"{"command":"disconnect"}"#,
+        ]);
+        let mut sys = System::new();
+        DapServer::new(&mut sys).serve(&mut script).unwrap();
+        fs::remove_file(&path).unwrap();
+        let messages = script.messages();
+        assert_eq!(18, messages.len());
+
+        // Stepping into the subroutine shows its caller
+        assert_eq!(&Json::from(2u64), at(&messages[7], &["body", "totalFrames"]));
+        assert_eq!(&Json::from("bump"), at(&messages[7], &["body", "stackFrames", "0", "name"]));
+        assert_eq!(&Json::from(6u64), at(&messages[7], &["body", "stackFrames", "0", "line"]));
+        assert_eq!(&Json::from("start"), at(&messages[7], &["body", "stackFrames", "1", "name"]));
+        assert_eq!(&Json::from(2u64), at(&messages[7], &["body", "stackFrames", "1", "line"]));
+
+        // Stepping out returns to the line after the call
+        assert_eq!(&Json::from(1u64), at(&messages[10], &["body", "totalFrames"]));
+        assert_eq!(&Json::from(3u64), at(&messages[10], &["body", "stackFrames", "0", "line"]));
+
+        // Next runs the second call through without stopping inside it
+        assert_eq!(&Json::from("step"), at(&messages[14], &["body", "reason"]));
+        assert_eq!(&Json::from("0x0002"), at(&messages[15], &["body", "variables", "0", "value"]));
+        assert_eq!(&Json::from(false), at(&messages[16], &["success"]));
+    }
+
+    #[test]
+    pub fn test_pause() {
+        let path = env::temp_dir().join(format!("vcpu16-dap-pause-{}.asm", process::id()));
+        fs::write(&path, ":loop   SET PC, loop\n").unwrap();
+        let path = path.to_str().unwrap().replace('\\', "/");
+        let launch = format!(r#"{{"command":"launch","arguments":{{"program":"{}"}}}}"#, path);
+        let mut script = Script::new(&[
+            r#"{"command":"initialize","arguments":{"adapterID":"vcpu16"}}"#,
+            &launch,
+            r#"{"command":"configurationDone"}"#,
+            r#"{"command":"threads"}"#,
+            r#"{"command":"pause","arguments":{"threadId":1}}"#,
+            r#"{"command":"disconnect"}"#,
+        ]);
+        let mut sys = System::new();
+        DapServer::new(&mut sys).serve(&mut script).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // Requests are answered while running and pause stops the loop
+        let messages = script.messages();
+        let kinds: Vec<&str> = messages.iter()
+            .map(|message| message.get("command").or_else(|| message.get("event")).unwrap().as_str().unwrap())
+            .collect();
+        assert_eq!(vec!["initialize", "initialized", "launch", "configurationDone", "threads", "pause", "stopped", "disconnect"], kinds);
+        assert_eq!(&Json::from("pause"), at(&messages[6], &["body", "reason"]));
+        assert_eq!(2 * BATCH, sys.clock().cycles());
+    }
+
+    #[test]
+    pub fn test_bounds() {
+        let mut script = Script::new(&[
+            r#"{"command":"initialize","arguments":{"adapterID":"vcpu16"}}"#,
+            r#"{"command":"disassemble","arguments":{"memoryReference":"0x0000","instructionOffset":-9223372036854775808,"instructionCount":1}}"#,
+            r#"{"command":"disassemble","arguments":{"memoryReference":"0x0000","instructionCount":1000000000000}}"#,
+            r#"{"command":"disassemble","arguments":{"memoryReference":"0x0002","instructionOffset":-65536,"instructionCount":1}}"#,
+            r#"{"command":"readMemory","arguments":{"memoryReference":"0x0000","count":9223372036854775807}}"#,
+            r#"{"command":"readMemory","arguments":{"memoryReference":"0x0000","offset":-1,"count":2}}"#,
+            r#"{"command":"readMemory","arguments":{"memoryReference":"0xFFFF","count":4}}"#,
+            r#"{"command":"disconnect"}"#,
+        ]);
+        let mut sys = System::new();
+        DapServer::new(&mut sys).serve(&mut script).unwrap();
+        let messages = script.messages();
+
+        // Offsets and counts beyond memory are refused rather than wrapped or looped over
+        let success: Vec<bool> = messages[2..8].iter().map(|message| at(message, &["success"]).as_bool().unwrap()).collect();
+        assert_eq!(vec![false, false, true, false, false, true], success);
+        assert_eq!(&Json::from("0x0000"), at(&messages[4], &["body", "instructions", "0", "address"]));
+
+        // Reads stop at the end of memory
+        assert_eq!(&Json::from("AAA="), at(&messages[7], &["body", "data"]));
+        assert_eq!(&Json::from(2u64), at(&messages[7], &["body", "unreadableBytes"]));
+    }
+
+    #[test]
+    pub fn test_base64() {
+        assert_eq!("", base64(b""));
+        assert_eq!("Zg==", base64(b"f"));
+        assert_eq!("Zm8=", base64(b"fo"));
+        assert_eq!("Zm9vYmFy", base64(b"foobar"));
+    }
+}
@@ -0,0 +1,423 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! GDB Remote Serial Protocol stub
+//!
+//! VCPU16 is Word addressed, so addresses sent over the protocol are Word addresses, memory
+//! lengths count Words and every Word or Register is sent as four hex digits, low byte first.
+//! Registers are numbered in the order A, B, C, X, Y, Z, I, J, PC, SP, PS, IA.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{TcpListener, ToSocketAddrs};
+use system2::{Breakpoint, Event, Register, Stop, System, SystemError, Trigger, Word};
+use super::Transport;
+
+/// Cycles run between checks for an interrupt request while continuing
+const BATCH: u64 = 10_000;
+
+/// GDB Remote Serial Protocol stub serving a System
+pub struct GdbServer<'sys> {
+    system: &'sys mut System,
+    /// System Breakpoint ids by Z packet type, address and length
+    points: BTreeMap<(u8, Word, Word), Vec<usize>>,
+    /// Reply describing the last stop
+    last: String,
+}
+
+impl<'sys> GdbServer<'sys> {
+    /// Create a stub for system
+    pub fn new(system: &'sys mut System) -> GdbServer<'sys> {
+        GdbServer {
+            system,
+            points: BTreeMap::new(),
+            last: String::from("S05"),
+        }
+    }
+    /// Accept a single connection on address and serve it
+    pub fn listen<A: ToSocketAddrs>(&mut self, address: A) -> io::Result<()> {
+        let listener = TcpListener::bind(address)?;
+        let (mut stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        self.serve(&mut stream)
+    }
+    /// Serve requests until the front-end detaches, kills the target or disconnects
+    pub fn serve(&mut self, transport: &mut dyn Transport) -> io::Result<()> {
+        while let Some(packet) = read_packet(transport)? {
+            match packet.as_str() {
+                "k" => return Ok(()),
+                "D" => return write_packet(transport, "OK"),
+                _ => {}
+            }
+            let reply = self.handle(&packet, transport)?;
+            write_packet(transport, &reply)?;
+        }
+        Ok(())
+    }
+    /// Handle a single packet, returning the reply
+    fn handle(&mut self, packet: &str, transport: &mut dyn Transport) -> io::Result<String> {
+        let (command, args) = match (packet.chars().next(), packet.get(1..)) {
+            (Some(command), Some(args)) => (command, args),
+            _ => return Ok(String::new()),
+        };
+        let reply = match command {
+            '?' => self.last.clone(),
+            'g' => Register::ALL.iter().map(|&register| word_hex(self.system.registers().get(register))).collect(),
+            'G' => match parse_words(args) {
+                Some(ref words) if words.len() == Register::ALL.len() => {
+                    for (&register, &value) in Register::ALL.iter().zip(words) {
+                        self.system.registers_mut().set(register, value);
+                    }
+                    String::from("OK")
+                }
+                _ => String::from("E01"),
+            },
+            'p' => match parse_hex(args).and_then(|index| Register::ALL.get(index as usize)) {
+                Some(&register) => word_hex(self.system.registers().get(register)),
+                None => String::from("E01"),
+            },
+            'P' => self.write_register(args).unwrap_or_else(|| String::from("E01")),
+            'm' => self.read_memory(args).unwrap_or_else(|| String::from("E01")),
+            'M' => self.write_memory(args).unwrap_or_else(|| String::from("E01")),
+            'c' | 's' => {
+                if let Some(address) = parse_hex(args) {
+                    self.system.registers_mut().pc = address as Word;
+                }
+                self.last = if command == 'c' { self.resume(transport)? } else { self.step() };
+                self.last.clone()
+            }
+            'Z' | 'z' => self.breakpoint(command == 'Z', args).unwrap_or_else(|| String::from("E01")),
+            'q' => self.query(args),
+            'H' | 'T' => String::from("OK"),
+            _ => String::new(),
+        };
+        Ok(reply)
+    }
+    /// Reply to a general query
+    fn query(&self, args: &str) -> String {
+        const FEATURES: &str = "Xfer:features:read:target.xml:";
+        if args.starts_with("Supported") {
+            String::from("PacketSize=1000;qXfer:features:read+;swbreak+;hwbreak+")
+        } else if let Some(range) = args.strip_prefix(FEATURES) {
+            let (offset, length) = match split_pair(range, ',') {
+                Some(pair) => pair,
+                None => return String::from("E01"),
+            };
+            let xml = target_xml();
+            let start = (offset as usize).min(xml.len());
+            let end = (start + length as usize).min(xml.len());
+            let prefix = if end == xml.len() { 'l' } else { 'm' };
+            format!("{}{}", prefix, &xml[start..end])
+        } else {
+            match args {
+                "Attached" => String::from("1"),
+                "C" => String::from("QC1"),
+                "fThreadInfo" => String::from("m1"),
+                "sThreadInfo" => String::from("l"),
+                _ => String::new(),
+            }
+        }
+    }
+    /// Write a single register from `n=value`
+    fn write_register(&mut self, args: &str) -> Option<String> {
+        let index = args.find('=')?;
+        let register = *Register::ALL.get(parse_hex(&args[..index])? as usize)?;
+        let value = *parse_words(&args[index + 1..])?.first()?;
+        self.system.registers_mut().set(register, value);
+        Some(String::from("OK"))
+    }
+    /// Read Words from `address,length`
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (address, length) = split_pair(args, ',')?;
+        if address.checked_add(length)? > 0x10000 {
+            return None;
+        }
+        let memory = self.system.memory();
+        Some((address..address + length).map(|address| word_hex(memory.get(address as Word))).collect())
+    }
+    /// Write Words from `address,length:data`
+    fn write_memory(&mut self, args: &str) -> Option<String> {
+        let colon = args.find(':')?;
+        let (address, length) = split_pair(&args[..colon], ',')?;
+        let words = parse_words(&args[colon + 1..])?;
+        if words.len() as u32 != length || address.checked_add(length)? > 0x10000 {
+            return None;
+        }
+        self.system.memory_mut().write(address as Word, &words).ok()?;
+        Some(String::from("OK"))
+    }
+    /// Insert or remove a breakpoint or watchpoint from `type,address,kind`
+    fn breakpoint(&mut self, insert: bool, args: &str) -> Option<String> {
+        let mut fields = args.split(',');
+        let kind = fields.next()?.parse::<u8>().ok()?;
+        let address = parse_hex(fields.next()?)? as Word;
+        let length = if kind < 2 { 1 } else { parse_hex(fields.next()?)?.max(1) as Word };
+        let triggers = match kind {
+            0 | 1 => vec![Trigger::Address(address)],
+            2 => vec![Trigger::Write(address, length)],
+            3 => vec![Trigger::Read(address, length)],
+            4 => vec![Trigger::Read(address, length), Trigger::Write(address, length)],
+            _ => return Some(String::new()),
+        };
+        let key = (kind, address, length);
+        if insert {
+            if !self.points.contains_key(&key) {
+                let ids = triggers.into_iter()
+                    .map(|trigger| self.system.add_breakpoint(Breakpoint::new(trigger)))
+                    .collect();
+                self.points.insert(key, ids);
+            }
+        } else if let Some(ids) = self.points.remove(&key) {
+            for id in ids {
+                self.system.remove_breakpoint(id);
+            }
+        }
+        Some(String::from("OK"))
+    }
+    /// Run until a breakpoint, an error or an interrupt request
+    fn resume(&mut self, transport: &mut dyn Transport) -> io::Result<String> {
+        loop {
+            match self.system.run(BATCH) {
+                Ok(Some(stop)) => return Ok(self.stopped(stop)),
+                Ok(None) => {
+                    if transport.interrupted()? {
+                        return Ok(String::from("S02"));
+                    }
+                }
+                Err(error) => return Ok(failed(error)),
+            }
+        }
+    }
+    /// Step a single instruction
+    fn step(&mut self) -> String {
+        match self.system.step_instruction() {
+            Ok(Some(stop)) => self.stopped(stop),
+            Ok(None) => String::from("S05"),
+            Err(error) => failed(error),
+        }
+    }
+    /// Stop reply for a breakpoint hit
+    fn stopped(&self, stop: Stop) -> String {
+        let kind = self.points.iter()
+            .find(|&(_, ids)| ids.contains(&stop.id))
+            .map(|(&(kind, _, _), _)| kind);
+        match (kind, stop.event) {
+            (Some(0), Event::Address(_)) => String::from("T05swbreak:;"),
+            (Some(1), Event::Address(_)) => String::from("T05hwbreak:;"),
+            (Some(2), Event::Write { address, .. }) => format!("T05watch:{:x};", address),
+            (Some(3), Event::Read(address)) => format!("T05rwatch:{:x};", address),
+            (Some(4), Event::Read(address)) | (Some(4), Event::Write { address, .. }) => {
+                format!("T05awatch:{:x};", address)
+            }
+            _ => String::from("S05"),
+        }
+    }
+}
+
+/// Stop reply for a System error, SIGILL for invalid instructions and SIGABRT otherwise
+fn failed(error: SystemError) -> String {
+    match error {
+        SystemError::InvalidInstruction => String::from("S04"),
+        _ => String::from("S06"),
+    }
+}
+
+/// Target description listing the Registers
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\"><feature name=\"org.vcpu16.core\">",
+    );
+    for (index, register) in Register::ALL.iter().enumerate() {
+        let kind = match *register {
+            Register::PC => "code_ptr",
+            Register::SP => "data_ptr",
+            _ => "uint16",
+        };
+        xml.push_str(&format!(
+            "<reg name=\"{}\" bitsize=\"16\" regnum=\"{}\" type=\"{}\"/>",
+            register.name().to_lowercase(), index, kind
+        ));
+    }
+    xml.push_str("</feature></target>");
+    xml
+}
+
+/// Word as four hex digits, low byte first
+fn word_hex(word: Word) -> String {
+    format!("{:02x}{:02x}", word & 0xFF, word >> 8)
+}
+
+/// Parse Words sent as four hex digits, low byte first
+fn parse_words(text: &str) -> Option<Vec<Word>> {
+    if !text.len().is_multiple_of(4) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len()).step_by(4)
+        .map(|index| {
+            let lo = u8::from_str_radix(&text[index..index + 2], 16).ok()?;
+            let hi = u8::from_str_radix(&text[index + 2..index + 4], 16).ok()?;
+            Some(u16::from_le_bytes([lo, hi]))
+        })
+        .collect()
+}
+
+/// Parse a hex number
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+/// Parse a pair of hex numbers separated by separator
+fn split_pair(text: &str, separator: char) -> Option<(u32, u32)> {
+    let index = text.find(separator)?;
+    Some((parse_hex(&text[..index])?, parse_hex(&text[index + 1..])?))
+}
+
+/// Modulo 256 sum of packet data
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// Read the next packet, acknowledging it and skipping acknowledgements and stray bytes
+fn read_packet(transport: &mut dyn Transport) -> io::Result<Option<String>> {
+    let mut byte = [0u8; 1];
+    loop {
+        loop {
+            if transport.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+        let mut data = Vec::new();
+        loop {
+            if transport.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'#' {
+                break;
+            }
+            data.push(byte[0]);
+        }
+        let mut sum = [0u8; 2];
+        transport.read_exact(&mut sum)?;
+        let expected = String::from_utf8_lossy(&sum).into_owned();
+        if u8::from_str_radix(&expected, 16).ok() == Some(checksum(&data)) {
+            transport.write_all(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+        transport.write_all(b"-")?;
+    }
+}
+
+/// Write a packet with its checksum
+fn write_packet(transport: &mut dyn Transport, data: &str) -> io::Result<()> {
+    write!(transport, "${}#{:02x}", data, checksum(data.as_bytes()))?;
+    transport.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use system2::System;
+    use super::{checksum, GdbServer};
+
+    /// Scripted RSP client
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        /// Send a packet and return the reply
+        fn request(&mut self, data: &str) -> String {
+            write!(self.stream, "${}#{:02x}", data, checksum(data.as_bytes())).unwrap();
+            let mut byte = [0u8; 1];
+            self.stream.read_exact(&mut byte).unwrap();
+            assert_eq!(b'+', byte[0], "packet {} was not acknowledged", data);
+            self.stream.read_exact(&mut byte).unwrap();
+            assert_eq!(b'$', byte[0]);
+            let mut reply = Vec::new();
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+                if byte[0] == b'#' {
+                    break;
+                }
+                reply.push(byte[0]);
+            }
+            let mut sum = [0u8; 2];
+            self.stream.read_exact(&mut sum).unwrap();
+            assert_eq!(format!("{:02x}", checksum(&reply)).as_bytes(), &sum);
+            self.stream.write_all(b"+").unwrap();
+            String::from_utf8(reply).unwrap()
+        }
+    }
+
+    #[test]
+    pub fn test_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut sys = System::new();
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.set_nodelay(true).unwrap();
+            GdbServer::new(&mut sys).serve(&mut stream).unwrap();
+            sys.memory().get(0x1000)
+        });
+        let mut client = Client { stream: TcpStream::connect(address).unwrap() };
+        client.stream.set_nodelay(true).unwrap();
+
+        // Handshake and target description
+        assert!(client.request("qSupported:swbreak+;hwbreak+").contains("qXfer:features:read+"));
+        let xml = client.request("qXfer:features:read:target.xml:0,fff");
+        assert!(xml.starts_with("l<?xml"));
+        assert!(xml.contains("<reg name=\"pc\" bitsize=\"16\" regnum=\"8\" type=\"code_ptr\"/>"));
+        assert_eq!("S05", client.request("?"));
+        assert_eq!("0000".repeat(12), client.request("g"));
+
+        // Load a counting loop: SET A, 0; ADD A, 1; SET [0x1000], A; SET PC, 1
+        assert_eq!("OK", client.request("M0,5:01840288c1030010818b"));
+        assert_eq!("01840288", client.request("m0,2"));
+        assert_eq!("E01", client.request("mffff,2"));
+        assert_eq!("E01", client.request("mffffffff,1"));
+        assert_eq!("E01", client.request("Mffffffff,1:0000"));
+        assert_eq!("", client.request(""));
+        assert_eq!("", client.request("\u{e9}0"));
+
+        // Software breakpoint
+        assert_eq!("OK", client.request("Z0,2,1"));
+        assert_eq!("T05swbreak:;", client.request("c"));
+        assert_eq!("0200", client.request("p8"));
+        assert_eq!("OK", client.request("z0,2,1"));
+
+        // Write watchpoint stops part way through SET [0x1000], A
+        assert_eq!("OK", client.request("Z2,1000,1"));
+        assert_eq!("T05watch:1000;", client.request("c"));
+        assert_eq!("0100", client.request("p0"));
+        assert_eq!("S05", client.request("s"));
+        assert_eq!("0400", client.request("p8"));
+
+        // Register writes and unsupported packets
+        assert_eq!("OK", client.request("P0=3412"));
+        assert_eq!("3412", client.request("p0"));
+        assert_eq!("", client.request("vMustReplyEmpty"));
+        assert_eq!("OK", client.request("D"));
+        assert_eq!(0x0001, server.join().unwrap());
+    }
+}
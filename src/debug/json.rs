@@ -0,0 +1,332 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Minimal JSON values for debugger protocols
+
+use std::fmt;
+
+/// JSON value, objects keep their key order
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Build an object from key value pairs
+    pub fn object(pairs: Vec<(&str, Json)>) -> Json {
+        Json::Object(pairs.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+    /// Parse a complete JSON document
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { bytes: text.as_bytes(), offset: 0 };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.offset != parser.bytes.len() {
+            return Err(format!("trailing characters at {}", parser.offset));
+        }
+        Ok(value)
+    }
+    /// Member of an object
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref pairs) => pairs.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+    /// String value
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Json::String(ref value) => Some(value),
+            _ => None,
+        }
+    }
+    /// Integer value
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Json::Number(value) if value.fract() == 0.0 => Some(value as i64),
+            _ => None,
+        }
+    }
+    /// Boolean value
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Json::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+    /// Array elements
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match *self {
+            Json::Array(ref values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Json {
+        Json::Bool(value)
+    }
+}
+
+impl From<i64> for Json {
+    fn from(value: i64) -> Json {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<u64> for Json {
+    fn from(value: u64) -> Json {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(value: usize) -> Json {
+        Json::Number(value as f64)
+    }
+}
+
+impl From<u16> for Json {
+    fn from(value: u16) -> Json {
+        Json::Number(value as f64)
+    }
+}
+
+impl<'a> From<&'a str> for Json {
+    fn from(value: &'a str) -> Json {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Json {
+        Json::String(value)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(values: Vec<Json>) -> Json {
+        Json::Array(values)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::String(ref value) => write_string(f, value),
+            Json::Array(ref values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(ref pairs) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in pairs.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// Write a quoted and escaped string
+fn write_string(f: &mut fmt::Formatter, value: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for ch in value.chars() {
+        match ch {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            ch if (ch as u32) < 0x20 => write!(f, "\\u{:04x}", ch as u32)?,
+            ch => write!(f, "{}", ch)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Recursive descent parser
+struct Parser<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn whitespace(&mut self) {
+        while self.offset < self.bytes.len() && (self.bytes[self.offset] as char).is_ascii_whitespace() {
+            self.offset += 1;
+        }
+    }
+    fn error<T>(&self) -> Result<T, String> {
+        Err(format!("unexpected input at {}", self.offset))
+    }
+    fn literal(&mut self, text: &str, value: Json) -> Result<Json, String> {
+        if self.bytes[self.offset..].starts_with(text.as_bytes()) {
+            self.offset += text.len();
+            Ok(value)
+        } else {
+            self.error()
+        }
+    }
+    fn value(&mut self) -> Result<Json, String> {
+        self.whitespace();
+        match self.bytes.get(self.offset) {
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'[') => {
+                self.offset += 1;
+                let mut values = Vec::new();
+                self.whitespace();
+                if self.bytes.get(self.offset) == Some(&b']') {
+                    self.offset += 1;
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    self.whitespace();
+                    match self.bytes.get(self.offset) {
+                        Some(b',') => self.offset += 1,
+                        Some(b']') => {
+                            self.offset += 1;
+                            return Ok(Json::Array(values));
+                        }
+                        _ => return self.error(),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.offset += 1;
+                let mut pairs = Vec::new();
+                self.whitespace();
+                if self.bytes.get(self.offset) == Some(&b'}') {
+                    self.offset += 1;
+                    return Ok(Json::Object(pairs));
+                }
+                loop {
+                    self.whitespace();
+                    if self.bytes.get(self.offset) != Some(&b'"') {
+                        return self.error();
+                    }
+                    let key = self.string()?;
+                    self.whitespace();
+                    if self.bytes.get(self.offset) != Some(&b':') {
+                        return self.error();
+                    }
+                    self.offset += 1;
+                    pairs.push((key, self.value()?));
+                    self.whitespace();
+                    match self.bytes.get(self.offset) {
+                        Some(b',') => self.offset += 1,
+                        Some(b'}') => {
+                            self.offset += 1;
+                            return Ok(Json::Object(pairs));
+                        }
+                        _ => return self.error(),
+                    }
+                }
+            }
+            Some(&byte) if byte == b'-' || byte.is_ascii_digit() => {
+                let start = self.offset;
+                while self.offset < self.bytes.len() && b"+-.eE0123456789".contains(&self.bytes[self.offset]) {
+                    self.offset += 1;
+                }
+                let text = String::from_utf8_lossy(&self.bytes[start..self.offset]).into_owned();
+                text.parse().map(Json::Number).map_err(|_| format!("invalid number at {}", start))
+            }
+            _ => self.error(),
+        }
+    }
+    fn string(&mut self) -> Result<String, String> {
+        self.offset += 1;
+        let mut value = Vec::new();
+        loop {
+            match self.bytes.get(self.offset) {
+                None => return self.error(),
+                Some(b'"') => {
+                    self.offset += 1;
+                    return String::from_utf8(value).map_err(|_| String::from("invalid utf-8"));
+                }
+                Some(b'\\') => {
+                    let escape = match self.bytes.get(self.offset + 1) {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let hex = self.bytes.get(self.offset + 2..self.offset + 6).unwrap_or(&[]);
+                            let code = u32::from_str_radix(&String::from_utf8_lossy(hex), 16).or_else(|_| self.error())?;
+                            self.offset += 4;
+                            ::std::char::from_u32(code).unwrap_or('\u{FFFD}')
+                        }
+                        _ => return self.error(),
+                    };
+                    let mut buffer = [0u8; 4];
+                    value.extend_from_slice(escape.encode_utf8(&mut buffer).as_bytes());
+                    self.offset += 2;
+                }
+                Some(&byte) => {
+                    value.push(byte);
+                    self.offset += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Json;
+
+    #[test]
+    pub fn test_round_trip() {
+        let text = r#"{"seq":1,"type":"request","arguments":{"lines":[1,-2.5],"path":"a\"b\n\u0041","ok":true,"none":null}}"#;
+        let json = Json::parse(text).unwrap();
+        assert_eq!(Some(1), json.get("seq").and_then(Json::as_i64));
+        let arguments = json.get("arguments").unwrap();
+        assert_eq!(Some("a\"b\nA"), arguments.get("path").and_then(Json::as_str));
+        assert_eq!(Some(true), arguments.get("ok").and_then(Json::as_bool));
+        assert_eq!(2, arguments.get("lines").and_then(Json::as_array).unwrap().len());
+        assert_eq!(json, Json::parse(&json.to_string()).unwrap());
+        assert!(Json::parse("{\"a\":}").is_err());
+        assert!(Json::parse("[1] 2").is_err());
+    }
+}
@@ -0,0 +1,516 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Command interpreter behind the `vcpu16-dbg` binary
+
+use std::fs;
+use assembler::{assemble, Symbols};
+use system2::{Breakpoint, Condition, Decoding, Format, Instruction, Register};
+use system2::{Profile, Snapshot, Stop, System, SystemError, Trigger, Word};
+
+/// Cycles of History recorded for stepping backwards
+const HISTORY: usize = 100_000;
+
+/// Cycles run by continue when no limit is given
+const RUN_LIMIT: u64 = 10_000_000;
+
+/// Command summary printed by help
+const HELP: &str = "\
+step [n]              (s)   step n instructions
+next                  (n)   step over JSR
+finish                (fin) run until the current subroutine returns
+backtrace             (bt)  show the calls leading to PC
+continue [cycles]     (c)   run until a breakpoint or the cycle limit
+back [n]                    step n instructions backwards
+rcontinue             (rc)  run backwards to the previous breakpoint or watchpoint
+break <loc> [if <cond>] [ignore <n>]  (b)  break at address, label+offset or :line
+watch <loc|reg> [len] [if <cond>]     (w)  stop on writes or register changes
+rwatch <loc> [len] [if <cond>]        (rw) stop on reads
+delete <id>           (d)   remove a breakpoint
+info                  (i)   list breakpoints
+regs                  (r)   show registers
+set <reg|[loc]> <value>     change a register or Word
+mem <loc> [len] [word|byte|packed|cell] [hex|unsigned|signed]  (x)  hexdump memory
+disasm [loc] [count]  (dis) disassemble, around PC by default
+sym <name|loc>              look up a label or address
+profile [start|stop|report|folded <file>]  count cycles by function, address and operation
+history                     list commands, !n repeats command n, empty repeats the last
+quit                  (q)   exit";
+
+/// Interactive debugger over a System
+pub struct Repl {
+    system: System,
+    symbols: Symbols,
+    history: Vec<String>,
+    finished: bool,
+}
+
+impl Repl {
+    /// Create a debugger for system described by symbols
+    pub fn new(mut system: System, symbols: Symbols) -> Repl {
+        system.record_history(HISTORY);
+        Repl {
+            system,
+            symbols,
+            history: Vec::new(),
+            finished: false,
+        }
+    }
+    /// Load an assembly source, a core file or a little endian image with optional symbol file
+    pub fn open(path: &str, symbols: Option<&str>) -> Result<Repl, String> {
+        let mut system = System::new();
+        let (words, mut table) = if path.ends_with(".asm") {
+            let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+            let program = assemble(&text).map_err(|error| format!("{}: {}", path, error))?;
+            (program.words, program.symbols)
+        } else {
+            let bytes = fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+            if bytes.starts_with(b"VC16SNAP") {
+                let snapshot = Snapshot::load(&mut &bytes[..]).map_err(|error| format!("{}: {}", path, error))?;
+                system.restore_core(&snapshot).map_err(|error| format!("{}: {}", path, error))?;
+                (Vec::new(), Symbols::new())
+            } else {
+                let words = bytes.chunks(2).map(|pair| u16::from_le_bytes([pair[0], *pair.get(1).unwrap_or(&0)])).collect();
+                (words, Symbols::new())
+            }
+        };
+        if let Some(symbols) = symbols {
+            let text = fs::read_to_string(symbols).map_err(|error| format!("{}: {}", symbols, error))?;
+            table = text.parse().map_err(|error| format!("{}: {}", symbols, error))?;
+        }
+        system.memory_mut().write(0, &words).map_err(|_| format!("{} does not fit in memory", path))?;
+        Ok(Repl::new(system, table))
+    }
+    /// Debugged System
+    pub fn system(&self) -> &System {
+        &self.system
+    }
+    /// Has quit been entered
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+    /// Execute a command line, returning its output
+    pub fn execute(&mut self, line: &str) -> String {
+        let line = line.trim();
+        let line = if line.is_empty() {
+            match self.history.last() {
+                Some(last) => last.clone(),
+                None => return String::new(),
+            }
+        } else if let Some(index) = line.strip_prefix('!') {
+            let entry = if index == "!" {
+                self.history.last()
+            } else {
+                index.parse::<usize>().ok().and_then(|index| self.history.get(index.wrapping_sub(1)))
+            };
+            match entry {
+                Some(entry) => entry.clone(),
+                None => return format!("error: no command {} in history", index),
+            }
+        } else {
+            line.to_string()
+        };
+        if self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+        }
+        match self.command(&line) {
+            Ok(output) => output,
+            Err(message) => format!("error: {}", message),
+        }
+    }
+    /// Dispatch a single command
+    fn command(&mut self, line: &str) -> Result<String, String> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let args: Vec<&str> = words.collect();
+        match command {
+            "s" | "step" => {
+                let count = self.count(args.first())?;
+                for _ in 1..count {
+                    if let Some(stop) = self.system.step_instruction().map_err(|error| self.failed(error))? {
+                        return Ok(self.stopped(Some(stop)));
+                    }
+                }
+                let stop = self.system.step_instruction().map_err(|error| self.failed(error))?;
+                Ok(self.stopped(stop))
+            }
+            "n" | "next" => self.next(),
+            "fin" | "finish" => self.finish(),
+            "bt" | "backtrace" => Ok(self.system.backtrace(Some(&self.symbols))
+                .iter()
+                .enumerate()
+                .map(|(index, frame)| format!("#{:<2} {}", index, frame))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            "c" | "continue" => {
+                let limit = match args.first() {
+                    Some(text) => text.parse().map_err(|_| format!("invalid cycle count '{}'", text))?,
+                    None => RUN_LIMIT,
+                };
+                let start = self.system.clock().cycles();
+                match self.system.run(limit).map_err(|error| self.failed(error))? {
+                    Some(stop) => Ok(self.stopped(Some(stop))),
+                    None => Ok(format!("ran {} cycles\n{}", self.system.clock().cycles() - start, self.location())),
+                }
+            }
+            "back" => {
+                for _ in 0..self.count(args.first())? {
+                    self.system.step_back_instruction().map_err(|error| error.to_string())?;
+                }
+                Ok(self.location())
+            }
+            "rc" | "rcontinue" => match self.system.run_back() {
+                Ok(stop) => Ok(self.stopped(Some(stop))),
+                Err(SystemError::HistoryExhausted) => Ok(format!("reached start of history\n{}", self.location())),
+                Err(error) => Err(error.to_string()),
+            },
+            "b" | "break" => {
+                let (location, condition, ignore) = self.clauses(&args)?;
+                let address = self.location_of(&location)?;
+                self.add(Trigger::Address(address), condition, ignore)
+            }
+            "w" | "watch" | "rw" | "rwatch" => {
+                let (location, condition, ignore) = self.clauses(&args)?;
+                let mut parts = location.split_whitespace();
+                let target = parts.next().ok_or("missing location")?;
+                let length = match parts.next() {
+                    Some(text) => self.address(text)?,
+                    None => 1,
+                };
+                let trigger = match Register::from_name(target) {
+                    Some(register) if command.starts_with('w') => Trigger::Register(register),
+                    _ if command.starts_with('w') => Trigger::Write(self.location_of(target)?, length),
+                    _ => Trigger::Read(self.location_of(target)?, length),
+                };
+                self.add(trigger, condition, ignore)
+            }
+            "d" | "delete" => {
+                let id = args.first().and_then(|text| text.parse().ok()).ok_or("delete requires a breakpoint id")?;
+                match self.system.remove_breakpoint(id) {
+                    Some(_) => Ok(format!("deleted {}", id)),
+                    None => Err(format!("no breakpoint {}", id)),
+                }
+            }
+            "i" | "info" => Ok(self.system.breakpoints().iter()
+                .map(|(id, breakpoint)| format!("{:>3} {}", id, breakpoint))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            "r" | "regs" => {
+                let registers = self.system.registers();
+                let mut output = String::new();
+                for (index, &register) in Register::ALL.iter().enumerate() {
+                    let separator = if index % 4 == 3 { "\n" } else { "  " };
+                    output.push_str(&format!("{:>2}: {:04X}{}", register.name(), registers.get(register), separator));
+                }
+                output.push_str(&format!("cycles: {}  {:?}", self.system.clock().cycles(), self.system.state()));
+                Ok(output)
+            }
+            "set" => {
+                if args.len() != 2 {
+                    return Err(String::from("set requires a target and a value"));
+                }
+                let value = self.address(args[1])?;
+                if let Some(register) = Register::from_name(args[0]) {
+                    self.system.registers_mut().set(register, value);
+                } else if args[0].starts_with('[') && args[0].ends_with(']') {
+                    let address = self.address(&args[0][1..args[0].len() - 1])?;
+                    self.system.memory_mut().set(address, value);
+                } else {
+                    return Err(format!("cannot set '{}'", args[0]));
+                }
+                Ok(String::new())
+            }
+            "x" | "mem" => {
+                let address = self.location_of(args.first().ok_or("mem requires an address")?)?;
+                let mut length = 64;
+                let mut decoding = Decoding::Word;
+                let mut format = Format::Hex;
+                for arg in args.iter().skip(1) {
+                    match *arg {
+                        "word" => decoding = Decoding::Word,
+                        "byte" => decoding = Decoding::Byte,
+                        "packed" => decoding = Decoding::Packed,
+                        "cell" => decoding = Decoding::Cell,
+                        "hex" => format = Format::Hex,
+                        "unsigned" => format = Format::Unsigned,
+                        "signed" => format = Format::Signed,
+                        text => length = self.address(text)? as usize,
+                    }
+                }
+                let columns = (if decoding == Decoding::Byte { 8 } else { 16 }).min(length);
+                let view = self.system.memory().view()
+                    .range(address, length)
+                    .columns(columns)
+                    .decoding(decoding)
+                    .format(format)
+                    .elide_zeros(false);
+                Ok(view.to_string().trim_end().to_string())
+            }
+            "dis" | "disasm" => {
+                let pc = self.system.registers().pc;
+                let start = match args.first() {
+                    Some(text) => self.location_of(text)?,
+                    None => pc,
+                };
+                let count = match args.get(1) {
+                    Some(text) => self.address(text)? as usize,
+                    None => 8,
+                };
+                Ok(self.disassemble(start, count))
+            }
+            "sym" => {
+                let text = args.first().ok_or("sym requires a name or address")?;
+                if let Some(address) = self.symbols.label(text) {
+                    return Ok(format!("{} = 0x{:04X}", text, address));
+                }
+                let address = self.location_of(text)?;
+                Ok(match self.symbols.line(address) {
+                    Some(line) => format!("0x{:04X} = {} (line {})", address, self.describe(address), line),
+                    None => format!("0x{:04X} = {}", address, self.describe(address)),
+                })
+            }
+            "profile" => {
+                let symbols = &self.symbols;
+                let report = |profile: &Profile| profile.report(Some(symbols), 10);
+                match args.first().cloned().unwrap_or("report") {
+                    "start" => {
+                        self.system.start_profile();
+                        Ok(String::from("profiling"))
+                    }
+                    "stop" => self.system.stop_profile().map(|profile| report(&profile)).ok_or_else(|| String::from("not profiling")),
+                    "report" => self.system.profile().map(report).ok_or_else(|| String::from("not profiling")),
+                    "folded" => {
+                        let path = args.get(1).ok_or("profile folded requires a file")?;
+                        let profile = self.system.profile().ok_or("not profiling")?;
+                        fs::write(path, profile.folded(Some(symbols))).map_err(|error| format!("{}: {}", path, error))?;
+                        Ok(format!("wrote {}", path))
+                    }
+                    other => Err(format!("unknown profile command '{}'", other)),
+                }
+            }
+            "history" => Ok(self.history.iter()
+                .enumerate()
+                .map(|(index, line)| format!("{:>4}  {}", index + 1, line))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            "h" | "help" => Ok(HELP.to_string()),
+            "q" | "quit" => {
+                self.finished = true;
+                Ok(String::new())
+            }
+            _ => Err(format!("unknown command '{}', try help", command)),
+        }
+    }
+    /// Step over a JSR by running until the call returns, or the cycle limit
+    fn next(&mut self) -> Result<String, String> {
+        let result = self.system.step_over(RUN_LIMIT);
+        self.returned(result)
+    }
+    /// Run until the current subroutine or interrupt handler returns, or the cycle limit
+    fn finish(&mut self) -> Result<String, String> {
+        if self.system.call_stack().depth() == 0 {
+            return Err(String::from("not in a subroutine"));
+        }
+        let result = self.system.step_out(RUN_LIMIT);
+        self.returned(result)
+    }
+    /// Describe the result of running until a call returns
+    fn returned(&self, result: Result<Option<Stop>, SystemError>) -> Result<String, String> {
+        match result {
+            Ok(stop) => Ok(self.stopped(stop)),
+            Err(SystemError::StepLimit) => Err(format!("call still running after {} cycles\n{}", RUN_LIMIT, self.location())),
+            Err(error) => Err(self.failed(error)),
+        }
+    }
+    /// Add a Breakpoint with optional condition and ignore count
+    fn add(&mut self, trigger: Trigger, condition: Option<Condition>, ignore: u64) -> Result<String, String> {
+        let mut breakpoint = Breakpoint::new(trigger).ignore(ignore);
+        if let Some(condition) = condition {
+            breakpoint = breakpoint.when(condition);
+        }
+        let text = breakpoint.to_string();
+        let id = self.system.add_breakpoint(breakpoint);
+        Ok(format!("{} {}", id, text))
+    }
+    /// Split `location [if condition] [ignore n]`
+    fn clauses(&self, args: &[&str]) -> Result<(String, Option<Condition>, u64), String> {
+        let text = args.join(" ");
+        let (text, ignore) = match text.find(" ignore ") {
+            Some(index) => {
+                let count = text[index + 8..].trim();
+                (text[..index].to_string(), count.parse().map_err(|_| format!("invalid ignore count '{}'", count))?)
+            }
+            None => (text.clone(), 0),
+        };
+        match text.find(" if ") {
+            Some(index) => {
+                let condition = text[index + 4..].trim();
+                let condition = condition.parse().map_err(|_| format!("invalid condition '{}'", condition))?;
+                Ok((text[..index].trim().to_string(), Some(condition), ignore))
+            }
+            None if text.is_empty() => Err(String::from("missing location")),
+            None => Ok((text.trim().to_string(), None, ignore)),
+        }
+    }
+    /// Resolve `:line`, `label`, `label+offset` or a number
+    fn location_of(&self, text: &str) -> Result<Word, String> {
+        match text.strip_prefix(':') {
+            Some(line) => {
+                let line = line.parse().map_err(|_| format!("invalid line '{}'", line))?;
+                self.symbols.address(line).ok_or_else(|| format!("no code at or after line {}", line))
+            }
+            None => self.address(text),
+        }
+    }
+    /// Resolve `label`, `label+offset` or a number
+    fn address(&self, text: &str) -> Result<Word, String> {
+        let (base, offset) = match text.find('+') {
+            Some(index) => (&text[..index], Some(&text[index + 1..])),
+            None => (text, None),
+        };
+        let value = |text: &str| -> Result<Word, String> {
+            if let Some(address) = self.symbols.label(text) {
+                return Ok(address);
+            }
+            let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+                Some(hex) => Word::from_str_radix(hex, 16),
+                None => text.parse(),
+            };
+            parsed.map_err(|_| format!("unknown address '{}'", text))
+        };
+        let base = value(base)?;
+        match offset {
+            Some(offset) => Ok(base.wrapping_add(value(offset)?)),
+            None => Ok(base),
+        }
+    }
+    /// Parse an optional repeat count
+    fn count(&self, text: Option<&&str>) -> Result<usize, String> {
+        match text {
+            Some(text) => text.parse().map_err(|_| format!("invalid count '{}'", text)),
+            None => Ok(1),
+        }
+    }
+    /// Describe a stop and the new location
+    fn stopped(&self, stop: Option<Stop>) -> String {
+        match stop {
+            Some(stop) => format!("{}\n{}", stop, self.location()),
+            None => self.location(),
+        }
+    }
+    /// Describe an error stop, keeping the location in the message
+    fn failed(&self, error: SystemError) -> String {
+        format!("stopped by {}\n{}", error, self.location())
+    }
+    /// Listing of the instruction at PC
+    pub fn location(&self) -> String {
+        self.disassemble(self.system.registers().pc, 1)
+    }
+    /// Nearest label and offset, or the address
+    fn describe(&self, address: Word) -> String {
+        match self.symbols.lookup(address) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+{}", name, offset),
+            None => format!("0x{:04X}", address),
+        }
+    }
+    /// Listing of count instructions from address, marking PC
+    fn disassemble(&self, mut address: Word, count: usize) -> String {
+        let memory = self.system.memory();
+        let pc = self.system.registers().pc;
+        let mut lines = Vec::new();
+        for _ in 0..count {
+            let instruction = Instruction::decode(memory, address);
+            let words: Vec<String> = (0..instruction.size)
+                .map(|index| format!("{:04X}", memory.get(address.wrapping_add(index))))
+                .collect();
+            lines.push(format!(
+                "{} 0x{:04X} <{}>  {:<15}{}",
+                if address == pc { "=>" } else { "  " },
+                address,
+                self.describe(address),
+                words.join(" "),
+                instruction
+            ));
+            address = address.wrapping_add(instruction.size);
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assembler::assemble;
+    use system2::System;
+    use super::Repl;
+
+    fn repl() -> Repl {
+        let program = assemble(
+            ":start  SET A, 0\n\
+             :loop   JSR bump\n\
+             \x20       SET [count], A\n\
+             \x20       SET PC, loop\n\
+             :bump   ADD A, 1\n\
+             \x20       SET PC, POP\n\
+             :count  DAT 0\n",
+        ).unwrap();
+        let mut system = System::new();
+        system.memory_mut().write(0, &program.words).unwrap();
+        Repl::new(system, program.symbols)
+    }
+
+    #[test]
+    pub fn test_commands() {
+        let mut repl = repl();
+
+        // Breakpoints by line and label with conditions
+        assert_eq!("1 address 0x0003 if A == 0x0002 hits 0", repl.execute("break :3 if A == 2"));
+        assert_eq!("Breakpoint 1 at 0x0003\n=> 0x0003 <loop+2>  03C1 0009      SET [0x0009], A", repl.execute("c"));
+        assert_eq!("=> 0x0005 <loop+4>  7F81 0001      SET PC, 0x0001", repl.execute("s"));
+
+        // Step over and into the subroutine, then finish it
+        assert_eq!("=> 0x0001 <loop>  7C20 0007      JSR 0x0007", repl.execute("s"));
+        repl.execute("n");
+        assert_eq!(3, repl.system().registers().a);
+        assert_eq!(0x0003, repl.system().registers().pc);
+        repl.execute("set PC loop");
+        repl.execute("s");
+        assert_eq!(0x0007, repl.system().registers().pc);
+        assert_eq!("#0  0x0007 <bump>\n#1  0x0001 <loop>", repl.execute("bt"));
+        assert!(repl.execute("fin").starts_with("=> 0x0003"));
+        assert_eq!("error: not in a subroutine", repl.execute("fin"));
+
+        // Watchpoints, memory, symbols and history
+        assert_eq!("2 write 0x0009+1 hits 0", repl.execute("w count"));
+        assert!(repl.execute("c").starts_with("Watchpoint 2 write 0x0009: 0002 -> 0004"));
+        assert_eq!("Memory    0\n0x0009 0004 .", repl.execute("x count 1"));
+        assert_eq!("bump = 0x0007", repl.execute("sym bump"));
+        assert_eq!("0x0008 = bump+1 (line 6)", repl.execute("sym 8"));
+        assert_eq!("deleted 2", repl.execute("d 2"));
+        assert!(repl.execute("back").starts_with("=> 0x0003"));
+        assert_eq!(2, repl.system().memory().get(0x0009));
+        assert!(repl.execute("history").starts_with("   1  break :3 if A == 2"));
+        assert!(repl.execute("bogus").starts_with("error: unknown command"));
+
+        // Profile a few loops
+        assert_eq!("error: not profiling", repl.execute("profile"));
+        repl.execute("profile start");
+        repl.execute("c 140");
+        assert!(repl.execute("profile stop").contains("  25.7%       13  bump\n"));
+        repl.execute("q");
+        assert!(repl.is_finished());
+    }
+}
@@ -0,0 +1,149 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use std::io::{self, Read, Stdout, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+/// Byte stream to a debugger front-end
+pub trait Transport: Read + Write {
+    /// Check without blocking whether the front-end sent anything or closed the stream
+    fn pending(&mut self) -> io::Result<bool>;
+    /// Check without blocking whether the front-end sent an interrupt request (0x03)
+    fn interrupted(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+impl Transport for TcpStream {
+    fn pending(&mut self) -> io::Result<bool> {
+        let mut byte = [0u8; 1];
+        self.set_nonblocking(true)?;
+        let peeked = self.peek(&mut byte);
+        self.set_nonblocking(false)?;
+        match peeked {
+            Ok(_) => Ok(true),
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+    fn interrupted(&mut self) -> io::Result<bool> {
+        let mut byte = [0u8; 1];
+        self.set_nonblocking(true)?;
+        let peeked = self.peek(&mut byte);
+        self.set_nonblocking(false)?;
+        match peeked {
+            Ok(1) if byte[0] == 0x03 => {
+                self.read_exact(&mut byte)?;
+                Ok(true)
+            }
+            Ok(_) => Ok(false),
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// Standard input and output of the process as a Transport
+///
+/// Standard input is read on a background thread so requests and interrupts are seen while
+/// the System is running.
+pub struct Stdio {
+    receiver: Receiver<u8>,
+    /// Byte received by a check but not yet read
+    next: Option<u8>,
+    stdout: Stdout,
+}
+
+impl Stdio {
+    /// Create a Transport over standard input and output
+    pub fn new() -> Stdio {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for byte in io::stdin().lock().bytes() {
+                match byte {
+                    Ok(byte) if sender.send(byte).is_ok() => {}
+                    _ => break,
+                }
+            }
+        });
+        Stdio {
+            receiver,
+            next: None,
+            stdout: io::stdout(),
+        }
+    }
+}
+
+impl Default for Stdio {
+    fn default() -> Stdio {
+        Stdio::new()
+    }
+}
+
+impl Read for Stdio {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        buffer[0] = match self.next.take().or_else(|| self.receiver.recv().ok()) {
+            Some(byte) => byte,
+            None => return Ok(0),
+        };
+        let mut length = 1;
+        while length < buffer.len() {
+            match self.receiver.try_recv() {
+                Ok(byte) => buffer[length] = byte,
+                Err(_) => break,
+            }
+            length += 1;
+        }
+        Ok(length)
+    }
+}
+
+impl Write for Stdio {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.stdout.write(buffer)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdout.flush()
+    }
+}
+
+impl Transport for Stdio {
+    fn pending(&mut self) -> io::Result<bool> {
+        if self.next.is_some() {
+            return Ok(true);
+        }
+        match self.receiver.try_recv() {
+            Ok(byte) => {
+                self.next = Some(byte);
+                Ok(true)
+            }
+            Err(TryRecvError::Empty) => Ok(false),
+            Err(TryRecvError::Disconnected) => Ok(true),
+        }
+    }
+    fn interrupted(&mut self) -> io::Result<bool> {
+        if self.pending()? && self.next == Some(0x03) {
+            self.next = None;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
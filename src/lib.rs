@@ -17,4 +17,12 @@
 #[cfg(test)]
 extern crate rand;
 
-pub mod system2;
\ No newline at end of file
+pub mod assembler;
+pub mod debug;
+pub mod system2;
+
+/// Legacy VCPU16 System, of which only the CPU builds
+#[cfg(feature = "legacy")]
+pub mod system {
+    pub mod cpu;
+}
\ No newline at end of file
@@ -3,8 +3,9 @@ use std::mem;
 use std::slice;
 
 /// VCPU16 Context
-pub struct VCPU16<'sys> {
-    sys: &'sys System,
+pub struct VCPU16 {
+    mem: Memory,
+    clk: Clock,
     irq: IRQ,
     pc: u16,
     sp: u16,
@@ -26,6 +27,7 @@ struct Memory {
 }
 
 /// Interrupt Request Queue
+#[allow(clippy::upper_case_acronyms)]
 struct IRQ {
     /// Queue of interrupts
     interrupts: Vec<u16>,
@@ -41,7 +43,8 @@ struct Clock {
     halted: bool,
     /// Cycles since Startup
     cycles: u64,
-    /// Countdown timer
+    /// Countdown timer, not yet implemented
+    #[allow(dead_code)]
     timer: u16,
     /// Cycles remaining till instruction completes
     busy: u8,
@@ -84,8 +87,8 @@ impl VCPU16 {
             },
             irq: IRQ {
                 interrupts: Vec::new(),
-                enabled: bool,
-                address: u16,
+                enabled: false,
+                address: 0,
             },
             pc: 0,
             sp: 0,
@@ -103,7 +106,7 @@ impl VCPU16 {
     ///
     /// Load Memory from Reader
     ///
-    pub fn load_mem(&mut self, reader: &mut Read) {
+    pub fn load_mem(&mut self, reader: &mut dyn Read) {
         unsafe {
             let memory_size = mem::size_of_val(&self.mem.buffer);
             let memory_slice = slice::from_raw_parts_mut(
@@ -116,14 +119,14 @@ impl VCPU16 {
     ///
     /// Save memory to writer
     ///
-    pub fn save_mem(&mut self, writer: &mut Write) {
+    pub fn save_mem(&mut self, writer: &mut dyn Write) {
         unsafe {
             let memory_size = mem::size_of_val(&self.mem.buffer);
             let memory_slice = slice::from_raw_parts_mut(
                 &mut self.mem.buffer as *mut _ as *mut u8,
                 memory_size,
             );
-            writer.write(memory_slice).unwrap();
+            writer.write_all(memory_slice).unwrap();
         }
     }
     ///
@@ -136,13 +139,13 @@ impl VCPU16 {
     /// Write a slice of memory from buffer
     ///
     pub fn write_mem(&mut self, address: u16, buffer: &[u16]) {
-        self.mem.buffer[address as usize..buffer.len()].copy_from_slice(buffer)
+        self.mem.buffer[address as usize..address as usize + buffer.len()].copy_from_slice(buffer)
     }
     ///
     /// Read a slice length of memory at address
     ///
     pub fn read_mem(&mut self, address: u16, length: u16) -> &[u16] {
-        &self.mem.buffer[address as usize..length as usize]
+        &self.mem.buffer[address as usize..address as usize + length as usize]
     }
     ///
     /// Set a single Cell of Memory at address
@@ -180,9 +183,13 @@ impl VCPU16 {
     pub fn get_i(&self) -> u16 { self.i }
     /// Get value of Register J
     pub fn get_j(&self) -> u16 { self.j }
+    /// Get cycles since Startup
+    pub fn get_cycles(&self) -> u64 { self.clk.cycles }
+    /// Is CPU still busy with the last instruction
+    pub fn is_busy(&self) -> bool { self.clk.busy > 0 }
     /// Enqueue Interrupt
     pub fn interrupt(&mut self, message: u16) {
-        if self.ia != 0 {
+        if self.irq.address != 0 {
             self.irq.interrupts.push(message);
         }
     }
@@ -196,36 +203,32 @@ impl VCPU16 {
         if self.clk.busy > 0 {
             // CPU is busy
             self.clk.busy -= 1;
-        } else if self.irq.enabled && self.irq.interrupts.len() > 0 {
-            let message = self.irq.interrupts.pop().unwrap();
-
-            // Interrupt Queued
+        } else if self.irq.enabled && !self.irq.interrupts.is_empty() {
+            // Interrupt Queued, not yet delivered
+            self.irq.interrupts.pop();
 
         } else {
             self.execute();
         }
-    }
-    /// Was CPU Interrupted
-    fn interrupted(&mut self) -> bool {
-
     }
     /// Execute Next Instruction
     fn execute(&mut self) {
         let address = self.pc;
-        let word = self.mem.buffer[address as usize] as u16;
+        let word = self.mem.buffer[address as usize];
+        self.pc = address.wrapping_add(1);
         fn next_pc(cpu: &mut VCPU16) -> u16 {
             let pc = cpu.pc;
             cpu.clk.busy += 1;
-            cpu.pc += 1;
-            cpu.mem.buffer[pc as usize] as u16
+            cpu.pc = cpu.pc.wrapping_add(1);
+            cpu.mem.buffer[pc as usize]
         }
         fn push_sp(cpu: &mut VCPU16) -> u16 {
-            cpu.sp -= 1;
+            cpu.sp = cpu.sp.wrapping_sub(1);
             cpu.sp
         }
         fn pop_sp(cpu: &mut VCPU16) -> u16 {
             let sp = cpu.sp;
-            cpu.sp += 1;
+            cpu.sp = cpu.sp.wrapping_add(1);
             sp
         }
         fn write_arg(cpu: &mut VCPU16, arg: Argument, value: u16) {
@@ -291,18 +294,18 @@ impl VCPU16 {
                 0x0E => { Argument::Memory(cpu.i) }
                 0x0F => { Argument::Memory(cpu.j) }
                 // [register + NEXT_PC]
-                0x10 => { Argument::Memory(cpu.a + next_pc(cpu)) }
-                0x11 => { Argument::Memory(cpu.b + next_pc(cpu)) }
-                0x12 => { Argument::Memory(cpu.c + next_pc(cpu)) }
-                0x13 => { Argument::Memory(cpu.x + next_pc(cpu)) }
-                0x14 => { Argument::Memory(cpu.y + next_pc(cpu)) }
-                0x15 => { Argument::Memory(cpu.z + next_pc(cpu)) }
-                0x16 => { Argument::Memory(cpu.i + next_pc(cpu)) }
-                0x17 => { Argument::Memory(cpu.j + next_pc(cpu)) }
+                0x10 => { Argument::Memory(cpu.a.wrapping_add(next_pc(cpu))) }
+                0x11 => { Argument::Memory(cpu.b.wrapping_add(next_pc(cpu))) }
+                0x12 => { Argument::Memory(cpu.c.wrapping_add(next_pc(cpu))) }
+                0x13 => { Argument::Memory(cpu.x.wrapping_add(next_pc(cpu))) }
+                0x14 => { Argument::Memory(cpu.y.wrapping_add(next_pc(cpu))) }
+                0x15 => { Argument::Memory(cpu.z.wrapping_add(next_pc(cpu))) }
+                0x16 => { Argument::Memory(cpu.i.wrapping_add(next_pc(cpu))) }
+                0x17 => { Argument::Memory(cpu.j.wrapping_add(next_pc(cpu))) }
                 // Stack Operations
                 0x18 => { Argument::Memory(pop_sp(cpu)) }
                 0x19 => { Argument::Memory(cpu.sp) }
-                0x1A => { Argument::Memory(cpu.sp + next_pc(cpu)) }
+                0x1A => { Argument::Memory(cpu.sp.wrapping_add(next_pc(cpu))) }
                 // Specialty Registers
                 0x1B => { Argument::Register(Register::SP) }
                 0x1C => { Argument::Register(Register::PC) }
@@ -346,7 +349,7 @@ impl VCPU16 {
                 0x3F => { Argument::Literal(0x001E) }
                 _ => { Argument::Literal(0x0000) }
             }
-        };
+        }
         fn middle(cpu: &mut VCPU16, word: u16) -> Argument {
             match (word & 0x03E0) >> 5 {
                 // register
@@ -368,18 +371,18 @@ impl VCPU16 {
                 0x0E => { Argument::Memory(cpu.i) }
                 0x0F => { Argument::Memory(cpu.j) }
                 // [register + NEXT_PC]
-                0x10 => { Argument::Memory(cpu.a + next_pc(cpu)) }
-                0x11 => { Argument::Memory(cpu.b + next_pc(cpu)) }
-                0x12 => { Argument::Memory(cpu.c + next_pc(cpu)) }
-                0x13 => { Argument::Memory(cpu.x + next_pc(cpu)) }
-                0x14 => { Argument::Memory(cpu.y + next_pc(cpu)) }
-                0x15 => { Argument::Memory(cpu.z + next_pc(cpu)) }
-                0x16 => { Argument::Memory(cpu.i + next_pc(cpu)) }
-                0x17 => { Argument::Memory(cpu.j + next_pc(cpu)) }
+                0x10 => { Argument::Memory(cpu.a.wrapping_add(next_pc(cpu))) }
+                0x11 => { Argument::Memory(cpu.b.wrapping_add(next_pc(cpu))) }
+                0x12 => { Argument::Memory(cpu.c.wrapping_add(next_pc(cpu))) }
+                0x13 => { Argument::Memory(cpu.x.wrapping_add(next_pc(cpu))) }
+                0x14 => { Argument::Memory(cpu.y.wrapping_add(next_pc(cpu))) }
+                0x15 => { Argument::Memory(cpu.z.wrapping_add(next_pc(cpu))) }
+                0x16 => { Argument::Memory(cpu.i.wrapping_add(next_pc(cpu))) }
+                0x17 => { Argument::Memory(cpu.j.wrapping_add(next_pc(cpu))) }
                 // Stack Operations
                 0x18 => { Argument::Memory(push_sp(cpu)) }
                 0x19 => { Argument::Memory(cpu.sp) }
-                0x1A => { Argument::Memory(cpu.sp + next_pc(cpu)) }
+                0x1A => { Argument::Memory(cpu.sp.wrapping_add(next_pc(cpu))) }
                 // Specialty Registers
                 0x1B => { Argument::Register(Register::SP) }
                 0x1C => { Argument::Register(Register::PC) }
@@ -390,46 +393,24 @@ impl VCPU16 {
                 0x1F => { Argument::Literal(next_pc(cpu)) }
                 _ => { Argument::Literal(0x0000) }
             }
-        };
+        }
         fn skip_next(cpu: &mut VCPU16) {
-            let word = cpu.pc;
+            // Operands which take the next word
+            fn extra(operand: u16) -> u16 {
+                match operand {
+                    0x10..=0x17 | 0x1A | 0x1E | 0x1F => 1,
+                    _ => 0,
+                }
+            }
+            let word = cpu.mem.buffer[cpu.pc as usize];
+            cpu.clk.busy += 1;
+            cpu.pc = cpu.pc.wrapping_add(1);
             if (word & 0x3FF) == 0 {
-                cpu.clk.busy += 1;
-                cpu.pc += 1;
+                // Nullary OpCodes take no operands
             } else if (word & 0x001F) == 0 {
-                cpu.clk.busy += 1;
-                cpu.pc += 1;
-                match (word & 0xFC00) >> 10 {
-                    0x10 => { cpu.pc += 1 }
-                    0x11 => { cpu.pc += 1 }
-                    0x12 => { cpu.pc += 1 }
-                    0x13 => { cpu.pc += 1 }
-                    0x14 => { cpu.pc += 1 }
-                    0x15 => { cpu.pc += 1 }
-                    0x16 => { cpu.pc += 1 }
-                    0x17 => { cpu.pc += 1 }
-                    0x1A => { cpu.pc += 1 }
-                    0x1E => { cpu.pc += 1 }
-                    0x1F => { cpu.pc += 1 }
-                    _ => {}
-                }
+                cpu.pc = cpu.pc.wrapping_add(extra((word & 0xFC00) >> 10));
             } else {
-                cpu.clk.busy += 1;
-                cpu.pc += 1;
-                match (word & 0x03E0) >> 5 {
-                    0x10 => { cpu.pc += 1 }
-                    0x11 => { cpu.pc += 1 }
-                    0x12 => { cpu.pc += 1 }
-                    0x13 => { cpu.pc += 1 }
-                    0x14 => { cpu.pc += 1 }
-                    0x15 => { cpu.pc += 1 }
-                    0x16 => { cpu.pc += 1 }
-                    0x17 => { cpu.pc += 1 }
-                    0x1A => { cpu.pc += 1 }
-                    0x1E => { cpu.pc += 1 }
-                    0x1F => { cpu.pc += 1 }
-                    _ => {}
-                }
+                cpu.pc = cpu.pc.wrapping_add(extra((word & 0xFC00) >> 10) + extra((word & 0x03E0) >> 5));
             }
         }
         if (word & 0x3FF) == 0 {
@@ -450,7 +431,8 @@ impl VCPU16 {
                     // JSR u
                     // Pushes the address of the next instruction to the stack, then sets PC to u
                     self.clk.busy += 3;
-                    self.mem.buffer[push_sp(self) as usize] = self.pc;
+                    let sp = push_sp(self);
+                    self.mem.buffer[sp as usize] = self.pc;
                     self.pc = read_arg(self, upper);
                 }
                 0x08 => {
@@ -462,7 +444,7 @@ impl VCPU16 {
             };
         } else {
             // Process Binary OpCode
-            let code = (word & 0x001F) >> 0;
+            let code = word & 0x001F;
             let u_arg = upper(self, word);
             let m_arg = middle(self, word);
             match code {
@@ -505,7 +487,7 @@ impl VCPU16 {
                     let u_val = read_arg(self, u_arg) as u32;
                     let result = m_val * u_val;
                     let ps = ((result & 0xFFFF0000) >> 16) as u16;
-                    let rv = ((result & 0x0000FFFF) >> 0) as u16;
+                    let rv = (result & 0x0000FFFF) as u16;
                     write_arg(self, m_arg, rv);
                     self.ps = ps;
                 }
@@ -513,11 +495,11 @@ impl VCPU16 {
                     // MLI m, u
                     // Sets m to (m * u), sets PS to ((m*u)>>16) & 0xFFFF) (treats m, u as signed)
                     self.clk.busy += 2;
-                    let m_val = read_arg(self, m_arg) as i32;
-                    let u_val = read_arg(self, u_arg) as i32;
+                    let m_val = read_arg(self, m_arg) as i16 as i32;
+                    let u_val = read_arg(self, u_arg) as i16 as i32;
                     let result = m_val * u_val;
                     let ps = ((result as u32 & 0xFFFF0000) >> 16) as u16;
-                    let rv = ((result as u32 & 0x0000FFFF) >> 0) as u16;
+                    let rv = (result as u32 & 0x0000FFFF) as u16;
                     write_arg(self, m_arg, rv);
                     self.ps = ps;
                 }
@@ -528,12 +510,9 @@ impl VCPU16 {
                     self.clk.busy += 3;
                     let m_val = read_arg(self, m_arg) as u32;
                     let u_val = read_arg(self, u_arg) as u32;
-                    let (rv, ps) = if u_val != 0 {
-                        let rv = ((m_val / u_val) & 0xFFFF) as u16;
-                        let ps = (((m_val << 16) / u_val) & 0xFFFF) as u16;
-                        (rv, ps)
-                    } else {
-                        (0, 0)
+                    let (rv, ps) = match m_val.checked_div(u_val) {
+                        Some(quotient) => ((quotient & 0xFFFF) as u16, (((m_val << 16) / u_val) & 0xFFFF) as u16),
+                        None => (0, 0),
                     };
                     write_arg(self, m_arg, rv);
                     self.ps = ps;
@@ -543,11 +522,11 @@ impl VCPU16 {
                     // Sets m to m / u, sets PS to ((m<<16)/u)&0xFFFF.
                     // If u==0, sets m and PS to 0 instead. (treats m, u as signed)
                     self.clk.busy += 3;
-                    let m_val = read_arg(self, m_arg) as i32;
-                    let u_val = read_arg(self, u_arg) as i32;
+                    let m_val = read_arg(self, m_arg) as i16 as i32;
+                    let u_val = read_arg(self, u_arg) as i16 as i32;
                     let (rv, ps) = if u_val != 0 {
                         let rv = ((m_val / u_val) & 0xFFFF) as u16;
-                        let ps = (((m_val << 16) / u_val) & 0xFFFF) as u16;
+                        let ps = ((m_val << 16).wrapping_div(u_val) & 0xFFFF) as u16;
                         (rv, ps)
                     } else {
                         (0, 0)
@@ -571,7 +550,7 @@ impl VCPU16 {
                     self.clk.busy += 3;
                     let m_val = read_arg(self, m_arg) as i16;
                     let u_val = read_arg(self, u_arg) as i16;
-                    let rv = if u_val != 0 { m_val % u_val } else { 0 };
+                    let rv = if u_val != 0 { m_val.wrapping_rem(u_val) } else { 0 };
                     write_arg(self, m_arg, rv as u16);
                 }
                 0x0A => {
@@ -607,8 +586,8 @@ impl VCPU16 {
                     self.clk.busy += 1;
                     let m_val = read_arg(self, m_arg);
                     let u_val = read_arg(self, u_arg);
-                    let rv = m_val << u_val;
-                    let ps = ((((m_val as u32) << (u_val as u32)) >> 16) & 0xFFFF) as u16;
+                    let rv = m_val.checked_shl(u_val as u32).unwrap_or(0);
+                    let ps = (((m_val as u32).checked_shl(u_val as u32).unwrap_or(0) >> 16) & 0xFFFF) as u16;
                     write_arg(self, m_arg, rv);
                     self.ps = ps;
                 }
@@ -618,8 +597,8 @@ impl VCPU16 {
                     self.clk.busy += 1;
                     let m_val = read_arg(self, m_arg);
                     let u_val = read_arg(self, u_arg);
-                    let rv = m_val >> u_val;
-                    let ps = ((((m_val as u32) << 16) >> u_val) & 0xFFFF) as u16;
+                    let rv = m_val.checked_shr(u_val as u32).unwrap_or(0);
+                    let ps = (((m_val as u32) << 16).checked_shr(u_val as u32).unwrap_or(0) & 0xFFFF) as u16;
                     write_arg(self, m_arg, rv);
                     self.ps = ps;
                 }
@@ -628,8 +607,8 @@ impl VCPU16 {
                     // Sets m to m >>> u, sets PS to ((m<<16)>>>u)&0xFFFF (arithmetic shift) (treats m as signed)
                     self.clk.busy += 1;
                     let m_val = read_arg(self, m_arg) as i16;
-                    let u_val = read_arg(self, u_arg) as u16;
-                    let rv = m_val >> u_val; // i16 >>> u16
+                    let u_val = read_arg(self, u_arg).min(31) as u32;
+                    let rv = m_val >> u_val.min(15); // i16 >>> u16
                     let ps = ((((m_val as i32) << 16) >> u_val) & 0xFFFF) as u16;
                     write_arg(self, m_arg, rv as u16);
                     self.ps = ps;
@@ -640,9 +619,7 @@ impl VCPU16 {
                     self.clk.busy += 2;
                     let m_val = read_arg(self, m_arg);
                     let u_val = read_arg(self, u_arg);
-                    if m_val & u_val != 0 {
-                        self.execute();
-                    } else {
+                    if m_val & u_val == 0 {
                         skip_next(self);
                     }
                 }
@@ -652,9 +629,7 @@ impl VCPU16 {
                     self.clk.busy += 2;
                     let m_val = read_arg(self, m_arg);
                     let u_val = read_arg(self, u_arg);
-                    if m_val & u_val == 0 {
-                        self.execute();
-                    } else {
+                    if m_val & u_val != 0 {
                         skip_next(self);
                     }
                 }
@@ -664,9 +639,7 @@ impl VCPU16 {
                     self.clk.busy += 2;
                     let m_val = read_arg(self, m_arg);
                     let u_val = read_arg(self, u_arg);
-                    if m_val == u_val {
-                        self.execute();
-                    } else {
+                    if m_val != u_val {
                         skip_next(self);
                     }
                 }
@@ -676,9 +649,7 @@ impl VCPU16 {
                     self.clk.busy += 2;
                     let m_val = read_arg(self, m_arg);
                     let u_val = read_arg(self, u_arg);
-                    if m_val != u_val {
-                        self.execute();
-                    } else {
+                    if m_val == u_val {
                         skip_next(self);
                     }
                 }
@@ -688,9 +659,7 @@ impl VCPU16 {
                     self.clk.busy += 2;
                     let m_val = read_arg(self, m_arg);
                     let u_val = read_arg(self, u_arg);
-                    if m_val > u_val {
-                        self.execute();
-                    } else {
+                    if m_val <= u_val {
                         skip_next(self);
                     }
                 }
@@ -700,9 +669,7 @@ impl VCPU16 {
                     self.clk.busy += 2;
                     let m_val = read_arg(self, m_arg) as i16;
                     let u_val = read_arg(self, u_arg) as i16;
-                    if m_val > u_val {
-                        self.execute();
-                    } else {
+                    if m_val <= u_val {
                         skip_next(self);
                     }
                 }
@@ -712,9 +679,7 @@ impl VCPU16 {
                     self.clk.busy += 2;
                     let m_val = read_arg(self, m_arg);
                     let u_val = read_arg(self, u_arg);
-                    if m_val < u_val {
-                        self.execute();
-                    } else {
+                    if m_val >= u_val {
                         skip_next(self);
                     }
                 }
@@ -724,9 +689,7 @@ impl VCPU16 {
                     self.clk.busy += 2;
                     let m_val = read_arg(self, m_arg) as i16;
                     let u_val = read_arg(self, u_arg) as i16;
-                    if m_val < u_val {
-                        self.execute();
-                    } else {
+                    if m_val >= u_val {
                         skip_next(self);
                     }
                 }
@@ -746,13 +709,13 @@ impl VCPU16 {
                 0x1B => {
                     // SBX m, u
                     // Sets m to m - u + PS, sets PS to 0xFFFF if there is an underflow, 0x0000 otherwise
-                    // TODO: Figure out Better Logic Here
+                    // PS holds 0xFFFF after a borrow, which carries as -1
                     self.clk.busy += 3;
-                    let m_val = read_arg(self, m_arg) as u32;
-                    let u_val = read_arg(self, u_arg) as u32;
-                    let result = m_val - u_val + self.ps as u32;
-                    let rv = (result % 65_536) as u16;
-                    let ps = if result / 65_536 > 0 { 0xFFFF } else { 0x0000 };
+                    let m_val = read_arg(self, m_arg) as i32;
+                    let u_val = read_arg(self, u_arg) as i32;
+                    let result = m_val - u_val + self.ps as i16 as i32;
+                    let rv = result as u16;
+                    let ps = if result < 0 { 0xFFFF } else { 0x0000 };
                     write_arg(self, m_arg, rv);
                     self.ps = ps;
                 }
@@ -762,8 +725,8 @@ impl VCPU16 {
                     self.clk.busy += 2;
                     let u_val = read_arg(self, u_arg);
                     write_arg(self, m_arg, u_val);
-                    self.i += 1;
-                    self.j += 1;
+                    self.i = self.i.wrapping_add(1);
+                    self.j = self.j.wrapping_add(1);
                 }
                 0x1F => {
                     // STD m, u
@@ -771,8 +734,8 @@ impl VCPU16 {
                     self.clk.busy += 2;
                     let u_val = read_arg(self, u_arg);
                     write_arg(self, m_arg, u_val);
-                    self.i -= 1;
-                    self.j -= 1;
+                    self.i = self.i.wrapping_sub(1);
+                    self.j = self.j.wrapping_sub(1);
                 }
                 _ => { /* Error */ }
             }
@@ -780,6 +743,12 @@ impl VCPU16 {
     }
 }
 
+impl Default for VCPU16 {
+    fn default() -> VCPU16 {
+        VCPU16::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::VCPU16;
@@ -835,12 +804,12 @@ mod tests {
         let newvalue: u16 = 0x2222;
 
         // Assert Memory at address equals oldvalue
-        assert_eq!(oldvalue, cpu.get(address));
+        assert_eq!(&[oldvalue], cpu.read_mem(address, 1));
 
-        // Set Memory at address to newvalue
-        ram.set(address, newvalue);
+        // Write newvalue to Memory at address
+        cpu.write_mem(address, &[newvalue]);
 
         // Assert Memory at address equals newvalue
-        assert_eq!(newvalue, ram.get(address));
+        assert_eq!(&[newvalue], cpu.read_mem(address, 1));
     }
 }
\ No newline at end of file
@@ -0,0 +1,309 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use std::fmt;
+use std::str::FromStr;
+use super::Memory;
+use super::Mismatch;
+use super::Operand;
+use super::Register;
+use super::Registers;
+use super::SystemError;
+use super::Word;
+
+/// Event which triggers a Breakpoint
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Trigger {
+    /// Next instruction to execute is at address
+    Address(Word),
+    /// Clock reaches cycle
+    Cycle(u64),
+    /// Interrupt is delivered to the handler, with message or any message
+    Interrupt(Option<Word>),
+    /// Instruction reads a Word in the range of address and length
+    Read(Word, Word),
+    /// Anything writes a Word in the range of address and length
+    Write(Word, Word),
+    /// Register changes value
+    Register(Register),
+    /// Return does not match the shadow call stack
+    Return,
+}
+
+impl Trigger {
+    /// Is this a watchpoint on data rather than a breakpoint on control flow
+    pub fn is_watchpoint(&self) -> bool {
+        matches!(*self, Trigger::Read(..) | Trigger::Write(..) | Trigger::Register(_))
+    }
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Trigger::Address(address) => write!(f, "address 0x{:04X}", address),
+            Trigger::Cycle(cycle) => write!(f, "cycle {}", cycle),
+            Trigger::Interrupt(Some(message)) => write!(f, "interrupt 0x{:04X}", message),
+            Trigger::Interrupt(None) => write!(f, "interrupt"),
+            Trigger::Read(address, length) => write!(f, "read 0x{:04X}+{}", address, length),
+            Trigger::Write(address, length) => write!(f, "write 0x{:04X}+{}", address, length),
+            Trigger::Register(register) => write!(f, "register {}", register),
+            Trigger::Return => write!(f, "mismatched return"),
+        }
+    }
+}
+
+/// Comparison of a Condition
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Comparison {
+    /// Comparison symbol
+    pub fn symbol(&self) -> &'static str {
+        match *self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterEqual => ">=",
+        }
+    }
+}
+
+/// Unsigned comparison of a Register or Memory Operand against a value, e.g. `A == 0x10`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Condition {
+    /// Register, Indirect, Offset or Direct Operand
+    pub operand: Operand,
+    /// Comparison applied
+    pub comparison: Comparison,
+    /// Value compared against
+    pub value: Word,
+}
+
+impl Condition {
+    /// Evaluate Condition against the System state
+    pub fn evaluate(&self, registers: &Registers, memory: &Memory) -> bool {
+        let actual = match self.operand {
+            Operand::Register(register) => registers.get(register),
+            Operand::Indirect(register) => memory.get(registers.get(register)),
+            Operand::Offset(register, offset) => memory.get(registers.get(register).wrapping_add(offset)),
+            Operand::Direct(address) => memory.get(address),
+            Operand::Literal(value) => value,
+            Operand::Push | Operand::Pop => return false,
+        };
+        match self.comparison {
+            Comparison::Equal => actual == self.value,
+            Comparison::NotEqual => actual != self.value,
+            Comparison::Less => actual < self.value,
+            Comparison::LessEqual => actual <= self.value,
+            Comparison::Greater => actual > self.value,
+            Comparison::GreaterEqual => actual >= self.value,
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = SystemError;
+    fn from_str(text: &str) -> Result<Condition, SystemError> {
+        // Longest symbols first so "<=" is not taken for "<"
+        let comparisons = [
+            Comparison::Equal, Comparison::NotEqual, Comparison::LessEqual,
+            Comparison::GreaterEqual, Comparison::Less, Comparison::Greater,
+        ];
+        for &comparison in &comparisons {
+            if let Some(index) = text.find(comparison.symbol()) {
+                let operand = parse_operand(text[..index].trim())?;
+                let value = parse_number(text[index + comparison.symbol().len()..].trim())?;
+                return Ok(Condition { operand, comparison, value });
+            }
+        }
+        Err(SystemError::InvalidExpression)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} 0x{:04X}", self.operand, self.comparison.symbol(), self.value)
+    }
+}
+
+/// Parse a decimal or `0x` prefixed hexadecimal number
+pub fn parse_number(text: &str) -> Result<Word, SystemError> {
+    let result = if text.starts_with("0x") || text.starts_with("0X") {
+        Word::from_str_radix(&text[2..], 16)
+    } else {
+        text.parse()
+    };
+    result.map_err(|_| SystemError::InvalidExpression)
+}
+
+/// Parse a side effect free Operand: `A`, `[A]`, `[A + 0x10]`, `[0x1000]` or a number
+pub fn parse_operand(text: &str) -> Result<Operand, SystemError> {
+    if let Some(register) = Register::from_name(text) {
+        return Ok(Operand::Register(register));
+    }
+    if !text.starts_with('[') {
+        return parse_number(text).map(Operand::Literal);
+    }
+    if !text.ends_with(']') {
+        return Err(SystemError::InvalidExpression);
+    }
+    let inner = text[1..text.len() - 1].trim();
+    if let Some(index) = inner.find('+') {
+        let register = Register::from_name(inner[..index].trim()).ok_or(SystemError::InvalidExpression)?;
+        let offset = parse_number(inner[index + 1..].trim())?;
+        return Ok(Operand::Offset(register, offset));
+    }
+    match Register::from_name(inner) {
+        Some(register) => Ok(Operand::Indirect(register)),
+        None => parse_number(inner).map(Operand::Direct),
+    }
+}
+
+/// Breakpoint or Watchpoint on a System
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Breakpoint {
+    /// Event which triggers the Breakpoint
+    pub trigger: Trigger,
+    /// Condition which must also hold after the triggering cycle
+    pub condition: Option<Condition>,
+    /// Number of hits to pass over before stopping
+    pub ignore: u64,
+    /// Disabled Breakpoints never trigger
+    pub enabled: bool,
+    /// Number of times triggered with the Condition holding
+    pub hits: u64,
+}
+
+impl Breakpoint {
+    /// Create an enabled Breakpoint stopping on every trigger
+    pub fn new(trigger: Trigger) -> Breakpoint {
+        Breakpoint {
+            trigger,
+            condition: None,
+            ignore: 0,
+            enabled: true,
+            hits: 0,
+        }
+    }
+    /// Only trigger while condition holds
+    pub fn when(mut self, condition: Condition) -> Breakpoint {
+        self.condition = Some(condition);
+        self
+    }
+    /// Pass over the first count hits
+    pub fn ignore(mut self, count: u64) -> Breakpoint {
+        self.ignore = count;
+        self
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.trigger)?;
+        if let Some(ref condition) = self.condition {
+            write!(f, " if {}", condition)?;
+        }
+        if self.ignore > 0 {
+            write!(f, " ignore {}", self.ignore)?;
+        }
+        if !self.enabled {
+            write!(f, " (disabled)")?;
+        }
+        write!(f, " hits {}", self.hits)
+    }
+}
+
+/// What happened to stop the System
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// Next instruction is at address
+    Address(Word),
+    /// Clock reached cycle
+    Cycle(u64),
+    /// Interrupt with message was delivered
+    Interrupt(Word),
+    /// Word at address was read
+    Read(Word),
+    /// Word at address was written
+    Write { address: Word, old: Word, new: Word },
+    /// Register changed value
+    Register { register: Register, old: Word, new: Word },
+    /// Return did not match the shadow call stack
+    Return(Mismatch),
+}
+
+/// Reason the System stopped, by Breakpoint id
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Stop {
+    /// Breakpoint id
+    pub id: usize,
+    /// Event which triggered it
+    pub event: Event,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.event {
+            Event::Address(address) => write!(f, "Breakpoint {} at 0x{:04X}", self.id, address),
+            Event::Cycle(cycle) => write!(f, "Breakpoint {} at cycle {}", self.id, cycle),
+            Event::Interrupt(message) => write!(f, "Breakpoint {} on interrupt 0x{:04X}", self.id, message),
+            Event::Read(address) => write!(f, "Watchpoint {} read 0x{:04X}", self.id, address),
+            Event::Write { address, old, new } => {
+                write!(f, "Watchpoint {} write 0x{:04X}: {:04X} -> {:04X}", self.id, address, old, new)
+            }
+            Event::Register { register, old, new } => {
+                write!(f, "Watchpoint {} register {}: {:04X} -> {:04X}", self.id, register, old, new)
+            }
+            Event::Return(mismatch) => write!(f, "Breakpoint {} on mismatched return: {}", self.id, mismatch),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Memory, Operand, Register, Registers, SystemError};
+    use super::{Comparison, Condition};
+
+    #[test]
+    pub fn test_conditions() {
+        let mut registers = Registers::new();
+        let mut memory = Memory::new();
+        registers.a = 0x0010;
+        registers.b = 0x1000;
+        memory.set(0x1002, 7);
+
+        let condition: Condition = "A == 0x10".parse().unwrap();
+        assert_eq!(Operand::Register(Register::A), condition.operand);
+        assert_eq!(Comparison::Equal, condition.comparison);
+        assert!(condition.evaluate(&registers, &memory));
+        assert_eq!("A == 0x0010", condition.to_string());
+
+        assert!("[b + 2] >= 7".parse::<Condition>().unwrap().evaluate(&registers, &memory));
+        assert!("[0x1002]<=6".parse::<Condition>().map(|c| !c.evaluate(&registers, &memory)).unwrap());
+        assert!("[B] != 0".parse::<Condition>().map(|c| !c.evaluate(&registers, &memory)).unwrap());
+        assert_eq!(SystemError::InvalidExpression, "A = 1".parse::<Condition>().unwrap_err());
+        assert_eq!(SystemError::InvalidExpression, "Q == 1".parse::<Condition>().unwrap_err());
+    }
+}
@@ -0,0 +1,194 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use std::fmt;
+use super::Word;
+
+/// Subroutine call or interrupt entry on the shadow call stack
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Call {
+    /// Address of the JSR instruction, or the interrupted PC
+    pub call: Word,
+    /// Address called
+    pub target: Word,
+    /// Return address pushed
+    pub ret: Word,
+    /// Stack address holding the return address
+    pub slot: Word,
+    /// Interrupt message when entered by an interrupt
+    pub interrupt: Option<Word>,
+    /// Clock cycle of the call
+    pub cycle: u64,
+}
+
+/// Return which did not match the shadow call stack
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Mismatch {
+    /// Address of the returning instruction
+    pub address: Word,
+    /// Call expected to return, if any was on the shadow stack
+    pub expected: Option<Call>,
+    /// Address actually returned to
+    pub actual: Word,
+    /// Stack address the return address was popped from
+    pub slot: Word,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:04X} returned to 0x{:04X} from stack 0x{:04X}", self.address, self.actual, self.slot)?;
+        match self.expected {
+            Some(call) => write!(f, ", expected 0x{:04X} from stack 0x{:04X}", call.ret, call.slot),
+            None => write!(f, " with no call outstanding"),
+        }
+    }
+}
+
+/// Names addresses for a backtrace
+pub trait Symbolizer {
+    /// Name for address, such as a label and offset
+    fn symbolize(&self, address: Word) -> Option<String>;
+}
+
+/// Frame of a backtrace, innermost first
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StackFrame {
+    /// Current address within the frame
+    pub address: Word,
+    /// Address of the subroutine or handler, None for the outermost frame
+    pub function: Option<Word>,
+    /// Symbolized address
+    pub name: Option<String>,
+    /// Interrupt message when the frame is an interrupt handler
+    pub interrupt: Option<Word>,
+}
+
+impl fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:04X}", self.address)?;
+        match (self.name.as_ref(), self.function) {
+            (Some(name), _) => write!(f, " <{}>", name)?,
+            (None, Some(function)) => write!(f, " in 0x{:04X}", function)?,
+            (None, None) => {}
+        }
+        if let Some(message) = self.interrupt {
+            write!(f, " (interrupt 0x{:04X})", message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Shadow call stack maintained from JSR, interrupt entry, SET PC, POP and RFI
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CallStack {
+    calls: Vec<Call>,
+}
+
+impl CallStack {
+    /// Create an empty CallStack
+    pub fn new() -> CallStack {
+        CallStack { calls: Vec::new() }
+    }
+    /// Outstanding calls, outermost first
+    pub fn calls(&self) -> &[Call] {
+        &self.calls
+    }
+    /// Number of outstanding calls
+    pub fn depth(&self) -> usize {
+        self.calls.len()
+    }
+    /// Forget all outstanding calls
+    pub fn clear(&mut self) {
+        self.calls.clear();
+    }
+    /// Frames from pc out to the outermost caller, named by symbols
+    pub fn backtrace(&self, pc: Word, symbols: Option<&dyn Symbolizer>) -> Vec<StackFrame> {
+        let name = |address: Word| symbols.and_then(|symbols| symbols.symbolize(address));
+        let mut frames = Vec::new();
+        let mut address = pc;
+        for call in self.calls.iter().rev() {
+            frames.push(StackFrame { address, function: Some(call.target), name: name(address), interrupt: call.interrupt });
+            address = call.call;
+        }
+        frames.push(StackFrame { address, function: None, name: name(address), interrupt: None });
+        frames
+    }
+    /// Record a call
+    pub(super) fn call(&mut self, call: Call) {
+        self.calls.push(call);
+    }
+    /// Record a return from address to actual, popped from slot, returning any Mismatch
+    ///
+    /// Calls made deeper in the stack than slot were abandoned and are discarded. The call
+    /// whose return address was held in slot is popped, and matches if it returned to the
+    /// address it pushed.
+    pub(super) fn ret(&mut self, address: Word, actual: Word, slot: Word) -> Option<Mismatch> {
+        let expected = self.calls.last().cloned();
+        let abandoned = self.calls.iter().any(|call| call.slot < slot);
+        self.calls.retain(|call| call.slot >= slot);
+        let popped = match self.calls.last() {
+            Some(call) if call.slot == slot => self.calls.pop(),
+            _ => None,
+        };
+        match popped {
+            Some(call) if call.ret == actual && !abandoned => None,
+            _ => Some(Mismatch { address, expected, actual, slot }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Call, CallStack};
+
+    fn call(ret: u16, slot: u16) -> Call {
+        Call { call: ret - 2, target: 0x0100, ret, slot, interrupt: None, cycle: 0 }
+    }
+
+    #[test]
+    pub fn test_returns() {
+        let mut stack = CallStack::new();
+        stack.call(call(0x0002, 0xFFFF));
+        stack.call(call(0x0102, 0xFFFE));
+
+        // Matching return
+        assert_eq!(None, stack.ret(0x0105, 0x0102, 0xFFFE));
+        assert_eq!(1, stack.depth());
+
+        // Corrupted return address still pops its call
+        let mismatch = stack.ret(0x0105, 0x4000, 0xFFFF).unwrap();
+        assert_eq!(Some(call(0x0002, 0xFFFF)), mismatch.expected);
+        assert_eq!(0, stack.depth());
+
+        // Returning past an inner call abandons it
+        stack.call(call(0x0002, 0xFFFF));
+        stack.call(call(0x0102, 0xFFFE));
+        let mismatch = stack.ret(0x0105, 0x0002, 0xFFFF).unwrap();
+        assert_eq!(Some(call(0x0102, 0xFFFE)), mismatch.expected);
+        assert_eq!(0, stack.depth());
+
+        // Backtrace from inside a handler interrupting a call
+        stack.call(call(0x0002, 0xFFFF));
+        stack.call(Call { call: 0x0105, target: 0x0200, ret: 0x0105, slot: 0xFFFD, interrupt: Some(0x0042), cycle: 0 });
+        let frames: Vec<String> = stack.backtrace(0x0201, None).iter().map(|frame| frame.to_string()).collect();
+        assert_eq!(vec!["0x0201 in 0x0200 (interrupt 0x0042)", "0x0105 in 0x0100", "0x0000"], frames);
+        stack.clear();
+
+        // Returning with nothing called
+        let mismatch = stack.ret(0x0105, 0x0002, 0xFFFF).unwrap();
+        assert_eq!("0x0105 returned to 0x0002 from stack 0xFFFF with no call outstanding", mismatch.to_string());
+    }
+}
@@ -25,6 +25,9 @@ pub struct Clock {
 }
 
 impl Clock {
+    /// Largest count advance reaches, leaving room for deadlines past the current cycle
+    pub const LIMIT: u64 = u64::MAX / 2;
+
     /// Create a new Clock
     pub fn new() -> Clock {
         Clock {
@@ -45,16 +48,26 @@ impl Clock {
         if self.halted {
             Err(SystemError::ClockHalted)
         } else {
-            self.cycles += 1;
+            self.cycles = self.cycles.saturating_add(1);
             Ok(self.cycles)
         }
     }
+    /// Advance Clock by a number of cycles, even if halted, stopping at Clock::LIMIT
+    pub fn advance(&mut self, cycles: u64) {
+        self.cycles = self.cycles.saturating_add(cycles).min(Clock::LIMIT).max(self.cycles);
+    }
     /// Halt Clock
     pub fn halt(&mut self) {
         self.halted = true;
     }
 }
 
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock::new()
+    }
+}
+
 impl fmt::Display for Clock {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "Clock Halted: {} Cycles: {}", self.halted, self.cycles)
@@ -0,0 +1,235 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use std::fmt;
+use std::io::{self, Write};
+use super::Instruction;
+use super::Memory;
+use super::OpCode;
+use super::Operand;
+use super::Snapshot;
+use super::State;
+use super::SystemError;
+use super::Trace;
+use super::Word;
+
+/// Most stack words scanned for return addresses
+const STACK_SCAN: Word = 256;
+
+/// Instructions listed either side of the failing address
+const LISTING: usize = 4;
+
+/// Subroutine call found by scanning the stack
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Frame {
+    /// Stack address holding the return address
+    pub slot: Word,
+    /// Address of the JSR instruction
+    pub call: Word,
+    /// Return address pushed by the JSR
+    pub ret: Word,
+    /// Subroutine address when the JSR operand is a literal
+    pub target: Option<Word>,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:04X} ", self.call)?;
+        match self.target {
+            Some(target) => write!(f, "JSR 0x{:04X}", target)?,
+            None => write!(f, "JSR")?,
+        }
+        write!(f, " returning to 0x{:04X} (stack 0x{:04X})", self.ret, self.slot)
+    }
+}
+
+/// Post-mortem state of a System which stopped on an error
+#[derive(Clone)]
+pub struct CrashReport {
+    /// Error which stopped the System
+    pub reason: SystemError,
+    /// Address of the last instruction executed, or PC when none were kept
+    pub address: Word,
+    /// Complete System state, saved as the core file
+    pub snapshot: Snapshot,
+    /// Calls found on the stack, innermost first
+    pub backtrace: Vec<Frame>,
+    /// Most recently executed instructions, oldest first
+    pub recent: Vec<Trace>,
+    /// Instructions around the failing address as address, raw words and decoded Instruction
+    pub disassembly: Vec<(Word, Vec<Word>, Instruction)>,
+}
+
+impl CrashReport {
+    /// Build a report of snapshot stopped by reason, with the recent instruction Traces
+    pub fn new(reason: SystemError, snapshot: Snapshot, recent: Vec<Trace>) -> CrashReport {
+        let address = recent.last().map_or(snapshot.registers.pc, |trace| trace.pc);
+        let backtrace = backtrace(&snapshot.memory, snapshot.registers.sp);
+        let disassembly = disassemble(&snapshot.memory, address);
+        CrashReport { reason, address, snapshot, backtrace, recent, disassembly }
+    }
+    /// Write the core file, a Snapshot which the debugger can load
+    pub fn save_core(&self, writer: &mut dyn Write) -> io::Result<()> {
+        self.snapshot.save(writer)
+    }
+}
+
+impl fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let snapshot = &self.snapshot;
+        writeln!(f, "System stopped: {}", self.reason)?;
+        writeln!(f, "Cycle {}{}", snapshot.clock.cycles(), if snapshot.clock.halted() { ", clock halted" } else { "" })?;
+        if let State::Execute { address, cycles } = snapshot.state {
+            writeln!(f, "Executing 0x{:04X} with {} cycles remaining", address, cycles)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Registers:")?;
+        writeln!(f, "  {}", snapshot.registers)?;
+        writeln!(f)?;
+        writeln!(f, "Backtrace:")?;
+        writeln!(f, "  #0 0x{:04X}", self.address)?;
+        for (index, frame) in self.backtrace.iter().enumerate() {
+            writeln!(f, "  #{} {}", index + 1, frame)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Disassembly:")?;
+        for &(address, ref words, instruction) in &self.disassembly {
+            let marker = if address == self.address { "=>" } else { "  " };
+            let words: Vec<String> = words.iter().map(|word| format!("{:04X}", word)).collect();
+            writeln!(f, "{} 0x{:04X}  {:<15} {}", marker, address, words.join(" "), instruction)?;
+        }
+        if !self.recent.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Last {} instructions:", self.recent.len())?;
+            for trace in &self.recent {
+                writeln!(f, "  {}", trace)?;
+            }
+        }
+        writeln!(f)?;
+        let state = if snapshot.queue.is_enabled() { "queueing" } else { "triggering" };
+        write!(f, "Interrupt queue ({}):", state)?;
+        if snapshot.queue.is_empty() {
+            write!(f, " empty")?;
+        }
+        for message in snapshot.queue.iter() {
+            write!(f, " 0x{:04X}", message)?;
+        }
+        writeln!(f)
+    }
+}
+
+/// Scan the stack from sp for return addresses which follow a JSR instruction
+fn backtrace(memory: &Memory, sp: Word) -> Vec<Frame> {
+    let mut frames = Vec::new();
+    // The stack grows down from 0, so it is empty when SP is 0
+    if sp == 0 {
+        return frames;
+    }
+    let depth = (0x10000 - sp as u32).min(STACK_SCAN as u32) as Word;
+    for offset in 0..depth {
+        let slot = sp.wrapping_add(offset);
+        let ret = memory.get(slot);
+        for size in 1..3 {
+            let call = ret.wrapping_sub(size);
+            let instruction = Instruction::decode(memory, call);
+            if instruction.opcode == OpCode::JSR && instruction.size == size {
+                let target = match instruction.u {
+                    Some(Operand::Literal(target)) => Some(target),
+                    _ => None,
+                };
+                frames.push(Frame { slot, call, ret, target });
+                break;
+            }
+        }
+    }
+    frames
+}
+
+/// Instructions before and after pc, finding earlier boundaries by decoding forward
+fn disassemble(memory: &Memory, pc: Word) -> Vec<(Word, Vec<Word>, Instruction)> {
+    let mut starts = Vec::new();
+    let mut scan = pc.saturating_sub((LISTING * 3) as Word);
+    while scan < pc {
+        starts.push(scan);
+        match scan.checked_add(Instruction::decode(memory, scan).size) {
+            Some(next) => scan = next,
+            None => break,
+        }
+    }
+    // Earlier instructions which do not line up with pc are not listed
+    let before = if scan == pc { &starts[starts.len().saturating_sub(LISTING)..] } else { &[][..] };
+    let mut address = before.first().cloned().unwrap_or(pc);
+    (0..before.len() + LISTING + 1)
+        .map(|_| {
+            let instruction = Instruction::decode(memory, address);
+            let words = (0..instruction.size).map(|index| memory.get(address.wrapping_add(index))).collect();
+            let line = (address, words, instruction);
+            address = address.wrapping_add(instruction.size);
+            line
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Snapshot, System, SystemError};
+    use super::Frame;
+    use std::io::Cursor;
+
+    #[test]
+    pub fn test_report() {
+        let mut sys = System::new();
+        sys.keep_recent(4);
+        sys.memory_mut().write(0x0000, &[
+            0x7C20, 0x0005,         // JSR 0x0005
+            0x0000,                 // NOP
+            0x0000,                 // NOP
+            0x0000,                 // NOP
+            0x0020,                 // JSR A (0x0005)
+            0x0000,                 // NOP
+            0xFC00,                 // ERR (0x0007)
+        ]).unwrap();
+        sys.registers_mut().a = 0x0007;
+        let error = loop {
+            if let Err(error) = sys.step() {
+                break error;
+            }
+        };
+        assert_eq!(SystemError::InvalidInstruction, error);
+
+        // Both calls are on the stack, innermost first
+        let report = sys.crash_report(error);
+        assert_eq!(vec![
+            Frame { slot: 0xFFFE, call: 0x0005, ret: 0x0006, target: None },
+            Frame { slot: 0xFFFF, call: 0x0000, ret: 0x0002, target: Some(0x0005) },
+        ], report.backtrace);
+        assert_eq!(3, report.recent.len());
+        assert_eq!(0x0007, report.address);
+        let addresses: Vec<u16> = report.disassembly.iter().map(|&(address, _, _)| address).collect();
+        assert_eq!(vec![0x0003, 0x0004, 0x0005, 0x0006, 0x0007, 0x0008, 0x0009, 0x000A, 0x000B], addresses);
+        let text = report.to_string();
+        assert!(text.starts_with("System stopped: SystemError::InvalidInstruction\n"));
+        assert!(text.contains("=> 0x0007  FC00"));
+        assert!(text.contains("Interrupt queue (triggering): empty"));
+
+        // Core file loads back as a Snapshot
+        let mut core = Vec::new();
+        report.save_core(&mut core).unwrap();
+        let snapshot = Snapshot::load(&mut Cursor::new(core)).unwrap();
+        assert_eq!(report.snapshot.registers, snapshot.registers);
+        assert!(snapshot.memory.diff(sys.memory()).is_empty());
+    }
+}
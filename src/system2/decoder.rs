@@ -14,27 +14,30 @@
 // limitations under the License.
 //
 
+use std::fmt;
+use super::Memory;
+use super::Word;
+
 /// CPU State
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum State {
-    /// Step 1
-    FetchBase {
-        address: Word,
-    },
-    Decode {
-        address: Word,
-
-    },
-
+    /// Ready to fetch the next instruction
+    Idle,
+    /// Instruction at address has executed and is waiting out its remaining cycles
     Execute {
         address: Word,
+        cycles: u16,
     },
 }
 
-#[derive(Copy, Clone, Debug)]
-enum Register {
+/// CPU Register
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum Register {
     PC,
     SP,
     PS,
+    IA,
     A,
     B,
     C,
@@ -45,14 +48,94 @@ enum Register {
     J,
 }
 
-#[derive(Copy, Clone, Debug)]
-enum Argument {
-    Memory(Word),
-    Literal(Word),
+impl Register {
+    /// All Registers in display order
+    pub const ALL: [Register; 12] = [
+        Register::A, Register::B, Register::C, Register::X, Register::Y, Register::Z,
+        Register::I, Register::J, Register::PC, Register::SP, Register::PS, Register::IA,
+    ];
+    /// Register name
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Register::PC => "PC",
+            Register::SP => "SP",
+            Register::PS => "PS",
+            Register::IA => "IA",
+            Register::A => "A",
+            Register::B => "B",
+            Register::C => "C",
+            Register::X => "X",
+            Register::Y => "Y",
+            Register::Z => "Z",
+            Register::I => "I",
+            Register::J => "J",
+        }
+    }
+    /// Register by case insensitive name
+    pub fn from_name(name: &str) -> Option<Register> {
+        Register::ALL.iter().cloned().find(|reg| reg.name().eq_ignore_ascii_case(name))
+    }
+    /// General Purpose Register by operand index
+    fn general(index: Word) -> Register {
+        Register::ALL[(index & 0x7) as usize]
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Decoded Instruction Operand
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Operand {
+    /// Register
     Register(Register),
+    /// Memory at Register
+    Indirect(Register),
+    /// Memory at Register + NEXT
+    Offset(Register, Word),
+    /// Push Stack [--SP]
+    Push,
+    /// Pop Stack [SP++]
+    Pop,
+    /// Memory at NEXT
+    Direct(Word),
+    /// Literal Value
+    Literal(Word),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Register(reg) => write!(f, "{}", reg),
+            Operand::Indirect(reg) => write!(f, "[{}]", reg),
+            Operand::Offset(reg, offset) => write!(f, "[{} + 0x{:04X}]", reg, offset),
+            Operand::Push => write!(f, "PUSH"),
+            Operand::Pop => write!(f, "POP"),
+            Operand::Direct(address) => write!(f, "[0x{:04X}]", address),
+            Operand::Literal(value) => write!(f, "0x{:04X}", value),
+        }
+    }
 }
 
-enum OpCode {
+/// Instruction Operation
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum OpCode {
+    NOP,
+    CLK,
+    ERR,
+    JSR,
+    INT,
+    IAG,
+    IAS,
+    RFI,
+    IAQ,
+    HWN,
+    HWQ,
+    HWI,
     SET,
     ADD,
     SUB,
@@ -82,3 +165,248 @@ enum OpCode {
     STD,
 }
 
+impl OpCode {
+    /// Operation mnemonic
+    pub fn name(&self) -> &'static str {
+        match *self {
+            OpCode::NOP => "NOP",
+            OpCode::CLK => "CLK",
+            OpCode::ERR => "ERR",
+            OpCode::JSR => "JSR",
+            OpCode::INT => "INT",
+            OpCode::IAG => "IAG",
+            OpCode::IAS => "IAS",
+            OpCode::RFI => "RFI",
+            OpCode::IAQ => "IAQ",
+            OpCode::HWN => "HWN",
+            OpCode::HWQ => "HWQ",
+            OpCode::HWI => "HWI",
+            OpCode::SET => "SET",
+            OpCode::ADD => "ADD",
+            OpCode::SUB => "SUB",
+            OpCode::MUL => "MUL",
+            OpCode::MLI => "MLI",
+            OpCode::DIV => "DIV",
+            OpCode::DVI => "DVI",
+            OpCode::MOD => "MOD",
+            OpCode::MDI => "MDI",
+            OpCode::AND => "AND",
+            OpCode::BOR => "BOR",
+            OpCode::XOR => "XOR",
+            OpCode::LLS => "LLS",
+            OpCode::LRS => "LRS",
+            OpCode::ARS => "ARS",
+            OpCode::IFB => "IFB",
+            OpCode::IFC => "IFC",
+            OpCode::IFE => "IFE",
+            OpCode::IFN => "IFN",
+            OpCode::IFG => "IFG",
+            OpCode::IFA => "IFA",
+            OpCode::IFL => "IFL",
+            OpCode::IFU => "IFU",
+            OpCode::ADX => "ADX",
+            OpCode::SBX => "SBX",
+            OpCode::STI => "STI",
+            OpCode::STD => "STD",
+        }
+    }
+    /// Base cycles excluding operands
+    pub fn cycles(&self) -> u16 {
+        match *self {
+            OpCode::NOP | OpCode::CLK | OpCode::ERR => 1,
+            OpCode::IAG | OpCode::IAS => 1,
+            OpCode::IAQ | OpCode::HWN => 2,
+            OpCode::JSR | OpCode::RFI => 3,
+            OpCode::INT | OpCode::HWQ | OpCode::HWI => 4,
+            OpCode::SET | OpCode::AND | OpCode::BOR | OpCode::XOR => 1,
+            OpCode::LLS | OpCode::LRS | OpCode::ARS => 1,
+            OpCode::ADD | OpCode::SUB | OpCode::MUL | OpCode::MLI => 2,
+            OpCode::DIV | OpCode::DVI | OpCode::MOD | OpCode::MDI => 3,
+            OpCode::IFB | OpCode::IFC | OpCode::IFE | OpCode::IFN => 2,
+            OpCode::IFG | OpCode::IFA | OpCode::IFL | OpCode::IFU => 2,
+            OpCode::ADX | OpCode::SBX => 3,
+            OpCode::STI | OpCode::STD => 2,
+        }
+    }
+    /// Is this a conditional operation
+    pub fn is_conditional(&self) -> bool {
+        matches!(*self, OpCode::IFB | OpCode::IFC | OpCode::IFE | OpCode::IFN |
+                        OpCode::IFG | OpCode::IFA | OpCode::IFL | OpCode::IFU)
+    }
+}
+
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Decoded Instruction
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Instruction {
+    /// Operation
+    pub opcode: OpCode,
+    /// Middle Operand of binary instructions
+    pub m: Option<Operand>,
+    /// Upper Operand of unary and binary instructions
+    pub u: Option<Operand>,
+    /// Length in Words
+    pub size: Word,
+}
+
+impl Instruction {
+    /// Decode the instruction starting at address
+    pub fn decode(memory: &Memory, address: Word) -> Instruction {
+        Instruction::fetch(|offset| memory.get(address.wrapping_add(offset)))
+    }
+    /// Decode an instruction from its raw words, reading missing words as 0
+    pub fn from_words(words: &[Word]) -> Instruction {
+        Instruction::fetch(|offset| words.get(offset as usize).cloned().unwrap_or(0))
+    }
+    /// Decode an instruction reading words by offset from its start
+    fn fetch<F: Fn(Word) -> Word>(read: F) -> Instruction {
+        let word = read(0);
+        let mut size = 1;
+        let mut next = || {
+            let value = read(size);
+            size += 1;
+            value
+        };
+        let (opcode, m, u) = if word & 0x03FF == 0 {
+            let opcode = match word >> 10 {
+                0x00 => OpCode::NOP,
+                0x01 => OpCode::CLK,
+                _ => OpCode::ERR,
+            };
+            (opcode, None, None)
+        } else if word & 0x001F == 0 {
+            let opcode = match (word >> 5) & 0x1F {
+                0x01 => OpCode::JSR,
+                0x08 => OpCode::INT,
+                0x09 => OpCode::IAG,
+                0x0A => OpCode::IAS,
+                0x0B => OpCode::RFI,
+                0x0C => OpCode::IAQ,
+                0x10 => OpCode::HWN,
+                0x11 => OpCode::HWQ,
+                0x12 => OpCode::HWI,
+                _ => OpCode::ERR,
+            };
+            if opcode == OpCode::ERR {
+                (opcode, None, None)
+            } else {
+                (opcode, None, Some(operand(word >> 10, true, &mut next)))
+            }
+        } else {
+            let opcode = match word & 0x1F {
+                0x01 => OpCode::SET,
+                0x02 => OpCode::ADD,
+                0x03 => OpCode::SUB,
+                0x04 => OpCode::MUL,
+                0x05 => OpCode::MLI,
+                0x06 => OpCode::DIV,
+                0x07 => OpCode::DVI,
+                0x08 => OpCode::MOD,
+                0x09 => OpCode::MDI,
+                0x0A => OpCode::AND,
+                0x0B => OpCode::BOR,
+                0x0C => OpCode::XOR,
+                0x0D => OpCode::LLS,
+                0x0E => OpCode::LRS,
+                0x0F => OpCode::ARS,
+                0x10 => OpCode::IFB,
+                0x11 => OpCode::IFC,
+                0x12 => OpCode::IFE,
+                0x13 => OpCode::IFN,
+                0x14 => OpCode::IFG,
+                0x15 => OpCode::IFA,
+                0x16 => OpCode::IFL,
+                0x17 => OpCode::IFU,
+                0x1A => OpCode::ADX,
+                0x1B => OpCode::SBX,
+                0x1E => OpCode::STI,
+                0x1F => OpCode::STD,
+                _ => OpCode::ERR,
+            };
+            if opcode == OpCode::ERR {
+                (opcode, None, None)
+            } else {
+                let u = operand(word >> 10, true, &mut next);
+                let m = operand((word >> 5) & 0x1F, false, &mut next);
+                (opcode, Some(m), Some(u))
+            }
+        };
+        Instruction { opcode, m, u, size }
+    }
+    /// Cycles required to execute, excluding skips and hardware
+    pub fn cycles(&self) -> u16 {
+        self.opcode.cycles() + self.size - 1
+    }
+}
+
+/// Decode an upper or middle operand value
+fn operand<F: FnMut() -> Word>(value: Word, upper: bool, next: &mut F) -> Operand {
+    match value {
+        0x00..=0x07 => Operand::Register(Register::general(value)),
+        0x08..=0x0F => Operand::Indirect(Register::general(value)),
+        0x10..=0x17 => Operand::Offset(Register::general(value), next()),
+        0x18 if upper => Operand::Pop,
+        0x18 => Operand::Push,
+        0x19 => Operand::Indirect(Register::SP),
+        0x1A => Operand::Offset(Register::SP, next()),
+        0x1B => Operand::Register(Register::SP),
+        0x1C => Operand::Register(Register::PC),
+        0x1D => Operand::Register(Register::PS),
+        0x1E => Operand::Direct(next()),
+        0x1F => Operand::Literal(next()),
+        _ => Operand::Literal(value.wrapping_sub(0x21)),
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.m, self.u) {
+            (Some(m), Some(u)) => write!(f, "{} {}, {}", self.opcode, m, u),
+            (None, Some(u)) => write!(f, "{} {}", self.opcode, u),
+            _ => write!(f, "{}", self.opcode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Memory;
+    use super::{Instruction, OpCode, Operand, Register};
+
+    #[test]
+    pub fn test_decode() {
+        let mut mem = Memory::new();
+        mem.write(0x0000, &[
+            0x7C01, 0x0030,         // SET A, 0x0030
+            0x7A21, 0x1000, 0x0020, // SET [B + 0x0020], [0x1000]
+            0x6381,                 // SET PC, POP
+            0x8442,                 // ADD C, 0x0000
+            0x0020,                 // JSR A
+            0x0400,                 // CLK
+            0xFC00,                 // ERR
+        ]).unwrap();
+
+        let set = Instruction::decode(&mem, 0x0000);
+        assert_eq!(OpCode::SET, set.opcode);
+        assert_eq!(Some(Operand::Register(Register::A)), set.m);
+        assert_eq!(Some(Operand::Literal(0x0030)), set.u);
+        assert_eq!(2, set.size);
+        assert_eq!(2, set.cycles());
+
+        let offset = Instruction::decode(&mem, 0x0002);
+        assert_eq!("SET [B + 0x0020], [0x1000]", format!("{}", offset));
+        assert_eq!(3, offset.size);
+
+        assert_eq!("SET PC, POP", format!("{}", Instruction::decode(&mem, 0x0005)));
+        assert_eq!("ADD C, 0x0000", format!("{}", Instruction::decode(&mem, 0x0006)));
+        assert_eq!("JSR A", format!("{}", Instruction::decode(&mem, 0x0007)));
+        assert_eq!("CLK", format!("{}", Instruction::decode(&mem, 0x0008)));
+        assert_eq!(OpCode::ERR, Instruction::decode(&mem, 0x0009).opcode);
+        assert_eq!(offset, Instruction::from_words(&[0x7A21, 0x1000, 0x0020]));
+    }
+}
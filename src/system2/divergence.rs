@@ -0,0 +1,423 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::rc::Rc;
+#[cfg(feature = "legacy")]
+use system::cpu::VCPU16;
+#[cfg(feature = "legacy")]
+use super::Instruction;
+use super::Register;
+use super::System;
+use super::SystemError;
+use super::Trace;
+use super::Word;
+
+/// Difference between the left and right Trace of an instruction
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Difference {
+    /// One Trace ended before the other
+    Ended,
+    /// Cycle the instruction executed on
+    Cycle(u64, u64),
+    /// Address of the instruction
+    Pc(Word, Word),
+    /// Raw instruction words
+    Words,
+    /// Interrupt delivered before the instruction
+    Interrupt(Option<Word>, Option<Word>),
+    /// Register value after the instruction, None when unchanged
+    Register(Register, Option<Word>, Option<Word>),
+    /// Memory value written by the instruction, None when not written
+    Write(Word, Option<Word>, Option<Word>),
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = |value: Option<Word>, none: &str| value.map_or(none.to_string(), |value| format!("0x{:04X}", value));
+        match *self {
+            Difference::Ended => write!(f, "trace ended"),
+            Difference::Cycle(left, right) => write!(f, "cycle {} != {}", left, right),
+            Difference::Pc(left, right) => write!(f, "PC 0x{:04X} != 0x{:04X}", left, right),
+            Difference::Words => write!(f, "instruction words differ"),
+            Difference::Interrupt(left, right) => {
+                write!(f, "interrupt {} != {}", value(left, "none"), value(right, "none"))
+            }
+            Difference::Register(register, left, right) => {
+                write!(f, "{} {} != {}", register, value(left, "unchanged"), value(right, "unchanged"))
+            }
+            Difference::Write(address, left, right) => {
+                write!(f, "[0x{:04X}] {} != {}", address, value(left, "unwritten"), value(right, "unwritten"))
+            }
+        }
+    }
+}
+
+/// First instruction at which two Traces disagree
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Divergence {
+    /// Index of the instruction from the start of comparison
+    pub index: u64,
+    /// Matching left Traces immediately before the divergence, oldest first
+    pub context: Vec<Trace>,
+    /// Left Trace at the divergence, None if it had ended
+    pub left: Option<Trace>,
+    /// Right Trace at the divergence, None if it had ended
+    pub right: Option<Trace>,
+    /// Everything that differs
+    pub differences: Vec<Difference>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Diverged at instruction {}", self.index)?;
+        for trace in &self.context {
+            writeln!(f, "  {}", trace)?;
+        }
+        for &(marker, trace) in &[('<', &self.left), ('>', &self.right)] {
+            match *trace {
+                Some(ref trace) => writeln!(f, "{} {}", marker, trace)?,
+                None => writeln!(f, "{} end of trace", marker)?,
+            }
+        }
+        for difference in &self.differences {
+            writeln!(f, "  {}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+/// Finds the first divergence between two Traces or two Systems run in lockstep
+pub struct TraceDiff {
+    context: usize,
+    cycles: bool,
+}
+
+impl TraceDiff {
+    /// Create a TraceDiff keeping 8 instructions of context and ignoring cycle counts
+    pub fn new() -> TraceDiff {
+        TraceDiff {
+            context: 8,
+            cycles: false,
+        }
+    }
+    /// Keep count matching instructions before a divergence
+    pub fn context(mut self, count: usize) -> TraceDiff {
+        self.context = count;
+        self
+    }
+    /// Also compare the cycle each instruction executed on
+    ///
+    /// Off by default, as implementations rarely agree on timing.
+    pub fn cycles(mut self, enabled: bool) -> TraceDiff {
+        self.cycles = enabled;
+        self
+    }
+    /// Everything that differs between the left and right Trace of an instruction
+    pub fn differences(&self, left: &Trace, right: &Trace) -> Vec<Difference> {
+        let mut differences = Vec::new();
+        if self.cycles && left.cycle != right.cycle {
+            differences.push(Difference::Cycle(left.cycle, right.cycle));
+        }
+        if left.pc != right.pc {
+            differences.push(Difference::Pc(left.pc, right.pc));
+        }
+        if left.words != right.words {
+            differences.push(Difference::Words);
+        }
+        if left.interrupt != right.interrupt {
+            differences.push(Difference::Interrupt(left.interrupt, right.interrupt));
+        }
+        let changed = |trace: &Trace, register: Register| {
+            trace.registers.iter().find(|&&(changed, _, _)| changed == register).map(|&(_, _, new)| new)
+        };
+        for &register in Register::ALL.iter() {
+            let (left_value, right_value) = (changed(left, register), changed(right, register));
+            if left_value != right_value {
+                differences.push(Difference::Register(register, left_value, right_value));
+            }
+        }
+        let writes = |trace: &Trace| -> BTreeMap<Word, Word> {
+            trace.writes.iter().map(|&(address, _, new)| (address, new)).collect()
+        };
+        let (left_writes, right_writes) = (writes(left), writes(right));
+        let addresses: BTreeSet<Word> = left_writes.keys().chain(right_writes.keys()).cloned().collect();
+        for address in addresses {
+            let (left_value, right_value) = (left_writes.get(&address).cloned(), right_writes.get(&address).cloned());
+            if left_value != right_value {
+                differences.push(Difference::Write(address, left_value, right_value));
+            }
+        }
+        differences
+    }
+    /// Compare two Traces, returning the first Divergence
+    pub fn compare<L, R>(&self, left: L, right: R) -> Option<Divergence>
+        where L: IntoIterator<Item = Trace>, R: IntoIterator<Item = Trace> {
+        let (mut left, mut right) = (left.into_iter(), right.into_iter());
+        let mut scan = Scan::new(self);
+        loop {
+            match (left.next(), right.next()) {
+                (None, None) => return None,
+                (left, right) => {
+                    if let Some(divergence) = scan.next(left, right) {
+                        return Some(divergence);
+                    }
+                }
+            }
+        }
+    }
+    /// Run two Systems an instruction at a time for at most count instructions, returning the first Divergence
+    ///
+    /// Any Trace sinks already installed are set aside while comparing. Breakpoints do not stop
+    /// the comparison. If either System fails without diverging the error is returned.
+    pub fn lockstep(&self, left: &mut System, right: &mut System, count: u64) -> Result<Option<Divergence>, SystemError> {
+        let (left_traces, right_traces) = (Rc::new(RefCell::new(Vec::<Trace>::new())), Rc::new(RefCell::new(Vec::<Trace>::new())));
+        let (left_sink, right_sink) = (left.stop_trace(), right.stop_trace());
+        left.trace(Box::new(left_traces.clone()));
+        right.trace(Box::new(right_traces.clone()));
+        let mut scan = Scan::new(self);
+        let mut result = Ok(None);
+        for _ in 0..count {
+            let (left_result, right_result) = (left.step_instruction(), right.step_instruction());
+            let (left_trace, right_trace) = (left_traces.borrow_mut().pop(), right_traces.borrow_mut().pop());
+            if let Some(divergence) = scan.next(left_trace, right_trace) {
+                result = Ok(Some(divergence));
+                break;
+            }
+            if let Err(error) = left_result.and(right_result) {
+                result = Err(error);
+                break;
+            }
+        }
+        left.stop_trace();
+        right.stop_trace();
+        if let Some(sink) = left_sink {
+            left.trace(sink);
+        }
+        if let Some(sink) = right_sink {
+            right.trace(sink);
+        }
+        result
+    }
+    /// Run a System against the legacy VCPU16 an instruction at a time for at most count instructions,
+    /// returning the first Divergence with the legacy core on the right
+    ///
+    /// The legacy core has no hardware and never delivers interrupts, and its Traces carry no
+    /// operand values. Load the same program into both before comparing.
+    #[cfg(feature = "legacy")]
+    pub fn legacy(&self, left: &mut System, right: &mut VCPU16, count: u64) -> Result<Option<Divergence>, SystemError> {
+        let traces = Rc::new(RefCell::new(Vec::<Trace>::new()));
+        let sink = left.stop_trace();
+        left.trace(Box::new(traces.clone()));
+        let mut scan = Scan::new(self);
+        let mut result = Ok(None);
+        for _ in 0..count {
+            let left_result = left.step_instruction();
+            let left_trace = traces.borrow_mut().pop();
+            if let Some(divergence) = scan.next(left_trace, Some(legacy_step(right))) {
+                result = Ok(Some(divergence));
+                break;
+            }
+            if let Err(error) = left_result {
+                result = Err(error);
+                break;
+            }
+        }
+        left.stop_trace();
+        if let Some(sink) = sink {
+            left.trace(sink);
+        }
+        result
+    }
+}
+
+/// Step the legacy VCPU16 through one whole instruction, recording it as a Trace
+#[cfg(feature = "legacy")]
+fn legacy_step(cpu: &mut VCPU16) -> Trace {
+    let registers = |cpu: &VCPU16| [
+        cpu.get_a(), cpu.get_b(), cpu.get_c(), cpu.get_x(), cpu.get_y(), cpu.get_z(),
+        cpu.get_i(), cpu.get_j(), cpu.get_pc(), cpu.get_sp(), cpu.get_ps(), cpu.get_ia(),
+    ];
+    let (cycle, pc, before) = (cpu.get_cycles(), cpu.get_pc(), registers(cpu));
+    let memory: Vec<Word> = (0..=0xFFFF).map(|address| cpu.get_mem(address)).collect();
+    let mut words: Vec<Word> = (0..3).map(|offset| cpu.get_mem(pc.wrapping_add(offset))).collect();
+    let instruction = Instruction::from_words(&words);
+    words.truncate(instruction.size as usize);
+    cpu.step();
+    while cpu.is_busy() {
+        cpu.step();
+    }
+    let after = registers(cpu);
+    Trace {
+        cycle,
+        pc,
+        words,
+        instruction,
+        u: None,
+        m: None,
+        registers: Register::ALL.iter().zip(before.iter().zip(after.iter()))
+            .filter(|&(_, (old, new))| old != new)
+            .map(|(&register, (&old, &new))| (register, old, new))
+            .collect(),
+        writes: (0..=0xFFFF).map(|address: Word| (address, memory[address as usize], cpu.get_mem(address)))
+            .filter(|&(_, old, new)| old != new)
+            .collect(),
+        interrupt: None,
+    }
+}
+
+impl Default for TraceDiff {
+    fn default() -> TraceDiff {
+        TraceDiff::new()
+    }
+}
+
+/// Progress through a pair of Traces
+struct Scan<'a> {
+    diff: &'a TraceDiff,
+    index: u64,
+    context: VecDeque<Trace>,
+}
+
+impl<'a> Scan<'a> {
+    /// Start scanning from the first instruction
+    fn new(diff: &'a TraceDiff) -> Scan<'a> {
+        Scan {
+            diff,
+            index: 0,
+            context: VecDeque::new(),
+        }
+    }
+    /// Compare the next pair of Traces, returning a Divergence if they differ
+    fn next(&mut self, left: Option<Trace>, right: Option<Trace>) -> Option<Divergence> {
+        let differences = match (&left, &right) {
+            (Some(left), Some(right)) => self.diff.differences(left, right),
+            (None, None) => Vec::new(),
+            _ => vec![Difference::Ended],
+        };
+        if !differences.is_empty() {
+            return Some(Divergence {
+                index: self.index,
+                context: self.context.drain(..).collect(),
+                left,
+                right,
+                differences,
+            });
+        }
+        if let Some(trace) = left {
+            if self.context.len() == self.diff.context {
+                self.context.pop_front();
+            }
+            if self.diff.context > 0 {
+                self.context.push_back(trace);
+            }
+        }
+        self.index += 1;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use super::super::{Register, System, Trace};
+    use super::{Difference, TraceDiff};
+
+    /// Counting loop storing each value, with a different step on the right
+    fn counter(step: u16) -> System {
+        let mut sys = System::new();
+        sys.memory_mut().write(0x0000, &[
+            0x8401,                 // SET A, 0
+            0x7C02, step,           // ADD A, step (0x0001)
+            0x03C1, 0x1000,         // SET [0x1000], A
+            0x8B81,                 // SET PC, 1
+        ]).unwrap();
+        sys
+    }
+
+    #[test]
+    pub fn test_lockstep() {
+        // Identical Systems never diverge
+        let diff = TraceDiff::new().context(2);
+        assert_eq!(None, diff.lockstep(&mut counter(1), &mut counter(1), 100).unwrap());
+
+        // Literal and register differences are reported on the first ADD
+        let (mut left, mut right) = (counter(1), counter(2));
+        let divergence = diff.lockstep(&mut left, &mut right, 100).unwrap().unwrap();
+        assert_eq!(1, divergence.index);
+        assert_eq!(vec![Difference::Words, Difference::Register(Register::A, Some(0x0001), Some(0x0002))], divergence.differences);
+        assert_eq!(1, divergence.context.len());
+        assert_eq!(0x0003, left.registers().pc);
+    }
+
+    #[cfg(feature = "legacy")]
+    #[test]
+    pub fn test_legacy() {
+        use system::cpu::VCPU16;
+
+        // System agrees with the legacy core on the counting loop
+        let mut cpu = VCPU16::new();
+        cpu.write_mem(0x0000, &[0x8401, 0x7C02, 0x0001, 0x03C1, 0x1000, 0x8B81]);
+        let mut sys = counter(1);
+        assert_eq!(None, TraceDiff::new().legacy(&mut sys, &mut cpu, 100).unwrap());
+        assert_eq!(sys.registers().a, cpu.get_a());
+        assert_eq!(sys.memory().get(0x1000), cpu.get_mem(0x1000));
+
+        // A different program is reported on the first ADD
+        let mut cpu = VCPU16::new();
+        cpu.write_mem(0x0000, &[0x8401, 0x7C02, 0x0002, 0x03C1, 0x1000, 0x8B81]);
+        let divergence = TraceDiff::new().legacy(&mut counter(1), &mut cpu, 100).unwrap().unwrap();
+        assert_eq!(1, divergence.index);
+        assert_eq!(vec![Difference::Words, Difference::Register(Register::A, Some(0x0001), Some(0x0002))], divergence.differences);
+    }
+
+    #[test]
+    pub fn test_compare() {
+        let traces = |step: u16| -> Vec<Trace> {
+            let mut sys = counter(step);
+            let lines = Rc::new(RefCell::new(Vec::<Trace>::new()));
+            sys.trace(Box::new(lines.clone()));
+            for _ in 0..6 {
+                sys.step_instruction().unwrap();
+            }
+            let traces = lines.borrow().iter().map(|trace| trace.to_string().parse().unwrap()).collect();
+            traces
+        };
+        let (left, right) = (traces(0x0001), traces(0x0001));
+        let diff = TraceDiff::new();
+        assert_eq!(None, diff.compare(left.clone(), right.clone()));
+
+        // Memory write and truncated trace
+        let mut changed = right.clone();
+        changed[5].writes[0].2 = 0x0099;
+        let divergence = diff.compare(left.clone(), changed).unwrap();
+        assert_eq!(5, divergence.index);
+        assert_eq!(vec![Difference::Write(0x1000, Some(left[5].writes[0].2), Some(0x0099))], divergence.differences);
+        assert!(divergence.to_string().starts_with("Diverged at instruction 5\n"));
+        let divergence = diff.compare(left.clone(), right[..3].to_vec()).unwrap();
+        assert_eq!((3, None), (divergence.index, divergence.right));
+        assert_eq!(vec![Difference::Ended], divergence.differences);
+
+        // Cycles only when asked
+        let mut shifted = right.clone();
+        shifted[0].cycle += 1;
+        assert_eq!(None, diff.compare(left.clone(), shifted.clone()));
+        assert_eq!(Some(0), TraceDiff::new().cycles(true).compare(left, shifted).map(|divergence| divergence.index));
+    }
+}
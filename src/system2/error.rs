@@ -14,6 +14,8 @@
 // limitations under the License.
 //
 
+use std::fmt;
+
 /// Errors thrown by the System
 #[derive(Clone, Copy, Eq, PartialEq)]
 pub enum SystemError {
@@ -27,28 +29,56 @@ pub enum SystemError {
     InterruptOverflow,
     /// Interrupt Queue has Underflowed
     InterruptUnderflow,
+    /// Instruction could not be Decoded
+    InvalidInstruction,
+    /// Snapshot is Malformed or Unsupported
+    InvalidSnapshot,
+    /// Saved Device State does not match the attached Hardware
+    IncompatibleHardware,
+    /// No recorded History left to step back through
+    HistoryExhausted,
+    /// Expression could not be Parsed
+    InvalidExpression,
+    /// Trace Sink failed to accept a record
+    TraceFailure,
+    /// Trace record could not be Parsed
+    InvalidTrace,
+    /// Recorded Input could not be Parsed
+    InvalidInput,
+    /// Cycle Limit was reached before a Step completed
+    StepLimit,
+}
+
+impl SystemError {
+    /// Name of the Error
+    fn name(&self) -> &'static str {
+        match *self {
+            SystemError::ClockHalted => "SystemError::ClockHalted",
+            SystemError::HardwareFailure => "SystemError::HardwareFailure",
+            SystemError::AddressOverflow => "SystemError::AddressOverflow",
+            SystemError::InterruptOverflow => "SystemError::InterruptOverflow",
+            SystemError::InterruptUnderflow => "SystemError::InterruptUnderflow",
+            SystemError::InvalidInstruction => "SystemError::InvalidInstruction",
+            SystemError::InvalidSnapshot => "SystemError::InvalidSnapshot",
+            SystemError::IncompatibleHardware => "SystemError::IncompatibleHardware",
+            SystemError::HistoryExhausted => "SystemError::HistoryExhausted",
+            SystemError::InvalidExpression => "SystemError::InvalidExpression",
+            SystemError::TraceFailure => "SystemError::TraceFailure",
+            SystemError::InvalidTrace => "SystemError::InvalidTrace",
+            SystemError::InvalidInput => "SystemError::InvalidInput",
+            SystemError::StepLimit => "SystemError::StepLimit",
+        }
+    }
 }
 
 impl fmt::Display for SystemError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "Clock Halted: {} Cycles: {}", match f {
-            &SystemError::ClockHalted => "SystemError::ClockHalted",
-            &SystemError::HardwareFailure => "SystemError::HardwareFailure",
-            &SystemError::AddressOverflow => "SystemError::AddressOverflow",
-            &SystemError::InterruptOverflow => "SystemError::InterruptOverflow",
-            &SystemError::InterruptUnderflow => "SystemError::InterruptUnderflow",
-        })
+        write!(f, "{}", self.name())
     }
 }
 
 impl fmt::Debug for SystemError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "Clock Halted: {} Cycles: {}", match f {
-            &SystemError::ClockHalted => "SystemError::ClockHalted",
-            &SystemError::HardwareFailure => "SystemError::HardwareFailure",
-            &SystemError::AddressOverflow => "SystemError::AddressOverflow",
-            &SystemError::InterruptOverflow => "SystemError::InterruptOverflow",
-            &SystemError::InterruptUnderflow => "SystemError::InterruptUnderflow",
-        })
+        write!(f, "{}", self.name())
     }
 }
@@ -0,0 +1,133 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Floppy Disk Images
+//!
+//! An image holds 1440 sectors of 512 words as little endian bytes, the layout of a 3.5"
+//! 1440 KB floppy formatted in 16 bit mode. Shorter image files are padded with zeros when
+//! opened. Sector writes go through to the image file so the disk persists across runs.
+//! Sectors marked bad are lost to reads and writes but are not recorded in the image.
+
+use std::collections::BTreeSet;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use system2::Word;
+use super::CLOCK_RATE;
+
+/// Words in a sector
+pub const SECTOR_WORDS: usize = 512;
+
+/// Sectors on a disk
+pub const SECTORS: usize = 1440;
+
+/// Sectors on each track
+pub(super) const SECTORS_PER_TRACK: Word = 18;
+
+/// Cycles to move the head one track
+pub(super) const SEEK: u64 = CLOCK_RATE * 24 / 10_000;
+
+/// Cycles to transfer a sector at 30.7 kw/s
+pub(super) const TRANSFER: u64 = SECTOR_WORDS as u64 * CLOCK_RATE / 30_700;
+
+/// Floppy disk, in memory or backed by an image file
+#[derive(Clone, Debug)]
+pub struct Disk {
+    words: Vec<Word>,
+    protected: bool,
+    path: Option<PathBuf>,
+    bad: BTreeSet<Word>,
+}
+
+impl Disk {
+    /// Create a blank disk which is not saved anywhere
+    pub fn blank() -> Disk {
+        Disk { words: vec![0; SECTORS * SECTOR_WORDS], protected: false, path: None, bad: BTreeSet::new() }
+    }
+    /// Open an image file, which must exist and be no larger than a disk
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Disk> {
+        let bytes = fs::read(path.as_ref())?;
+        if bytes.len() > SECTORS * SECTOR_WORDS * 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "image is larger than a disk"));
+        }
+        let mut words: Vec<Word> = bytes.chunks(2).map(|pair| u16::from_le_bytes([pair[0], *pair.get(1).unwrap_or(&0)])).collect();
+        words.resize(SECTORS * SECTOR_WORDS, 0);
+        Ok(Disk { words, protected: false, path: Some(path.as_ref().to_path_buf()), bad: BTreeSet::new() })
+    }
+    /// Create a blank image file, replacing any existing file
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Disk> {
+        let disk = Disk { path: Some(path.as_ref().to_path_buf()), ..Disk::blank() };
+        disk.save(path)?;
+        Ok(disk)
+    }
+    /// Set the write protect tab
+    pub fn protected(mut self, protected: bool) -> Disk {
+        self.protected = protected;
+        self
+    }
+    /// Is the disk write protected
+    pub fn is_protected(&self) -> bool {
+        self.protected
+    }
+    /// Backing image file, if any
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+    /// Mark a sector as broken, losing its data
+    pub fn mark_bad(&mut self, sector: Word) {
+        self.bad.insert(sector);
+    }
+    /// Is a sector broken
+    pub fn is_bad(&self, sector: Word) -> bool {
+        self.bad.contains(&sector)
+    }
+    /// Broken sectors in order
+    pub fn bad_sectors(&self) -> Vec<Word> {
+        self.bad.iter().cloned().collect()
+    }
+    /// Every word on the disk
+    pub fn words(&self) -> &[Word] {
+        &self.words
+    }
+    /// Words of a sector, None past the end of the disk
+    pub fn sector(&self, sector: Word) -> Option<&[Word]> {
+        let start = sector as usize * SECTOR_WORDS;
+        self.words.get(start..start + SECTOR_WORDS)
+    }
+    /// Replace the words of a sector, writing them through to the image file
+    pub fn write_sector(&mut self, sector: Word, data: &[Word]) -> io::Result<()> {
+        let start = sector as usize * SECTOR_WORDS;
+        if data.len() != SECTOR_WORDS || start + SECTOR_WORDS > self.words.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no such sector"));
+        }
+        if let Some(ref path) = self.path {
+            let mut file = OpenOptions::new().write(true).open(path)?;
+            file.seek(SeekFrom::Start(start as u64 * 2))?;
+            file.write_all(&bytes(data))?;
+        }
+        self.words[start..start + SECTOR_WORDS].copy_from_slice(data);
+        Ok(())
+    }
+    /// Write the whole disk to an image file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, bytes(&self.words))
+    }
+}
+
+/// Little endian bytes of words
+fn bytes(words: &[Word]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect()
+}
@@ -0,0 +1,66 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use std::io::{self, Write};
+use super::super::Word;
+
+/// Rendered display image with pixels as 0x00RRGGBB, row by row from the top left
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Framebuffer {
+    /// Width in pixels
+    pub width: usize,
+    /// Height in pixels
+    pub height: usize,
+    /// Pixel colors
+    pub pixels: Vec<u32>,
+}
+
+impl Framebuffer {
+    /// Create a Framebuffer filled with color
+    pub fn new(width: usize, height: usize, color: u32) -> Framebuffer {
+        Framebuffer { width, height, pixels: vec![color; width * height] }
+    }
+    /// Color of the pixel at x, y
+    pub fn get(&self, x: usize, y: usize) -> u32 {
+        self.pixels[y * self.width + x]
+    }
+    /// Set the pixel at x, y to color
+    pub fn set(&mut self, x: usize, y: usize, color: u32) {
+        self.pixels[y * self.width + x] = color;
+    }
+    /// Fill a rectangle with color, clipped to the Framebuffer
+    pub fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: u32) {
+        for row in y..(y + height).min(self.height) {
+            for column in x..(x + width).min(self.width) {
+                self.set(column, row, color);
+            }
+        }
+    }
+    /// Write as a binary PPM image
+    pub fn write_ppm(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+        let bytes: Vec<u8> = self.pixels.iter()
+            .flat_map(|&pixel| vec![(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8])
+            .collect();
+        writer.write_all(&bytes)
+    }
+}
+
+/// Expand a 0000rrrrggggbbbb palette entry to 0x00RRGGBB
+pub fn rgb(color: Word) -> u32 {
+    let channel = |shift: u16| ((color >> shift) & 0xF) as u32 * 0x11;
+    (channel(8) << 16) | (channel(4) << 8) | channel(0)
+}
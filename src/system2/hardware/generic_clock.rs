@@ -0,0 +1,180 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Generic Clock
+//!
+//! Ticks 60/B times per emulated second, counting from the cycle B was set so that ticks land
+//! on the same cycles in every run without drifting. When the Clock runs backwards, as when
+//! History rewinds the System, the count restarts from the rewound cycle.
+
+use system2::{Clock, Memory, Queue, Registers, SystemError, Word};
+use super::{Hardware, CLOCK_RATE};
+
+/// Generic Clock
+#[derive(Clone, Debug, Default)]
+pub struct GenericClock {
+    /// Sixtieths of a second between ticks, 0 when off
+    rate: Word,
+    message: Word,
+    /// Cycle the rate was set
+    start: u64,
+    /// Ticks since the rate was set
+    ticks: u64,
+}
+
+impl GenericClock {
+    /// Create a Generic Clock which is turned off
+    pub fn new() -> GenericClock {
+        GenericClock::default()
+    }
+    /// Sixtieths of a second between ticks, 0 when off
+    pub fn rate(&self) -> Word {
+        self.rate
+    }
+    /// Ticks since the rate was last set
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+}
+
+impl Hardware for GenericClock {
+    fn mfg_id(&self) -> u32 {
+        // The specification names no manufacturer
+        0x0000_0000
+    }
+    fn hdw_id(&self) -> u32 {
+        0x12D0_B402
+    }
+    fn dev_id(&self) -> Word {
+        1
+    }
+    fn interrupt(&mut self, clock: &Clock, registers: &mut Registers, _: &mut Memory, _: &mut Queue) -> Result<u16, SystemError> {
+        match registers.a {
+            0 => {
+                self.rate = registers.b;
+                self.start = clock.cycles();
+                self.ticks = 0;
+            }
+            1 => registers.c = self.ticks as Word,
+            2 => self.message = registers.b,
+            _ => {}
+        }
+        Ok(0)
+    }
+    fn update(&mut self, clock: &Clock, _: &mut Registers, _: &mut Memory, queue: &mut Queue) -> Result<(), SystemError> {
+        if self.rate == 0 {
+            return Ok(());
+        }
+        if clock.cycles() < self.start {
+            self.start = clock.cycles();
+            self.ticks = 0;
+        }
+        // Wide enough for the Clock to have been fast-forwarded to its limit
+        let ticks = (clock.cycles().saturating_sub(self.start) as u128 * 60 / (CLOCK_RATE * self.rate as u64) as u128) as u64;
+        if ticks > self.ticks {
+            self.ticks = ticks;
+            if self.message != 0 {
+                queue.enqueue(self.message)?;
+            }
+        }
+        Ok(())
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(20);
+        data.extend_from_slice(&self.rate.to_le_bytes());
+        data.extend_from_slice(&self.message.to_le_bytes());
+        data.extend_from_slice(&self.start.to_le_bytes());
+        data.extend_from_slice(&self.ticks.to_le_bytes());
+        data
+    }
+    fn restore_state(&mut self, _: Word, data: &[u8]) -> Result<(), SystemError> {
+        if data.len() != 20 {
+            return Err(SystemError::IncompatibleHardware);
+        }
+        let long = |index: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&data[index..index + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        self.rate = u16::from_le_bytes([data[0], data[1]]);
+        self.message = u16::from_le_bytes([data[2], data[3]]);
+        self.start = long(4);
+        self.ticks = long(12);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use system2::{Clock, Hardware, Memory, Queue, Registers, System};
+    use super::super::CLOCK_RATE;
+    use super::GenericClock;
+
+    #[test]
+    pub fn test_generic_clock() {
+        let device = Rc::new(RefCell::new(GenericClock::new()));
+        let mut sys = System::new();
+        sys.attach(Box::new(device.clone()));
+        sys.memory_mut().write(0x0000, &[
+            0x7D40, 0x0100,         // IAS 0x0100
+            0x8401,                 // SET A, 0
+            0x8C21,                 // SET B, 2
+            0x8640,                 // HWI 0
+            0x8C01,                 // SET A, 2
+            0x7C21, 0x0042,         // SET B, 0x0042
+            0x8640,                 // HWI 0
+            0xAB81,                 // SET PC, 0x0009
+        ]).unwrap();
+        sys.memory_mut().write(0x0100, &[
+            0x88C2,                 // ADD I, 1
+            0x8560,                 // RFI 0
+        ]).unwrap();
+
+        // 30 ticks a second, each interrupting
+        while sys.clock().cycles() < CLOCK_RATE + 10 {
+            sys.step().unwrap();
+        }
+        assert_eq!(30, device.borrow().ticks());
+        assert_eq!(30, sys.registers().i);
+
+        // Ticks since the rate was set are read into C
+        let mut registers = Registers::new();
+        registers.a = 1;
+        device.borrow_mut().interrupt(sys.clock(), &mut registers, &mut Memory::new(), &mut Queue::new()).unwrap();
+        assert_eq!(30, registers.c);
+
+        // Turning the clock off stops ticks
+        registers.a = 0;
+        registers.b = 0;
+        device.borrow_mut().interrupt(sys.clock(), &mut registers, &mut Memory::new(), &mut Queue::new()).unwrap();
+        for _ in 0..CLOCK_RATE / 10 {
+            sys.step().unwrap();
+        }
+        assert_eq!((0, 30), (device.borrow().ticks(), sys.registers().i));
+
+        // A Clock behind the start restarts the count instead of underflowing
+        let mut clock = Clock::new();
+        let mut device = GenericClock::new();
+        clock.advance(CLOCK_RATE);
+        registers.a = 0;
+        registers.b = 60;
+        device.interrupt(&clock, &mut registers, &mut Memory::new(), &mut Queue::new()).unwrap();
+        device.update(&Clock::new(), &mut registers, &mut Memory::new(), &mut Queue::new()).unwrap();
+        assert_eq!((0, 0), (device.start, device.ticks()));
+    }
+}
@@ -0,0 +1,232 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Generic Keyboard
+//!
+//! Key events come from an InputSource polled every cycle, or are sent by the host. Typed
+//! keys wait in a buffer of up to 64 keys, dropping keys typed while it is full. Snapshots
+//! save the buffer and pressed keys but not the InputSource.
+
+use std::collections::VecDeque;
+use system2::{Clock, Memory, Queue, Registers, SystemError, Word};
+use super::{Hardware, InputSource, KeyEvent};
+
+/// Most keys held in the typed key buffer
+const BUFFER: usize = 64;
+
+/// Generic Keyboard
+#[derive(Default)]
+pub struct GenericKeyboard {
+    source: Option<Box<dyn InputSource>>,
+    buffer: VecDeque<Word>,
+    pressed: Vec<Word>,
+    message: Word,
+    /// Events since the last update
+    changed: bool,
+}
+
+impl GenericKeyboard {
+    /// Backspace key
+    pub const KEY_BACKSPACE: Word = 0x10;
+    /// Return key
+    pub const KEY_RETURN: Word = 0x11;
+    /// Insert key
+    pub const KEY_INSERT: Word = 0x12;
+    /// Delete key
+    pub const KEY_DELETE: Word = 0x13;
+    /// Arrow up key
+    pub const KEY_UP: Word = 0x80;
+    /// Arrow down key
+    pub const KEY_DOWN: Word = 0x81;
+    /// Arrow left key
+    pub const KEY_LEFT: Word = 0x82;
+    /// Arrow right key
+    pub const KEY_RIGHT: Word = 0x83;
+    /// Shift key
+    pub const KEY_SHIFT: Word = 0x90;
+    /// Control key
+    pub const KEY_CONTROL: Word = 0x91;
+
+    /// Create a Generic Keyboard with no InputSource
+    pub fn new() -> GenericKeyboard {
+        GenericKeyboard::default()
+    }
+    /// Take key events from source
+    pub fn source(mut self, source: Box<dyn InputSource>) -> GenericKeyboard {
+        self.source = Some(source);
+        self
+    }
+    /// Apply a key event from the host
+    pub fn key(&mut self, event: KeyEvent) {
+        match event {
+            KeyEvent::Press(key) => {
+                if !self.pressed.contains(&key) {
+                    self.pressed.push(key);
+                }
+            }
+            KeyEvent::Release(key) => self.pressed.retain(|&pressed| pressed != key),
+            KeyEvent::Type(key) => {
+                if self.buffer.len() < BUFFER {
+                    self.buffer.push_back(key);
+                }
+            }
+        }
+        self.changed = true;
+    }
+    /// Typed keys waiting to be read, oldest first
+    pub fn buffer(&self) -> impl Iterator<Item = &Word> {
+        self.buffer.iter()
+    }
+    /// Is key held down
+    pub fn is_pressed(&self, key: Word) -> bool {
+        self.pressed.contains(&key)
+    }
+}
+
+impl Hardware for GenericKeyboard {
+    fn mfg_id(&self) -> u32 {
+        // The specification names no manufacturer
+        0x0000_0000
+    }
+    fn hdw_id(&self) -> u32 {
+        0x30CF_7406
+    }
+    fn dev_id(&self) -> Word {
+        1
+    }
+    fn interrupt(&mut self, _: &Clock, registers: &mut Registers, _: &mut Memory, _: &mut Queue) -> Result<u16, SystemError> {
+        match registers.a {
+            0 => self.buffer.clear(),
+            1 => registers.c = self.buffer.pop_front().unwrap_or(0),
+            2 => registers.c = self.is_pressed(registers.b) as Word,
+            3 => self.message = registers.b,
+            _ => {}
+        }
+        Ok(0)
+    }
+    fn update(&mut self, clock: &Clock, _: &mut Registers, _: &mut Memory, queue: &mut Queue) -> Result<(), SystemError> {
+        let events = match self.source.as_mut() {
+            Some(source) => source.poll(clock.cycles()),
+            None => Vec::new(),
+        };
+        for event in events {
+            self.key(event);
+        }
+        if self.changed {
+            self.changed = false;
+            if self.message != 0 {
+                queue.enqueue(self.message)?;
+            }
+        }
+        Ok(())
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&self.message.to_le_bytes());
+        data.push(self.changed as u8);
+        for keys in &[self.buffer.iter().cloned().collect::<Vec<Word>>(), self.pressed.clone()] {
+            data.push(keys.len() as u8);
+            for key in keys {
+                data.extend_from_slice(&key.to_le_bytes());
+            }
+        }
+        data
+    }
+    fn restore_state(&mut self, _: Word, data: &[u8]) -> Result<(), SystemError> {
+        let invalid = || SystemError::IncompatibleHardware;
+        let (header, rest) = (data.get(..3).ok_or_else(invalid)?, &data[3..]);
+        let buffered = *rest.first().ok_or_else(invalid)? as usize;
+        let buffer = rest.get(1..1 + buffered * 2).ok_or_else(invalid)?;
+        let rest = &rest[1 + buffered * 2..];
+        let held = *rest.first().ok_or_else(invalid)? as usize;
+        let pressed = rest.get(1..1 + held * 2).ok_or_else(invalid)?;
+        let words = |bytes: &[u8]| bytes.chunks(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect::<Vec<Word>>();
+        self.message = u16::from_le_bytes([header[0], header[1]]);
+        self.changed = header[2] != 0;
+        self.buffer = words(buffer).into_iter().collect();
+        self.pressed = words(pressed);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use system2::{Clock, Hardware, Memory, Queue, Registers, Word};
+    use std::sync::mpsc;
+    use super::super::{InputSource, KeyEvent, ScriptedInput, TerminalInput};
+    use super::GenericKeyboard;
+
+    /// Send a command, returning C
+    fn command(keyboard: &mut GenericKeyboard, a: Word, b: Word) -> Word {
+        let mut registers = Registers::new();
+        registers.a = a;
+        registers.b = b;
+        keyboard.interrupt(&Clock::new(), &mut registers, &mut Memory::new(), &mut Queue::new()).unwrap();
+        registers.c
+    }
+
+    #[test]
+    pub fn test_generic_keyboard() {
+        let script: ScriptedInput = "# shifted hi\n5 press 0x90\n10 type 0x68\n11 type 0x69\n20 release 0x90\n".parse().unwrap();
+        assert_eq!(script, ScriptedInput::new().event(20, KeyEvent::Release(0x90)).event(5, KeyEvent::Press(0x90)).text(10, "hi"));
+        assert_eq!("5 press 0x90\n10 type 0x68\n11 type 0x69\n20 release 0x90\n", script.to_string());
+        assert!("5 hold 0x90".parse::<ScriptedInput>().is_err());
+
+        let mut keyboard = GenericKeyboard::new().source(Box::new(script));
+        let (mut clock, mut registers, mut memory, mut queue) = (Clock::new(), Registers::new(), Memory::new(), Queue::new());
+        command(&mut keyboard, 3, 0x0099);
+
+        // One interrupt per cycle with events
+        let mut interrupts = Vec::new();
+        while clock.cycles() < 30 {
+            clock.step().unwrap();
+            keyboard.update(&clock, &mut registers, &mut memory, &mut queue).unwrap();
+            if queue.dequeue().is_ok() {
+                interrupts.push(clock.cycles());
+            }
+            if clock.cycles() == 15 {
+                assert_eq!(1, command(&mut keyboard, 2, GenericKeyboard::KEY_SHIFT));
+            }
+        }
+        assert_eq!(vec![5, 10, 11, 20], interrupts);
+        assert_eq!(0, command(&mut keyboard, 2, GenericKeyboard::KEY_SHIFT));
+
+        // Typed keys are read in order, then cleared
+        assert_eq!(0x68, command(&mut keyboard, 1, 0));
+        keyboard.key(KeyEvent::Type(GenericKeyboard::KEY_RETURN));
+        let copy = keyboard.save_state();
+        assert_eq!(0x69, command(&mut keyboard, 1, 0));
+        command(&mut keyboard, 0, 0);
+        assert_eq!(0, command(&mut keyboard, 1, 0));
+
+        // Buffer survives saving
+        keyboard.restore_state(1, &copy).unwrap();
+        assert_eq!(vec![0x69, GenericKeyboard::KEY_RETURN], keyboard.buffer().cloned().collect::<Vec<Word>>());
+
+        // Terminal bytes and arrow escapes become typed keys
+        let (sender, receiver) = mpsc::channel();
+        let mut terminal = TerminalInput::new(receiver);
+        for &byte in b"a\x1B[A\x7F\n" {
+            sender.send(byte).unwrap();
+        }
+        assert_eq!(vec![
+            KeyEvent::Type(0x61),
+            KeyEvent::Type(GenericKeyboard::KEY_UP),
+            KeyEvent::Type(GenericKeyboard::KEY_BACKSPACE),
+            KeyEvent::Type(GenericKeyboard::KEY_RETURN),
+        ], terminal.poll(0));
+    }
+}
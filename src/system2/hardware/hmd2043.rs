@@ -0,0 +1,371 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Harold Media Drive HMD2043
+//!
+//! The community floppy drive which predates the M35FD, reading and writing runs of sectors
+//! of HMU1440 media, 1440 sectors of 512 words, from the same disk images. Transfers take
+//! the M35FD's seek and transfer times, which the HMD2043 specification leaves open.
+//! Blocking transfers complete within the HWI and halt the DCPU-16 for their duration, the
+//! Clock skipping any part beyond 65535 cycles. Non-blocking transfers complete later with a READ_COMPLETE or
+//! WRITE_COMPLETE interrupt. Bad sectors and writes to write locked media, which the
+//! specification does not cover, fail with ERROR_INVALID_SECTOR and ERROR_WRITE_LOCKED.
+
+use std::mem;
+use system2::{Clock, Memory, Queue, Registers, SystemError, Word};
+use super::{Disk, Hardware, SECTORS, SECTOR_WORDS};
+use super::disk::{SECTORS_PER_TRACK, SEEK, TRANSFER};
+
+/// Non-blocking read or write in progress
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Transfer {
+    write: bool,
+    sector: Word,
+    count: Word,
+    address: Word,
+    done: u64,
+}
+
+/// HMD2043 Floppy Drive
+#[derive(Clone, Debug, Default)]
+pub struct Hmd2043 {
+    disk: Option<Disk>,
+    flags: Word,
+    message: Word,
+    /// Type of the last interrupt raised
+    last: Word,
+    /// Error of the last completed non-blocking transfer
+    error: Word,
+    track: Word,
+    transfer: Option<Transfer>,
+    /// Media presence last reported by interrupt
+    present: bool,
+    /// Cycles of a blocking transfer beyond what an HWI can halt for
+    skip: u64,
+}
+
+impl Hmd2043 {
+    /// Operations return immediately and interrupt on completion
+    pub const NON_BLOCKING: Word = 0x0001;
+    /// Interrupt when media is inserted or ejected
+    pub const MEDIA_STATUS_INTERRUPT: Word = 0x0002;
+    /// No interrupt has been raised
+    pub const TYPE_NONE: Word = 0x0000;
+    /// Media was inserted or ejected
+    pub const TYPE_MEDIA_STATUS: Word = 0x0001;
+    /// A non-blocking read completed
+    pub const TYPE_READ_COMPLETE: Word = 0x0002;
+    /// A non-blocking write completed
+    pub const TYPE_WRITE_COMPLETE: Word = 0x0003;
+    /// Operation succeeded
+    pub const ERROR_NONE: Word = 0x0000;
+    /// No media is inserted
+    pub const ERROR_NO_MEDIA: Word = 0x0001;
+    /// Sectors are past the end of the media or broken
+    pub const ERROR_INVALID_SECTOR: Word = 0x0002;
+    /// A non-blocking operation is in progress
+    pub const ERROR_PENDING: Word = 0x0003;
+    /// Media is write locked
+    pub const ERROR_WRITE_LOCKED: Word = 0x0004;
+    /// Media quality of authentic HMU1440 disks
+    pub const QUALITY_AUTHENTIC: Word = 0x7FFF;
+
+    /// Create an empty HMD2043
+    pub fn new() -> Hmd2043 {
+        Hmd2043::default()
+    }
+    /// Device flags
+    pub fn flags(&self) -> Word {
+        self.flags
+    }
+    /// Inserted disk
+    pub fn disk(&self) -> Option<&Disk> {
+        self.disk.as_ref()
+    }
+    /// Is a non-blocking transfer in progress
+    pub fn is_busy(&self) -> bool {
+        self.transfer.is_some()
+    }
+    /// Insert a disk, returning any disk ejected to make room
+    pub fn insert(&mut self, disk: Disk) -> Option<Disk> {
+        let ejected = self.eject();
+        self.disk = Some(disk);
+        ejected
+    }
+    /// Eject the disk, abandoning any transfer in progress
+    pub fn eject(&mut self) -> Option<Disk> {
+        self.transfer = None;
+        self.disk.take()
+    }
+    /// Cycles to transfer count sectors from sector, seeking from the current track
+    fn duration(&self, sector: Word, count: Word) -> u64 {
+        let first = sector / SECTORS_PER_TRACK;
+        let last = (sector + count.max(1) - 1) / SECTORS_PER_TRACK;
+        (first.abs_diff(self.track) + last - first) as u64 * SEEK + count as u64 * TRANSFER
+    }
+    /// Check a transfer of count sectors from sector can start, returning the error if not
+    fn check(&self, write: bool, sector: Word, count: Word) -> Word {
+        match self.disk.as_ref() {
+            None => Hmd2043::ERROR_NO_MEDIA,
+            Some(_) if self.transfer.is_some() => Hmd2043::ERROR_PENDING,
+            Some(_) if sector as usize + count as usize > SECTORS => Hmd2043::ERROR_INVALID_SECTOR,
+            Some(disk) if write && disk.is_protected() => Hmd2043::ERROR_WRITE_LOCKED,
+            Some(_) => Hmd2043::ERROR_NONE,
+        }
+    }
+    /// Copy the sectors of a transfer, returning its error
+    fn complete(&mut self, transfer: Transfer, memory: &mut Memory) -> Word {
+        self.track = (transfer.sector + transfer.count.max(1) - 1) / SECTORS_PER_TRACK;
+        let disk = match self.disk.as_mut() {
+            Some(disk) => disk,
+            None => return Hmd2043::ERROR_NO_MEDIA,
+        };
+        for index in 0..transfer.count {
+            let sector = transfer.sector + index;
+            let address = transfer.address.wrapping_add(index.wrapping_mul(SECTOR_WORDS as Word));
+            if disk.is_bad(sector) {
+                return Hmd2043::ERROR_INVALID_SECTOR;
+            }
+            if transfer.write {
+                let data: Vec<Word> = (0..SECTOR_WORDS as Word).map(|offset| memory.get(address.wrapping_add(offset))).collect();
+                if disk.write_sector(sector, &data).is_err() {
+                    return Hmd2043::ERROR_INVALID_SECTOR;
+                }
+            } else if let Some(data) = disk.sector(sector) {
+                for (offset, &word) in data.iter().enumerate() {
+                    memory.set(address.wrapping_add(offset as Word), word);
+                }
+            }
+        }
+        Hmd2043::ERROR_NONE
+    }
+    /// Start or perform a transfer, returning the error and cycles to halt
+    fn start(&mut self, clock: &Clock, memory: &mut Memory, write: bool, sector: Word, count: Word, address: Word) -> (Word, u16) {
+        let error = self.check(write, sector, count);
+        if error != Hmd2043::ERROR_NONE {
+            return (error, 0);
+        }
+        let duration = self.duration(sector, count);
+        let transfer = Transfer { write, sector, count, address, done: clock.cycles().saturating_add(duration) };
+        if self.flags & Hmd2043::NON_BLOCKING != 0 {
+            self.transfer = Some(transfer);
+            (Hmd2043::ERROR_NONE, 0)
+        } else {
+            let halt = duration.min(u16::MAX as u64);
+            self.skip += duration - halt;
+            (self.complete(transfer, memory), halt as u16)
+        }
+    }
+    /// Raise an interrupt of a type if enabled
+    fn raise(&mut self, queue: &mut Queue, kind: Word) -> Result<(), SystemError> {
+        if self.message != 0 {
+            self.last = kind;
+            queue.enqueue(self.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl Hardware for Hmd2043 {
+    fn mfg_id(&self) -> u32 {
+        0x2154_4948
+    }
+    fn hdw_id(&self) -> u32 {
+        0x74FA_4CAE
+    }
+    fn dev_id(&self) -> Word {
+        0x07C2
+    }
+    fn interrupt(&mut self, clock: &Clock, registers: &mut Registers, memory: &mut Memory, _: &mut Queue) -> Result<u16, SystemError> {
+        let (error, cycles) = match registers.a {
+            0x0000 => {
+                registers.b = self.disk.is_some() as Word;
+                (Hmd2043::ERROR_NONE, 0)
+            }
+            0x0001 => match self.disk.as_ref() {
+                Some(disk) => {
+                    registers.b = SECTOR_WORDS as Word;
+                    registers.c = SECTORS as Word;
+                    registers.x = disk.is_protected() as Word;
+                    (Hmd2043::ERROR_NONE, 0)
+                }
+                None => (Hmd2043::ERROR_NO_MEDIA, 0),
+            },
+            0x0002 => {
+                registers.b = self.flags;
+                (Hmd2043::ERROR_NONE, 0)
+            }
+            0x0003 => {
+                self.flags = registers.b;
+                (Hmd2043::ERROR_NONE, 0)
+            }
+            0x0004 => {
+                registers.b = self.last;
+                (self.error, 0)
+            }
+            0x0005 => {
+                self.message = registers.b;
+                (Hmd2043::ERROR_NONE, 0)
+            }
+            0x0010 => self.start(clock, memory, false, registers.b, registers.c, registers.x),
+            0x0011 => self.start(clock, memory, true, registers.b, registers.c, registers.x),
+            0xFFFF => match self.disk {
+                Some(_) => {
+                    registers.b = Hmd2043::QUALITY_AUTHENTIC;
+                    (Hmd2043::ERROR_NONE, 0)
+                }
+                None => (Hmd2043::ERROR_NO_MEDIA, 0),
+            },
+            _ => return Ok(0),
+        };
+        registers.a = error;
+        Ok(cycles)
+    }
+    fn take_skip(&mut self) -> u64 {
+        mem::replace(&mut self.skip, 0)
+    }
+    fn update(&mut self, clock: &Clock, _: &mut Registers, memory: &mut Memory, queue: &mut Queue) -> Result<(), SystemError> {
+        if let Some(transfer) = self.transfer {
+            if clock.cycles() >= transfer.done {
+                self.transfer = None;
+                self.error = self.complete(transfer, memory);
+                let kind = if transfer.write { Hmd2043::TYPE_WRITE_COMPLETE } else { Hmd2043::TYPE_READ_COMPLETE };
+                self.raise(queue, kind)?;
+            }
+        }
+        if self.disk.is_some() != self.present {
+            self.present = self.disk.is_some();
+            if self.flags & Hmd2043::MEDIA_STATUS_INTERRUPT != 0 {
+                self.raise(queue, Hmd2043::TYPE_MEDIA_STATUS)?;
+            }
+        }
+        Ok(())
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let transfer = self.transfer.unwrap_or(Transfer { write: false, sector: 0, count: 0, address: 0, done: 0 });
+        let mut data = Vec::with_capacity(25);
+        for word in &[self.flags, self.message, self.last, self.error, self.track, transfer.sector, transfer.count, transfer.address] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data.extend_from_slice(&transfer.done.to_le_bytes());
+        data.push(self.transfer.is_some() as u8 | (transfer.write as u8) << 1 | (self.present as u8) << 2);
+        data
+    }
+    fn restore_state(&mut self, _: Word, data: &[u8]) -> Result<(), SystemError> {
+        if data.len() != 25 {
+            return Err(SystemError::IncompatibleHardware);
+        }
+        let word = |index: usize| u16::from_le_bytes([data[index], data[index + 1]]);
+        let mut done = [0; 8];
+        done.copy_from_slice(&data[16..24]);
+        self.flags = word(0);
+        self.message = word(2);
+        self.last = word(4);
+        self.error = word(6);
+        self.track = word(8);
+        self.present = data[24] & 4 != 0;
+        self.transfer = match data[24] & 1 {
+            0 => None,
+            _ => Some(Transfer { write: data[24] & 2 != 0, sector: word(10), count: word(12), address: word(14), done: u64::from_le_bytes(done) }),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use system2::{Clock, Hardware, Memory, Queue, Registers, System, Word};
+    use super::super::Disk;
+    use super::{Hmd2043, SEEK, TRANSFER};
+
+    #[test]
+    pub fn test_hmd2043() {
+        let mut drive = Hmd2043::new();
+        let (mut clock, mut memory, mut queue) = (Clock::new(), Memory::new(), Queue::new());
+        let mut registers = Registers::new();
+        let mut command = |drive: &mut Hmd2043, clock: &Clock, memory: &mut Memory, a: Word, b: Word, c: Word, x: Word| {
+            registers.a = a;
+            registers.b = b;
+            registers.c = c;
+            registers.x = x;
+            let cycles = drive.interrupt(clock, &mut registers, memory, &mut Queue::new()).unwrap();
+            (registers.a, registers.b, registers.c, registers.x, cycles)
+        };
+
+        // No media
+        assert_eq!(Hmd2043::ERROR_NO_MEDIA, command(&mut drive, &clock, &mut memory, 0x0010, 0, 1, 0x1000).0);
+        assert_eq!(0, command(&mut drive, &clock, &mut memory, 0x0000, 0, 0, 0).1);
+
+        // Media status interrupts on insert
+        command(&mut drive, &clock, &mut memory, 0x0005, 0x77, 0, 0);
+        command(&mut drive, &clock, &mut memory, 0x0003, Hmd2043::MEDIA_STATUS_INTERRUPT, 0, 0);
+        let mut disk = Disk::blank();
+        disk.write_sector(19, &[0xABCD; 512]).unwrap();
+        drive.insert(disk.protected(true));
+        drive.update(&clock, &mut Registers::new(), &mut memory, &mut queue).unwrap();
+        assert_eq!(Ok(0x77), queue.dequeue());
+        assert_eq!(Hmd2043::TYPE_MEDIA_STATUS, command(&mut drive, &clock, &mut memory, 0x0004, 0, 0, 0).1);
+        assert_eq!((Hmd2043::ERROR_NONE, 512, 1440, 1, 0), command(&mut drive, &clock, &mut memory, 0x0001, 0, 0, 0));
+        assert_eq!(Hmd2043::QUALITY_AUTHENTIC, command(&mut drive, &clock, &mut memory, 0xFFFF, 0, 0, 0).1);
+
+        // Blocking read of two sectors halts for the seek and transfer
+        let (error, _, _, _, cycles) = command(&mut drive, &clock, &mut memory, 0x0010, 18, 2, 0x1000);
+        assert_eq!((Hmd2043::ERROR_NONE, 240 + 2 * TRANSFER as u16), (error, cycles));
+        assert_eq!((0x0000, 0xABCD), (memory.get(0x11FF), memory.get(0x1200)));
+        assert_eq!(Hmd2043::ERROR_INVALID_SECTOR, command(&mut drive, &clock, &mut memory, 0x0010, 1439, 2, 0x1000).0);
+        assert_eq!(Hmd2043::ERROR_WRITE_LOCKED, command(&mut drive, &clock, &mut memory, 0x0011, 0, 1, 0x1000).0);
+
+        // Non-blocking write interrupts on completion
+        drive.insert(drive.disk().cloned().unwrap().protected(false));
+        command(&mut drive, &clock, &mut memory, 0x0003, Hmd2043::NON_BLOCKING, 0, 0);
+        assert_eq!(Hmd2043::ERROR_NONE, command(&mut drive, &clock, &mut memory, 0x0011, 19, 1, 0x1000).0);
+        assert_eq!(Hmd2043::ERROR_PENDING, command(&mut drive, &clock, &mut memory, 0x0010, 0, 1, 0x1000).0);
+        clock.advance(TRANSFER);
+        drive.update(&clock, &mut Registers::new(), &mut memory, &mut queue).unwrap();
+        assert_eq!(Ok(0x77), queue.dequeue());
+        assert!(!drive.is_busy());
+        let (error, kind, _, _, _) = command(&mut drive, &clock, &mut memory, 0x0004, 0, 0, 0);
+        assert_eq!((Hmd2043::ERROR_NONE, Hmd2043::TYPE_WRITE_COMPLETE), (error, kind));
+        assert_eq!(0, drive.disk().unwrap().sector(19).unwrap()[0]);
+    }
+
+    #[test]
+    pub fn test_long_read() {
+        let mut disk = Disk::blank();
+        disk.write_sector(1439, &[0xABCD; 512]).unwrap();
+        let mut drive = Hmd2043::new();
+        drive.insert(disk);
+        let mut sys = System::new();
+        sys.attach(Box::new(drive));
+        sys.memory_mut().write(0x0000, &[
+            0x7C01, 0x0010,         // SET A, 0x0010
+            0x8421,                 // SET B, 0
+            0x7C41, 0x05A0,         // SET C, 1440
+            0x7C61, 0x1000,         // SET X, 0x1000
+            0x8640,                 // HWI 0
+        ]).unwrap();
+
+        // Reading the whole disk takes far longer than an HWI can halt for
+        for _ in 0..5 {
+            sys.step_instruction().unwrap();
+        }
+        let duration = 79 * SEEK + 1440 * TRANSFER;
+        assert!(duration > u16::MAX as u64);
+        assert_eq!(7 + duration, sys.clock().cycles());
+        assert_eq!((Hmd2043::ERROR_NONE, 0x0008), (sys.registers().a, sys.registers().pc));
+        assert_eq!(0xABCD, sys.memory().get(0x4E00));
+    }
+}
@@ -0,0 +1,200 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Keyboard Input Sources
+//!
+//! Recorded input is text, one event per line as `cycle press|release|type key` with the key
+//! number in hex, such as `1200 type 0x41`. Blank lines and lines starting with `#` are
+//! ignored.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use system2::{SystemError, Word};
+use super::GenericKeyboard;
+
+/// Change to the state of a key
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeyEvent {
+    /// Key went down
+    Press(Word),
+    /// Key came up
+    Release(Word),
+    /// Key was typed, adding it to the keyboard buffer
+    Type(Word),
+}
+
+impl fmt::Display for KeyEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeyEvent::Press(key) => write!(f, "press 0x{:02X}", key),
+            KeyEvent::Release(key) => write!(f, "release 0x{:02X}", key),
+            KeyEvent::Type(key) => write!(f, "type 0x{:02X}", key),
+        }
+    }
+}
+
+/// Source of key events for a keyboard
+pub trait InputSource {
+    /// Events which have happened by cycle, in order
+    fn poll(&mut self, cycle: u64) -> Vec<KeyEvent>;
+}
+
+/// Key events at fixed cycles, from a test script or a recorded input file
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ScriptedInput {
+    events: VecDeque<(u64, KeyEvent)>,
+}
+
+impl ScriptedInput {
+    /// Create an empty script
+    pub fn new() -> ScriptedInput {
+        ScriptedInput::default()
+    }
+    /// Add an event at cycle
+    pub fn event(mut self, cycle: u64, event: KeyEvent) -> ScriptedInput {
+        let index = self.events.iter().take_while(|&&(at, _)| at <= cycle).count();
+        self.events.insert(index, (cycle, event));
+        self
+    }
+    /// Type text one key per cycle from cycle, with newlines as Return
+    pub fn text(self, cycle: u64, text: &str) -> ScriptedInput {
+        text.chars().enumerate().fold(self, |script, (offset, character)| {
+            let key = if character == '\n' { GenericKeyboard::KEY_RETURN } else { character as Word };
+            script.event(cycle + offset as u64, KeyEvent::Type(key))
+        })
+    }
+    /// Events yet to happen, by cycle
+    pub fn events(&self) -> impl Iterator<Item = &(u64, KeyEvent)> {
+        self.events.iter()
+    }
+}
+
+impl InputSource for ScriptedInput {
+    fn poll(&mut self, cycle: u64) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+        while self.events.front().is_some_and(|&(at, _)| at <= cycle) {
+            events.extend(self.events.pop_front().map(|(_, event)| event));
+        }
+        events
+    }
+}
+
+impl fmt::Display for ScriptedInput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &(cycle, event) in &self.events {
+            writeln!(f, "{} {}", cycle, event)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ScriptedInput {
+    type Err = SystemError;
+    fn from_str(text: &str) -> Result<ScriptedInput, SystemError> {
+        let mut script = ScriptedInput::new();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (cycle, kind, key) = match fields[..] {
+                [cycle, kind, key] => (cycle, kind, key),
+                _ => return Err(SystemError::InvalidInput),
+            };
+            let cycle = cycle.parse().map_err(|_| SystemError::InvalidInput)?;
+            let key = key.strip_prefix("0x")
+                .and_then(|hex| Word::from_str_radix(hex, 16).ok())
+                .ok_or(SystemError::InvalidInput)?;
+            let event = match kind {
+                "press" => KeyEvent::Press(key),
+                "release" => KeyEvent::Release(key),
+                "type" => KeyEvent::Type(key),
+                _ => return Err(SystemError::InvalidInput),
+            };
+            script = script.event(cycle, event);
+        }
+        Ok(script)
+    }
+}
+
+/// Keys typed on the host terminal
+///
+/// Terminals report characters rather than key presses, so only Type events are produced.
+/// Arrow keys are recognised from their ANSI escape sequences.
+pub struct TerminalInput {
+    receiver: Receiver<u8>,
+    escape: Vec<u8>,
+}
+
+impl TerminalInput {
+    /// Read bytes from a channel
+    pub fn new(receiver: Receiver<u8>) -> TerminalInput {
+        TerminalInput { receiver, escape: Vec::new() }
+    }
+    /// Read standard input on a background thread
+    pub fn stdin() -> TerminalInput {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for byte in io::stdin().lock().bytes() {
+                match byte {
+                    Ok(byte) if sender.send(byte).is_ok() => {}
+                    _ => break,
+                }
+            }
+        });
+        TerminalInput::new(receiver)
+    }
+    /// Key number of a byte, continuing any escape sequence
+    fn decode(&mut self, byte: u8) -> Option<Word> {
+        if !self.escape.is_empty() || byte == 0x1B {
+            self.escape.push(byte);
+            return match self.escape[..] {
+                [0x1B] | [0x1B, b'['] => None,
+                [0x1B, b'[', arrow] => {
+                    self.escape.clear();
+                    match arrow {
+                        b'A' => Some(GenericKeyboard::KEY_UP),
+                        b'B' => Some(GenericKeyboard::KEY_DOWN),
+                        b'D' => Some(GenericKeyboard::KEY_LEFT),
+                        b'C' => Some(GenericKeyboard::KEY_RIGHT),
+                        _ => None,
+                    }
+                }
+                _ => {
+                    self.escape.clear();
+                    None
+                }
+            };
+        }
+        match byte {
+            0x08 | 0x7F => Some(GenericKeyboard::KEY_BACKSPACE),
+            b'\n' | b'\r' => Some(GenericKeyboard::KEY_RETURN),
+            0x20..=0x7E => Some(byte as Word),
+            _ => None,
+        }
+    }
+}
+
+impl InputSource for TerminalInput {
+    fn poll(&mut self, _: u64) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+        while let Ok(byte) = self.receiver.try_recv() {
+            events.extend(self.decode(byte).map(KeyEvent::Type));
+        }
+        events
+    }
+}
@@ -0,0 +1,185 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Legacy Keyboard
+//!
+//! The keyboard of the 0x10c alpha builds, which predates HWI: typed keys are written to a
+//! 16 word ring buffer at 0x9000, each into the next slot once the program has read it and
+//! written it back to 0. Keys are ASCII with Return as 0x0A and Backspace as 0x08, other keys
+//! keep their Generic Keyboard numbers. Press and release events are ignored.
+//!
+//! It is mapped with `System::map` rather than attached, so it is updated each cycle without
+//! HWN or HWQ enumerating it, and has no identity and ignores HWI. Pair it with `Lem1802::connected(0x8000)` for programs which expect video
+//! ram at 0x8000 without mapping it.
+
+use std::collections::VecDeque;
+use system2::{Clock, Memory, Queue, Registers, SystemError, Word};
+use super::{GenericKeyboard, Hardware, InputSource, KeyEvent};
+
+/// Address of the ring buffer
+pub const LEGACY_KEYBOARD: Word = 0x9000;
+
+/// Slots in the ring buffer
+const SLOTS: Word = 16;
+
+/// Legacy memory mapped keyboard
+#[derive(Default)]
+pub struct LegacyKeyboard {
+    source: Option<Box<dyn InputSource>>,
+    /// Keys typed while the next slot was still full
+    pending: VecDeque<Word>,
+    /// Next slot to write
+    next: Word,
+}
+
+impl LegacyKeyboard {
+    /// Create a Legacy Keyboard with no InputSource
+    pub fn new() -> LegacyKeyboard {
+        LegacyKeyboard::default()
+    }
+    /// Take key events from source
+    pub fn source(mut self, source: Box<dyn InputSource>) -> LegacyKeyboard {
+        self.source = Some(source);
+        self
+    }
+    /// Apply a key event from the host
+    pub fn key(&mut self, event: KeyEvent) {
+        if let KeyEvent::Type(key) = event {
+            self.pending.push_back(match key {
+                GenericKeyboard::KEY_RETURN => 0x0A,
+                GenericKeyboard::KEY_BACKSPACE => 0x08,
+                key => key,
+            });
+        }
+    }
+    /// Typed keys waiting for a free slot, oldest first
+    pub fn pending(&self) -> impl Iterator<Item = &Word> {
+        self.pending.iter()
+    }
+}
+
+impl Hardware for LegacyKeyboard {
+    fn mfg_id(&self) -> u32 {
+        0
+    }
+    fn hdw_id(&self) -> u32 {
+        0
+    }
+    fn dev_id(&self) -> Word {
+        0
+    }
+    fn interrupt(&mut self, _: &Clock, _: &mut Registers, _: &mut Memory, _: &mut Queue) -> Result<u16, SystemError> {
+        Ok(0)
+    }
+    fn update(&mut self, clock: &Clock, _: &mut Registers, memory: &mut Memory, _: &mut Queue) -> Result<(), SystemError> {
+        let events = match self.source.as_mut() {
+            Some(source) => source.poll(clock.cycles()),
+            None => Vec::new(),
+        };
+        for event in events {
+            self.key(event);
+        }
+        while let Some(&key) = self.pending.front() {
+            let slot = LEGACY_KEYBOARD + self.next;
+            if memory.get(slot) != 0 {
+                break;
+            }
+            memory.set(slot, key);
+            self.pending.pop_front();
+            self.next = (self.next + 1) % SLOTS;
+        }
+        Ok(())
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let mut data = self.next.to_le_bytes().to_vec();
+        for key in &self.pending {
+            data.extend_from_slice(&key.to_le_bytes());
+        }
+        data
+    }
+    fn restore_state(&mut self, _: Word, data: &[u8]) -> Result<(), SystemError> {
+        if data.len() < 2 || !data.len().is_multiple_of(2) {
+            return Err(SystemError::IncompatibleHardware);
+        }
+        let mut words = data.chunks(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+        self.next = words.next().unwrap_or(0) % SLOTS;
+        self.pending = words.collect();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use assembler::assemble;
+    use system2::System;
+    use super::super::{Lem1802, ScriptedInput};
+    use super::LegacyKeyboard;
+
+    #[test]
+    pub fn test_legacy_keyboard() {
+        let program = assemble(
+            ":loop   IFE [0x9000+I], 0\n\
+             \x20       SET PC, loop\n\
+             \x20       SET A, [0x9000+I]\n\
+             \x20       SET [0x9000+I], 0\n\
+             \x20       BOR A, 0xF000\n\
+             \x20       SET [0x8000+J], A\n\
+             \x20       ADD J, 1\n\
+             \x20       ADD I, 1\n\
+             \x20       AND I, 0xF\n\
+             \x20       SET PC, loop\n",
+        ).unwrap();
+        let lem = Rc::new(RefCell::new(Lem1802::connected(0x8000)));
+        let text = "The quick brown fox\n";
+        let mut sys = System::new();
+        sys.attach(Box::new(lem.clone()));
+        sys.map(Box::new(LegacyKeyboard::new().source(Box::new(ScriptedInput::new().text(10, text)))));
+        sys.memory_mut().write(0, &program.words).unwrap();
+
+        // Keys typed faster than read wrap around the ring and are echoed in order
+        for _ in 0..2_000 {
+            sys.step().unwrap();
+        }
+        let echoed: String = sys.memory().as_slice()[0x8000..0x8000 + text.len()].iter().map(|&cell| (cell & 0x7F) as u8 as char).collect();
+        assert_eq!(text, echoed);
+        assert_eq!(0xF00A, sys.memory().get(0x8000 + text.len() as u16 - 1));
+        assert!(sys.memory().as_slice()[0x9000..0x9010].iter().all(|&slot| slot == 0));
+
+        // Video ram at 0x8000 shows without mapping or startup delay
+        assert!(lem.borrow().is_ready());
+        assert_eq!(0xFFFFFF, lem.borrow().render(sys.memory()).get(16, 16));
+    }
+
+    #[test]
+    pub fn test_enumeration() {
+        let mut sys = System::new();
+        sys.attach(Box::new(Lem1802::new()));
+        sys.map(Box::new(LegacyKeyboard::new()));
+        sys.memory_mut().write(0, &[
+            0x0200,                 // HWN A
+            0x8A20,                 // HWQ 1
+        ]).unwrap();
+
+        // Only the display is enumerated
+        sys.step_instruction().unwrap();
+        assert_eq!(1, sys.registers().a);
+        sys.step_instruction().unwrap();
+        assert_eq!((0, 0, 0), (sys.registers().x, sys.registers().y, sys.registers().z));
+        assert_eq!(2, sys.snapshot().devices.len());
+    }
+}
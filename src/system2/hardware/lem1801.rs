@@ -0,0 +1,164 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! LEM1801 Low Energy Monitor
+//!
+//! The predecessor of the LEM1802, with the built in palette only. Its double buffer holds
+//! only the characters of video ram: cell colors are latched once per refresh, so a cell
+//! written between refreshes shows its new character in the colors it had at the last one.
+//! Programs which change text and colors together see the old colors bleed for a frame.
+
+use system2::{Clock, Memory, Queue, Registers, SystemError, Word};
+use super::lem1802::CELLS;
+use super::{Framebuffer, Hardware, Lem1802, CLOCK_RATE};
+
+/// Cycles between screen refreshes, 60 times a second
+const REFRESH: u64 = CLOCK_RATE / 60;
+
+/// LEM1801 Display
+#[derive(Clone, Debug)]
+pub struct Lem1801 {
+    /// Display common to the LEM1802
+    display: Lem1802,
+    /// Color attributes of each cell latched at the last refresh
+    colors: Vec<Word>,
+}
+
+impl Lem1801 {
+    /// Create a disconnected LEM1801
+    pub fn new() -> Lem1801 {
+        Lem1801 { display: Lem1802::new(), colors: vec![0; CELLS] }
+    }
+    /// Video ram address, 0 when disconnected
+    pub fn screen(&self) -> Word {
+        self.display.screen()
+    }
+    /// Font ram address, 0 for the built in font
+    pub fn font(&self) -> Word {
+        self.display.font()
+    }
+    /// Border palette index
+    pub fn border(&self) -> Word {
+        self.display.border()
+    }
+    /// Is the screen connected and started up
+    pub fn is_ready(&self) -> bool {
+        self.display.is_ready()
+    }
+    /// Render the screen and border with the latched colors, black while disconnected or starting up
+    pub fn render(&self, memory: &Memory) -> Framebuffer {
+        let screen = self.display.screen();
+        self.display.draw(memory, |cell| (memory.get(screen.wrapping_add(cell)) & 0x00FF) | self.colors[cell as usize])
+    }
+}
+
+impl Default for Lem1801 {
+    fn default() -> Lem1801 {
+        Lem1801::new()
+    }
+}
+
+impl Hardware for Lem1801 {
+    fn mfg_id(&self) -> u32 {
+        self.display.mfg_id()
+    }
+    fn hdw_id(&self) -> u32 {
+        self.display.hdw_id()
+    }
+    fn dev_id(&self) -> Word {
+        0x1801
+    }
+    fn interrupt(&mut self, clock: &Clock, registers: &mut Registers, memory: &mut Memory, queue: &mut Queue) -> Result<u16, SystemError> {
+        match registers.a {
+            // MEM_MAP_PALETTE and MEM_DUMP_PALETTE arrived with the LEM1802
+            2 | 5 => Ok(0),
+            _ => self.display.interrupt(clock, registers, memory, queue),
+        }
+    }
+    fn update(&mut self, clock: &Clock, registers: &mut Registers, memory: &mut Memory, queue: &mut Queue) -> Result<(), SystemError> {
+        self.display.update(clock, registers, memory, queue)?;
+        let screen = self.display.screen();
+        if screen != 0 && clock.cycles().is_multiple_of(REFRESH) {
+            for (cell, color) in self.colors.iter_mut().enumerate() {
+                *color = memory.get(screen.wrapping_add(cell as Word)) & 0xFF00;
+            }
+        }
+        Ok(())
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let mut data = self.display.save_state();
+        for color in &self.colors {
+            data.extend_from_slice(&color.to_le_bytes());
+        }
+        data
+    }
+    fn restore_state(&mut self, dev_id: Word, data: &[u8]) -> Result<(), SystemError> {
+        let split = data.len().checked_sub(CELLS * 2).ok_or(SystemError::IncompatibleHardware)?;
+        self.display.restore_state(dev_id, &data[..split])?;
+        for (color, bytes) in self.colors.iter_mut().zip(data[split..].chunks(2)) {
+            *color = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use system2::{Clock, Hardware, Memory, Queue, Registers};
+    use super::super::CLOCK_RATE;
+    use super::{Lem1801, REFRESH};
+
+    #[test]
+    pub fn test_lem1801() {
+        let mut lem = Lem1801::new();
+        let (mut clock, mut registers, mut memory, mut queue) = (Clock::new(), Registers::new(), Memory::new(), Queue::new());
+        assert_eq!((0x7349_F615, 0x1801), (lem.hdw_id(), lem.dev_id()));
+
+        // Palette interrupts are ignored
+        registers.a = 2;
+        registers.b = 0x1000;
+        assert_eq!(0, lem.interrupt(&clock, &mut registers, &mut memory, &mut queue).unwrap());
+        registers.a = 5;
+        assert_eq!(0, lem.interrupt(&clock, &mut registers, &mut memory, &mut queue).unwrap());
+        assert_eq!(0, memory.get(0x1000));
+
+        // Connect and wait out startup on a refresh
+        registers.a = 0;
+        registers.b = 0x8000;
+        lem.interrupt(&clock, &mut registers, &mut memory, &mut queue).unwrap();
+        memory.set(0x8000, 0xF000 | 0x46);
+        clock.advance(CLOCK_RATE + REFRESH - CLOCK_RATE % REFRESH);
+        lem.update(&clock, &mut registers, &mut memory, &mut queue).unwrap();
+        let frame = lem.render(&memory);
+        assert_eq!(0xFFFFFF, frame.get(16, 16));
+
+        // New colors bleed in on the next refresh while the character changes immediately
+        memory.set(0x8000, 0x4000 | 0x20);
+        clock.advance(1);
+        lem.update(&clock, &mut registers, &mut memory, &mut queue).unwrap();
+        assert_eq!(0x000000, lem.render(&memory).get(16, 16));
+        memory.set(0x8000, 0x4000 | 0x46);
+        assert_eq!(0xFFFFFF, lem.render(&memory).get(16, 16));
+        clock.advance(REFRESH - 1);
+        lem.update(&clock, &mut registers, &mut memory, &mut queue).unwrap();
+        assert_eq!(0xAA0000, lem.render(&memory).get(16, 16));
+
+        // Latched colors survive saving
+        let mut copy = Lem1801::new();
+        copy.restore_state(0x1801, &lem.save_state()).unwrap();
+        assert_eq!(lem.render(&memory), copy.render(&memory));
+    }
+}
@@ -0,0 +1,333 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! LEM1802 Low Energy Monitor
+//!
+//! A 128x96 pixel display of 32x12 cells, each a 4x8 pixel character from a 128 character
+//! font in one of 16 foreground and background colors. Video, font and palette ram are
+//! mapped into DCPU-16 memory, with a built in font and palette used when unmapped.
+
+use system2::{Clock, Memory, Queue, Registers, SystemError, Word};
+use super::{rgb, Framebuffer, Hardware, CLOCK_RATE};
+
+/// Display columns and rows of cells
+const COLUMNS: usize = 32;
+const ROWS: usize = 12;
+
+/// Cells of video ram
+pub(super) const CELLS: usize = COLUMNS * ROWS;
+
+/// Cell size in pixels
+const CELL_WIDTH: usize = 4;
+const CELL_HEIGHT: usize = 8;
+
+/// Border width in pixels around the screen
+const BORDER: usize = 16;
+
+/// Cycles taken to start up after the screen is connected
+const STARTUP: u64 = CLOCK_RATE;
+
+/// Cycles a blinking character is shown, then hidden
+const BLINK: u64 = CLOCK_RATE / 2;
+
+/// Built in font, two words per character with a column in each octet and the top row in bit 0
+pub const DEFAULT_FONT: [Word; 256] = [
+    0xB79E, 0x388E, 0x722C, 0x75F4, 0x19BB, 0x7F8F, 0x85F9, 0xB158,
+    0x242E, 0x2400, 0x082A, 0x0800, 0x0008, 0x0000, 0x0808, 0x0808,
+    0x00FF, 0x0000, 0x00F8, 0x0808, 0xF808, 0x0000, 0x080F, 0x0000,
+    0x000F, 0x0808, 0x00FF, 0x0808, 0x08F8, 0x0808, 0xFF08, 0x0000,
+    0x080F, 0x0808, 0x08FF, 0x0808, 0x6633, 0x99CC, 0x9933, 0x66CC,
+    0xFEF8, 0xE080, 0x7F1F, 0x0701, 0x0107, 0x1F7F, 0x80E0, 0xF8FE,
+    0x5500, 0xAA00, 0x55AA, 0x55AA, 0xFFAA, 0xFF55, 0x0F0F, 0x0F0F,
+    0xF0F0, 0xF0F0, 0x0000, 0xFFFF, 0xFFFF, 0x0000, 0xFFFF, 0xFFFF,
+    0x0000, 0x0000, 0x005F, 0x0000, 0x0300, 0x0300, 0x3E14, 0x3E00,
+    0x266B, 0x3200, 0x611C, 0x4300, 0x3629, 0x7650, 0x0002, 0x0100,
+    0x1C22, 0x4100, 0x4122, 0x1C00, 0x1408, 0x1400, 0x081C, 0x0800,
+    0x4020, 0x0000, 0x0808, 0x0800, 0x0040, 0x0000, 0x601C, 0x0300,
+    0x3E49, 0x3E00, 0x427F, 0x4000, 0x6259, 0x4600, 0x2249, 0x3600,
+    0x0F08, 0x7F00, 0x2745, 0x3900, 0x3E49, 0x3200, 0x6119, 0x0700,
+    0x3649, 0x3600, 0x2649, 0x3E00, 0x0024, 0x0000, 0x4024, 0x0000,
+    0x0814, 0x2200, 0x1414, 0x1400, 0x2214, 0x0800, 0x0259, 0x0600,
+    0x3E59, 0x5E00, 0x7E09, 0x7E00, 0x7F49, 0x3600, 0x3E41, 0x2200,
+    0x7F41, 0x3E00, 0x7F49, 0x4100, 0x7F09, 0x0100, 0x3E41, 0x7A00,
+    0x7F08, 0x7F00, 0x417F, 0x4100, 0x2040, 0x3F00, 0x7F08, 0x7700,
+    0x7F40, 0x4000, 0x7F06, 0x7F00, 0x7F01, 0x7E00, 0x3E41, 0x3E00,
+    0x7F09, 0x0600, 0x3E61, 0x7E00, 0x7F09, 0x7600, 0x2649, 0x3200,
+    0x017F, 0x0100, 0x3F40, 0x7F00, 0x1F60, 0x1F00, 0x7F30, 0x7F00,
+    0x7708, 0x7700, 0x0778, 0x0700, 0x7149, 0x4700, 0x007F, 0x4100,
+    0x031C, 0x6000, 0x417F, 0x0000, 0x0201, 0x0200, 0x8080, 0x8000,
+    0x0001, 0x0200, 0x2454, 0x7800, 0x7F44, 0x3800, 0x3844, 0x2800,
+    0x3844, 0x7F00, 0x3854, 0x5800, 0x087E, 0x0900, 0x4854, 0x3C00,
+    0x7F04, 0x7800, 0x047D, 0x0000, 0x2040, 0x3D00, 0x7F10, 0x6C00,
+    0x017F, 0x0000, 0x7C18, 0x7C00, 0x7C04, 0x7800, 0x3844, 0x3800,
+    0x7C14, 0x0800, 0x0814, 0x7C00, 0x7C04, 0x0800, 0x4854, 0x2400,
+    0x043E, 0x4400, 0x3C40, 0x7C00, 0x1C60, 0x1C00, 0x7C30, 0x7C00,
+    0x6C10, 0x6C00, 0x4C50, 0x3C00, 0x6454, 0x4C00, 0x0836, 0x4100,
+    0x0077, 0x0000, 0x4136, 0x0800, 0x0201, 0x0201, 0x0205, 0x0200,
+];
+
+/// Built in palette as 0000rrrrggggbbbb
+pub const DEFAULT_PALETTE: [Word; 16] = [
+    0x0000, 0x000A, 0x00A0, 0x00AA, 0x0A00, 0x0A0A, 0x0A50, 0x0AAA,
+    0x0555, 0x055F, 0x05F5, 0x05FF, 0x0F55, 0x0F5F, 0x0FF5, 0x0FFF,
+];
+
+/// LEM1802 Display
+#[derive(Clone, Debug, Default)]
+pub struct Lem1802 {
+    /// Video ram address, 0 when disconnected
+    screen: Word,
+    /// Font ram address, 0 for the built in font
+    font: Word,
+    /// Palette ram address, 0 for the built in palette
+    palette: Word,
+    /// Border palette index
+    border: Word,
+    /// Cycle the display finishes starting up
+    ready: u64,
+    /// Cycle of the last update
+    cycles: u64,
+}
+
+impl Lem1802 {
+    /// Create a disconnected LEM1802
+    pub fn new() -> Lem1802 {
+        Lem1802::default()
+    }
+    /// Create a LEM1802 already started with video ram at screen, as most emulators power on
+    pub fn connected(screen: Word) -> Lem1802 {
+        Lem1802 { screen, ..Lem1802::default() }
+    }
+    /// Video ram address, 0 when disconnected
+    pub fn screen(&self) -> Word {
+        self.screen
+    }
+    /// Font ram address, 0 for the built in font
+    pub fn font(&self) -> Word {
+        self.font
+    }
+    /// Palette ram address, 0 for the built in palette
+    pub fn palette(&self) -> Word {
+        self.palette
+    }
+    /// Border palette index
+    pub fn border(&self) -> Word {
+        self.border
+    }
+    /// Is the screen connected and started up
+    pub fn is_ready(&self) -> bool {
+        self.screen != 0 && self.cycles >= self.ready
+    }
+    /// Render the screen and border, black while disconnected or starting up
+    pub fn render(&self, memory: &Memory) -> Framebuffer {
+        self.draw(memory, |cell| memory.get(self.screen.wrapping_add(cell)))
+    }
+    /// Render the video ram value of each cell returned by cells
+    pub(super) fn draw<F: Fn(Word) -> Word>(&self, memory: &Memory, cells: F) -> Framebuffer {
+        let width = COLUMNS * CELL_WIDTH + 2 * BORDER;
+        let height = ROWS * CELL_HEIGHT + 2 * BORDER;
+        if !self.is_ready() {
+            return Framebuffer::new(width, height, 0);
+        }
+        let colors: Vec<u32> = (0..16)
+            .map(|index| match self.palette {
+                0 => DEFAULT_PALETTE[index as usize],
+                palette => memory.get(palette.wrapping_add(index)),
+            })
+            .map(rgb)
+            .collect();
+        let mut frame = Framebuffer::new(width, height, colors[self.border as usize]);
+        let hidden = (self.cycles / BLINK) % 2 == 1;
+        for cell in 0..CELLS {
+            let value = cells(cell as Word);
+            let character = value & 0x7F;
+            let foreground = colors[(value >> 12) as usize];
+            let background = colors[((value >> 8) & 0xF) as usize];
+            let blinking = value & 0x80 != 0;
+            let glyph = [self.glyph(memory, character, 0), self.glyph(memory, character, 1)];
+            let (left, top) = (BORDER + (cell % COLUMNS) * CELL_WIDTH, BORDER + (cell / COLUMNS) * CELL_HEIGHT);
+            for column in 0..CELL_WIDTH {
+                let word = glyph[column / 2];
+                let bits = if column % 2 == 0 { word >> 8 } else { word & 0xFF };
+                for row in 0..CELL_HEIGHT {
+                    let lit = (bits >> row) & 1 != 0 && !(blinking && hidden);
+                    frame.set(left + column, top + row, if lit { foreground } else { background });
+                }
+            }
+        }
+        frame
+    }
+    /// Word of a character in the mapped or built in font
+    fn glyph(&self, memory: &Memory, character: Word, word: Word) -> Word {
+        match self.font {
+            0 => DEFAULT_FONT[(character * 2 + word) as usize],
+            font => memory.get(font.wrapping_add(character * 2 + word)),
+        }
+    }
+}
+
+impl Hardware for Lem1802 {
+    fn mfg_id(&self) -> u32 {
+        0x1C6C_8B36
+    }
+    fn hdw_id(&self) -> u32 {
+        0x7349_F615
+    }
+    fn dev_id(&self) -> Word {
+        0x1802
+    }
+    fn interrupt(&mut self, clock: &Clock, registers: &mut Registers, memory: &mut Memory, _: &mut Queue) -> Result<u16, SystemError> {
+        let b = registers.b;
+        match registers.a {
+            0 => {
+                if self.screen == 0 && b != 0 {
+                    self.ready = clock.cycles().saturating_add(STARTUP);
+                }
+                self.screen = b;
+            }
+            1 => self.font = b,
+            2 => self.palette = b,
+            3 => self.border = b & 0xF,
+            4 => {
+                for (offset, &word) in DEFAULT_FONT.iter().enumerate() {
+                    memory.set(b.wrapping_add(offset as Word), word);
+                }
+                return Ok(256);
+            }
+            5 => {
+                for (offset, &word) in DEFAULT_PALETTE.iter().enumerate() {
+                    memory.set(b.wrapping_add(offset as Word), word);
+                }
+                return Ok(16);
+            }
+            _ => {}
+        }
+        Ok(0)
+    }
+    fn update(&mut self, clock: &Clock, _: &mut Registers, _: &mut Memory, _: &mut Queue) -> Result<(), SystemError> {
+        self.cycles = clock.cycles();
+        Ok(())
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(24);
+        for word in &[self.screen, self.font, self.palette, self.border] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data.extend_from_slice(&self.ready.to_le_bytes());
+        data.extend_from_slice(&self.cycles.to_le_bytes());
+        data
+    }
+    fn restore_state(&mut self, _: Word, data: &[u8]) -> Result<(), SystemError> {
+        if data.len() != 24 {
+            return Err(SystemError::IncompatibleHardware);
+        }
+        let word = |index: usize| u16::from_le_bytes([data[index], data[index + 1]]);
+        let long = |index: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&data[index..index + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        self.screen = word(0);
+        self.font = word(2);
+        self.palette = word(4);
+        self.border = word(6);
+        self.ready = long(8);
+        self.cycles = long(16);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use system2::System;
+    use super::super::CLOCK_RATE;
+    use super::{Lem1802, DEFAULT_PALETTE};
+
+    #[test]
+    pub fn test_lem1802() {
+        let lem = Rc::new(RefCell::new(Lem1802::new()));
+        let mut sys = System::new();
+        sys.attach(Box::new(lem.clone()));
+        sys.memory_mut().write(0x0000, &[
+            0x8401,                 // SET A, 0
+            0x7C21, 0x8000,         // SET B, 0x8000
+            0x8640,                 // HWI 0
+            0x9401,                 // SET A, 4
+            0x7C21, 0x1000,         // SET B, 0x1000
+            0x8640,                 // HWI 0
+            0x9801,                 // SET A, 5
+            0x7C21, 0x1100,         // SET B, 0x1100
+            0x8640,                 // HWI 0
+            0x9001,                 // SET A, 3
+            0x9821,                 // SET B, 5
+            0x8640,                 // HWI 0
+            0xC381,                 // SET PC, 0x000F
+        ]).unwrap();
+        sys.memory_mut().write(0x8000, &[0xF046, 0xF0C6]).unwrap();
+
+        // Dumping the font and palette halts for 256 and 16 cycles beyond SET, SET and HWI
+        for _ in 0..3 {
+            sys.step_instruction().unwrap();
+        }
+        let start = sys.clock().cycles();
+        for _ in 0..3 {
+            sys.step_instruction().unwrap();
+        }
+        assert_eq!(start + 7 + 256, sys.clock().cycles());
+        for _ in 0..3 {
+            sys.step_instruction().unwrap();
+        }
+        assert_eq!(start + 14 + 256 + 16, sys.clock().cycles());
+        assert_eq!(0x7F09, sys.memory().get(0x1000 + 0x46 * 2));
+        assert_eq!(&DEFAULT_PALETTE[..], &sys.memory().as_slice()[0x1100..0x1110]);
+
+        // Black while starting up
+        for _ in 0..3 {
+            sys.step_instruction().unwrap();
+        }
+        assert_eq!(5, lem.borrow().border());
+        assert!(!lem.borrow().is_ready());
+        assert!(lem.borrow().render(sys.memory()).pixels.iter().all(|&pixel| pixel == 0));
+        while sys.clock().cycles() < start + CLOCK_RATE {
+            sys.step().unwrap();
+        }
+        assert!(lem.borrow().is_ready());
+
+        // White F on black in the first cell, blinking in the second, inside a magenta border
+        let frame = lem.borrow().render(sys.memory());
+        assert_eq!((160, 128), (frame.width, frame.height));
+        assert_eq!(0xAA00AA, frame.get(0, 0));
+        assert_eq!(0xFFFFFF, frame.get(16, 16));
+        assert_eq!(0x000000, frame.get(17, 17));
+        assert_eq!(0xFFFFFF, frame.get(20, 16));
+        while sys.clock().cycles() < start + CLOCK_RATE * 3 / 2 {
+            sys.step().unwrap();
+        }
+        let frame = lem.borrow().render(sys.memory());
+        assert_eq!(0xFFFFFF, frame.get(16, 16));
+        assert_eq!(0x000000, frame.get(20, 16));
+
+        // Display state survives a snapshot
+        let snapshot = sys.snapshot();
+        let copy = Rc::new(RefCell::new(Lem1802::new()));
+        let mut restored = System::new();
+        restored.attach(Box::new(copy.clone()));
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(frame, copy.borrow().render(restored.memory()));
+    }
+}
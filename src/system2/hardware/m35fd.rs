@@ -0,0 +1,402 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Mackapar 3.5" Floppy Drive
+//!
+//! Reads and writes are asynchronous: a command starts a transfer which completes after the
+//! head seeks to the sector's track, 2.4 ms per track, and 512 words pass at 30.7 kw/s. The
+//! whole sector is copied when the transfer completes, so there are no partial transfers.
+//! Snapshots save the drive but not the inserted disk, which lives on the host, nor any
+//! scheduled faults.
+//!
+//! Faults scheduled by the host take effect on the first update at or after their cycle, so
+//! drivers can be tested against bad sectors, disks ejected mid transfer and broken drives.
+//! A broken drive fails every command with ERROR_BROKEN until it is repaired.
+
+use system2::{Clock, Memory, Queue, Registers, SystemError, Word};
+use super::{Disk, Hardware, SECTORS, SECTOR_WORDS};
+use super::disk::{SECTORS_PER_TRACK, SEEK, TRANSFER};
+
+/// Read or write in progress
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Transfer {
+    write: bool,
+    sector: Word,
+    address: Word,
+    done: u64,
+}
+
+/// Failure injected into a drive
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Fault {
+    /// Mark a sector of the inserted disk as bad
+    BadSector(Word),
+    /// Eject the disk, failing any transfer in progress
+    Eject,
+    /// Break the drive until repaired
+    Broken,
+}
+
+/// M35FD Floppy Drive
+#[derive(Clone, Debug, Default)]
+pub struct M35fd {
+    disk: Option<Disk>,
+    error: Word,
+    message: Word,
+    track: Word,
+    transfer: Option<Transfer>,
+    /// State and error last reported by interrupt
+    notified: (Word, Word),
+    broken: bool,
+    /// Faults to inject by cycle, in order
+    faults: Vec<(u64, Fault)>,
+    /// Disk removed by an injected eject
+    ejected: Option<Disk>,
+}
+
+impl M35fd {
+    /// There's no floppy in the drive
+    pub const STATE_NO_MEDIA: Word = 0x0000;
+    /// The drive is ready to accept commands
+    pub const STATE_READY: Word = 0x0001;
+    /// Same as ready, except the floppy is write protected
+    pub const STATE_READY_WP: Word = 0x0002;
+    /// The drive is busy either reading or writing a sector
+    pub const STATE_BUSY: Word = 0x0003;
+    /// There's been no error since the last poll
+    pub const ERROR_NONE: Word = 0x0000;
+    /// Drive is busy performing an action
+    pub const ERROR_BUSY: Word = 0x0001;
+    /// Attempted to read or write with no floppy inserted
+    pub const ERROR_NO_MEDIA: Word = 0x0002;
+    /// Attempted to write to write protected floppy
+    pub const ERROR_PROTECTED: Word = 0x0003;
+    /// The floppy was removed while reading or writing
+    pub const ERROR_EJECT: Word = 0x0004;
+    /// The requested sector is broken, the data on it is lost
+    pub const ERROR_BAD_SECTOR: Word = 0x0005;
+    /// There's been some major software or hardware problem
+    pub const ERROR_BROKEN: Word = 0xFFFF;
+
+    /// Create an empty M35FD
+    pub fn new() -> M35fd {
+        M35fd::default()
+    }
+    /// Current state code
+    pub fn state(&self) -> Word {
+        match (self.disk.as_ref(), self.transfer) {
+            (None, _) => M35fd::STATE_NO_MEDIA,
+            (Some(_), Some(_)) => M35fd::STATE_BUSY,
+            (Some(disk), None) if disk.is_protected() => M35fd::STATE_READY_WP,
+            (Some(_), None) => M35fd::STATE_READY,
+        }
+    }
+    /// Error code since the last poll
+    pub fn error(&self) -> Word {
+        self.error
+    }
+    /// Inserted disk
+    pub fn disk(&self) -> Option<&Disk> {
+        self.disk.as_ref()
+    }
+    /// Insert a disk, returning any disk ejected to make room
+    pub fn insert(&mut self, disk: Disk) -> Option<Disk> {
+        let ejected = self.eject();
+        self.disk = Some(disk);
+        ejected
+    }
+    /// Eject the disk, failing any transfer in progress
+    pub fn eject(&mut self) -> Option<Disk> {
+        if self.transfer.take().is_some() {
+            self.error = M35fd::ERROR_EJECT;
+        }
+        self.disk.take()
+    }
+    /// Inject a fault at cycle
+    pub fn schedule(&mut self, cycle: u64, fault: Fault) {
+        let index = self.faults.iter().take_while(|&&(at, _)| at <= cycle).count();
+        self.faults.insert(index, (cycle, fault));
+    }
+    /// Faults yet to be injected, by cycle
+    pub fn faults(&self) -> &[(u64, Fault)] {
+        &self.faults
+    }
+    /// Take the disk removed by an injected eject
+    pub fn take_ejected(&mut self) -> Option<Disk> {
+        self.ejected.take()
+    }
+    /// Is the drive broken
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+    /// Repair a broken drive, as if turned off and on again
+    pub fn repair(&mut self) {
+        self.broken = false;
+    }
+    /// Apply a fault now
+    fn inject(&mut self, fault: Fault) {
+        match fault {
+            Fault::BadSector(sector) => {
+                if let Some(disk) = self.disk.as_mut() {
+                    disk.mark_bad(sector);
+                }
+            }
+            Fault::Eject => {
+                if let Some(disk) = self.eject() {
+                    self.ejected = Some(disk);
+                }
+            }
+            Fault::Broken => {
+                self.broken = true;
+                self.transfer = None;
+                self.error = M35fd::ERROR_BROKEN;
+            }
+        }
+    }
+    /// Start a transfer of sector to or from address, returning whether it started
+    fn start(&mut self, clock: &Clock, write: bool, sector: Word, address: Word) -> bool {
+        let error = match self.disk.as_ref() {
+            _ if self.broken => M35fd::ERROR_BROKEN,
+            None => M35fd::ERROR_NO_MEDIA,
+            Some(_) if self.transfer.is_some() => M35fd::ERROR_BUSY,
+            Some(disk) if write && disk.is_protected() => M35fd::ERROR_PROTECTED,
+            Some(_) if sector as usize >= SECTORS => M35fd::ERROR_BAD_SECTOR,
+            Some(_) => {
+                let tracks = (sector / SECTORS_PER_TRACK).abs_diff(self.track) as u64;
+                self.transfer = Some(Transfer { write, sector, address, done: clock.cycles().saturating_add(tracks * SEEK + TRANSFER) });
+                return true;
+            }
+        };
+        self.error = error;
+        false
+    }
+    /// Copy the sector of a transfer which has completed
+    fn complete(&mut self, transfer: Transfer, memory: &mut Memory) {
+        self.track = transfer.sector / SECTORS_PER_TRACK;
+        let disk = match self.disk.as_mut() {
+            Some(disk) => disk,
+            None => return,
+        };
+        if disk.is_bad(transfer.sector) {
+            self.error = M35fd::ERROR_BAD_SECTOR;
+        } else if transfer.write {
+            let data: Vec<Word> = (0..SECTOR_WORDS as Word).map(|offset| memory.get(transfer.address.wrapping_add(offset))).collect();
+            if disk.write_sector(transfer.sector, &data).is_err() {
+                self.error = M35fd::ERROR_BROKEN;
+            }
+        } else if let Some(data) = disk.sector(transfer.sector) {
+            for (offset, &word) in data.iter().enumerate() {
+                memory.set(transfer.address.wrapping_add(offset as Word), word);
+            }
+        }
+    }
+}
+
+impl Hardware for M35fd {
+    fn mfg_id(&self) -> u32 {
+        0x1EB3_7E91
+    }
+    fn hdw_id(&self) -> u32 {
+        0x4FD5_24C5
+    }
+    fn dev_id(&self) -> Word {
+        0x000B
+    }
+    fn interrupt(&mut self, clock: &Clock, registers: &mut Registers, _: &mut Memory, _: &mut Queue) -> Result<u16, SystemError> {
+        match registers.a {
+            0 => {
+                registers.b = self.state();
+                registers.c = self.error;
+                // Clearing the error by polling is not a change worth an interrupt
+                self.error = M35fd::ERROR_NONE;
+                self.notified.1 = M35fd::ERROR_NONE;
+            }
+            1 => self.message = registers.x,
+            2 => registers.b = self.start(clock, false, registers.x, registers.y) as Word,
+            3 => registers.b = self.start(clock, true, registers.x, registers.y) as Word,
+            _ => {}
+        }
+        Ok(0)
+    }
+    fn update(&mut self, clock: &Clock, _: &mut Registers, memory: &mut Memory, queue: &mut Queue) -> Result<(), SystemError> {
+        while self.faults.first().is_some_and(|&(cycle, _)| cycle <= clock.cycles()) {
+            let (_, fault) = self.faults.remove(0);
+            self.inject(fault);
+        }
+        if let Some(transfer) = self.transfer {
+            if clock.cycles() >= transfer.done {
+                self.transfer = None;
+                self.complete(transfer, memory);
+            }
+        }
+        let status = (self.state(), self.error);
+        if status != self.notified {
+            self.notified = status;
+            if self.message != 0 {
+                queue.enqueue(self.message)?;
+            }
+        }
+        Ok(())
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let transfer = self.transfer.unwrap_or(Transfer { write: false, sector: 0, address: 0, done: 0 });
+        let mut data = Vec::with_capacity(27);
+        for word in &[self.error, self.message, self.track, self.notified.0, self.notified.1, transfer.sector, transfer.address] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data.extend_from_slice(&transfer.done.to_le_bytes());
+        data.push(self.transfer.is_some() as u8 | (transfer.write as u8) << 1 | (self.broken as u8) << 2);
+        data
+    }
+    fn restore_state(&mut self, _: Word, data: &[u8]) -> Result<(), SystemError> {
+        if data.len() != 23 {
+            return Err(SystemError::IncompatibleHardware);
+        }
+        let word = |index: usize| u16::from_le_bytes([data[index], data[index + 1]]);
+        let mut done = [0; 8];
+        done.copy_from_slice(&data[14..22]);
+        self.error = word(0);
+        self.message = word(2);
+        self.track = word(4);
+        self.notified = (word(6), word(8));
+        self.broken = data[22] & 4 != 0;
+        self.transfer = match data[22] & 1 {
+            0 => None,
+            _ => Some(Transfer { write: data[22] & 2 != 0, sector: word(10), address: word(12), done: u64::from_le_bytes(done) }),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+    use system2::{Clock, Hardware, Memory, Queue, Registers, Word};
+    use super::super::Disk;
+    use super::{Fault, M35fd, SEEK, TRANSFER};
+
+    /// Send a command, returning B and C
+    fn command(drive: &mut M35fd, clock: &Clock, memory: &mut Memory, a: Word, x: Word, y: Word) -> (Word, Word) {
+        let mut registers = Registers::new();
+        registers.a = a;
+        registers.x = x;
+        registers.y = y;
+        drive.interrupt(clock, &mut registers, memory, &mut Queue::new()).unwrap();
+        (registers.b, registers.c)
+    }
+
+    /// Update the drive every cycle until cycle
+    fn run(drive: &mut M35fd, clock: &mut Clock, memory: &mut Memory, cycle: u64) {
+        while clock.cycles() < cycle {
+            clock.step().unwrap();
+            drive.update(clock, &mut Registers::new(), memory, &mut Queue::new()).unwrap();
+        }
+    }
+
+    #[test]
+    pub fn test_m35fd() {
+        let mut drive = M35fd::new();
+        let (mut clock, mut registers, mut memory, mut queue) = (Clock::new(), Registers::new(), Memory::new(), Queue::new());
+
+        // Reading without media fails
+        assert_eq!(0, command(&mut drive, &clock, &mut memory, 2, 3, 0x1000).0);
+        assert_eq!((M35fd::STATE_NO_MEDIA, M35fd::ERROR_NO_MEDIA), command(&mut drive, &clock, &mut memory, 0, 0, 0));
+        command(&mut drive, &clock, &mut memory, 1, 0x42, 0);
+
+        // Inserting an image with sector 3 filled with 0x1234 interrupts
+        let path = env::temp_dir().join(format!("vcpu16-m35fd-{}.img", process::id()));
+        let mut image = vec![0u8; 4 * 1024];
+        for pair in image[3 * 1024..].chunks_mut(2) {
+            pair.copy_from_slice(&[0x34, 0x12]);
+        }
+        fs::write(&path, &image).unwrap();
+        drive.insert(Disk::open(&path).unwrap());
+        drive.update(&clock, &mut registers, &mut memory, &mut queue).unwrap();
+        assert_eq!(Ok(0x42), queue.dequeue());
+
+        // Read completes after the transfer time on the current track
+        assert_eq!(1, command(&mut drive, &clock, &mut memory, 2, 3, 0x1000).0);
+        assert_eq!(0, command(&mut drive, &clock, &mut memory, 2, 3, 0x1000).0);
+        assert_eq!((M35fd::STATE_BUSY, M35fd::ERROR_BUSY), command(&mut drive, &clock, &mut memory, 0, 0, 0));
+        clock.advance(TRANSFER - 1);
+        drive.update(&clock, &mut registers, &mut memory, &mut queue).unwrap();
+        assert_eq!(0, memory.get(0x1000));
+        clock.advance(1);
+        drive.update(&clock, &mut registers, &mut memory, &mut queue).unwrap();
+        assert_eq!((0x1234, 0x1234), (memory.get(0x1000), memory.get(0x11FF)));
+        assert_eq!(M35fd::STATE_READY, drive.state());
+        assert_eq!(2, queue.len());
+
+        // Write seeks two tracks and goes through to the image
+        memory.set(0x2000, 0xBEEF);
+        assert_eq!(1, command(&mut drive, &clock, &mut memory, 3, 40, 0x2000).0);
+        clock.advance(2 * SEEK + TRANSFER);
+        drive.update(&clock, &mut registers, &mut memory, &mut queue).unwrap();
+        let image = fs::read(&path).unwrap();
+        assert_eq!(&[0xEF, 0xBE], &image[40 * 1024..40 * 1024 + 2]);
+        assert_eq!(0xBEEF, Disk::open(&path).unwrap().sector(40).unwrap()[0]);
+
+        // Ejecting mid transfer fails it
+        assert_eq!(1, command(&mut drive, &clock, &mut memory, 2, 0, 0x3000).0);
+        let disk = drive.eject().unwrap();
+        assert_eq!((M35fd::STATE_NO_MEDIA, M35fd::ERROR_EJECT), command(&mut drive, &clock, &mut memory, 0, 0, 0));
+
+        // Protected disks refuse writes
+        drive.insert(disk.protected(true));
+        assert_eq!(0, command(&mut drive, &clock, &mut memory, 3, 0, 0x2000).0);
+        assert_eq!((M35fd::STATE_READY_WP, M35fd::ERROR_PROTECTED), command(&mut drive, &clock, &mut memory, 0, 0, 0));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    pub fn test_faults() {
+        let mut drive = M35fd::new();
+        let (mut clock, mut memory) = (Clock::new(), Memory::new());
+        let mut disk = Disk::blank();
+        disk.write_sector(5, &[0x5555; 512]).unwrap();
+        drive.insert(disk);
+        drive.schedule(20_000, Fault::Broken);
+        drive.schedule(100, Fault::BadSector(5));
+        drive.schedule(5_000, Fault::Eject);
+        assert_eq!(vec![100, 5_000, 20_000], drive.faults().iter().map(|&(cycle, _)| cycle).collect::<Vec<_>>());
+
+        // Sector goes bad while it is being read
+        assert_eq!(1, command(&mut drive, &clock, &mut memory, 2, 5, 0x1000).0);
+        run(&mut drive, &mut clock, &mut memory, TRANSFER);
+        assert_eq!((M35fd::STATE_READY, M35fd::ERROR_BAD_SECTOR), command(&mut drive, &clock, &mut memory, 0, 0, 0));
+        assert_eq!(0, memory.get(0x1000));
+
+        // Disk is ejected mid transfer
+        run(&mut drive, &mut clock, &mut memory, 4_000);
+        assert_eq!(1, command(&mut drive, &clock, &mut memory, 2, 0, 0x1000).0);
+        run(&mut drive, &mut clock, &mut memory, 5_000);
+        assert_eq!((M35fd::STATE_NO_MEDIA, M35fd::ERROR_EJECT), command(&mut drive, &clock, &mut memory, 0, 0, 0));
+        let disk = drive.take_ejected().unwrap();
+        assert_eq!(vec![5], disk.bad_sectors());
+
+        // Drive breaks until repaired
+        drive.insert(disk);
+        run(&mut drive, &mut clock, &mut memory, 20_000);
+        assert!(drive.is_broken());
+        assert_eq!(0, command(&mut drive, &clock, &mut memory, 2, 0, 0x1000).0);
+        assert_eq!((M35fd::STATE_READY, M35fd::ERROR_BROKEN), command(&mut drive, &clock, &mut memory, 0, 0, 0));
+        drive.repair();
+        assert_eq!(1, command(&mut drive, &clock, &mut memory, 2, 0, 0x1000).0);
+        assert!(drive.faults().is_empty());
+    }
+}
@@ -14,23 +14,193 @@
 // limitations under the License.
 //
 
+use std::cell::RefCell;
+use std::rc::Rc;
 use super::Clock;
 use super::Memory;
-use super::PIC;
+use super::Queue;
 use super::Registers;
-use super::SSystemError;
+use super::SystemError;
 use super::Word;
 
+mod disk;
+mod framebuffer;
+mod generic_clock;
+mod generic_keyboard;
+mod hmd2043;
+mod input;
+mod legacy_keyboard;
+mod lem1801;
+mod lem1802;
+mod m35fd;
+mod spc2000;
+mod sped3;
+
+pub use self::disk::{Disk, SECTORS, SECTOR_WORDS};
+pub use self::framebuffer::{rgb, Framebuffer};
+pub use self::generic_clock::GenericClock;
+pub use self::generic_keyboard::GenericKeyboard;
+pub use self::hmd2043::Hmd2043;
+pub use self::input::{InputSource, KeyEvent, ScriptedInput, TerminalInput};
+pub use self::legacy_keyboard::{LegacyKeyboard, LEGACY_KEYBOARD};
+pub use self::lem1801::Lem1801;
+pub use self::lem1802::{Lem1802, DEFAULT_FONT, DEFAULT_PALETTE};
+pub use self::m35fd::{Fault, M35fd};
+pub use self::spc2000::{Environment, Spc2000};
+pub use self::sped3::{Line, Sped3};
+
+/// Cycles in one emulated second, the DCPU-16 runs at 100 kHz
+pub const CLOCK_RATE: u64 = 100_000;
+
 /// Hardware Trait
-trait Hardware {
+pub trait Hardware {
     /// Get Manufacturer ID
-    fn mfg_id(&self) -> Word;
+    fn mfg_id(&self) -> u32;
     /// Get Hardware ID
-    fn hdw_id(&self) -> Word;
-    /// Get Device ID
+    fn hdw_id(&self) -> u32;
+    /// Get Device Version
     fn dev_id(&self) -> Word;
-    /// Trigger Device Interrupt
-    fn interrupt(&mut self, value: Word) -> SystemError;
+    /// Trigger Device Interrupt, returning any additional cycles taken
+    fn interrupt(&mut self, clock: &Clock, registers: &mut Registers, memory: &mut Memory, queue: &mut Queue) -> Result<u16, SystemError>;
     /// Increment Device one Cycle
-    fn update(&mut self, clock: &Clock, registers: &mut Registers, memory: &mut Memory, pic: &mut PIC) -> SystemError;
-}
\ No newline at end of file
+    fn update(&mut self, clock: &Clock, registers: &mut Registers, memory: &mut Memory, queue: &mut Queue) -> Result<(), SystemError>;
+    /// Take the cycles the device has fast-forwarded emulated time by, which the System adds to its Clock
+    fn take_skip(&mut self) -> u64 {
+        0
+    }
+    /// Save opaque Device State, empty for stateless devices
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    /// Check whether state saved by a device with Hardware ID and Device Version can be restored
+    fn compatible(&self, hdw_id: u32, dev_id: Word) -> bool {
+        hdw_id == self.hdw_id() && dev_id == self.dev_id()
+    }
+    /// Restore opaque Device State saved by a compatible device of Device Version
+    fn restore_state(&mut self, dev_id: Word, data: &[u8]) -> Result<(), SystemError> {
+        let _ = dev_id;
+        if data.is_empty() {
+            Ok(())
+        } else {
+            Err(SystemError::IncompatibleHardware)
+        }
+    }
+}
+
+/// Shared devices let the host keep a handle, to render a display or type on a keyboard
+impl<T: Hardware> Hardware for Rc<RefCell<T>> {
+    fn mfg_id(&self) -> u32 {
+        self.borrow().mfg_id()
+    }
+    fn hdw_id(&self) -> u32 {
+        self.borrow().hdw_id()
+    }
+    fn dev_id(&self) -> Word {
+        self.borrow().dev_id()
+    }
+    fn interrupt(&mut self, clock: &Clock, registers: &mut Registers, memory: &mut Memory, queue: &mut Queue) -> Result<u16, SystemError> {
+        self.borrow_mut().interrupt(clock, registers, memory, queue)
+    }
+    fn update(&mut self, clock: &Clock, registers: &mut Registers, memory: &mut Memory, queue: &mut Queue) -> Result<(), SystemError> {
+        self.borrow_mut().update(clock, registers, memory, queue)
+    }
+    fn take_skip(&mut self) -> u64 {
+        self.borrow_mut().take_skip()
+    }
+    fn save_state(&self) -> Vec<u8> {
+        self.borrow().save_state()
+    }
+    fn compatible(&self, hdw_id: u32, dev_id: Word) -> bool {
+        self.borrow().compatible(hdw_id, dev_id)
+    }
+    fn restore_state(&mut self, dev_id: Word, data: &[u8]) -> Result<(), SystemError> {
+        self.borrow_mut().restore_state(dev_id, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Clock, Memory, Queue, Registers, System, SystemError, Word};
+    use super::Hardware;
+
+    /// Device counting cycles, saved as a little endian u32 since version 2
+    struct Counter {
+        version: Word,
+        cycles: u32,
+    }
+
+    impl Hardware for Counter {
+        fn mfg_id(&self) -> u32 { 0x1234_5678 }
+        fn hdw_id(&self) -> u32 { 0xC0C0_0001 }
+        fn dev_id(&self) -> Word { self.version }
+        fn interrupt(&mut self, _: &Clock, registers: &mut Registers, _: &mut Memory, _: &mut Queue) -> Result<u16, SystemError> {
+            registers.a = self.cycles as Word;
+            Ok(0)
+        }
+        fn update(&mut self, _: &Clock, _: &mut Registers, _: &mut Memory, _: &mut Queue) -> Result<(), SystemError> {
+            self.cycles += 1;
+            Ok(())
+        }
+        fn save_state(&self) -> Vec<u8> {
+            self.cycles.to_le_bytes().to_vec()
+        }
+        fn compatible(&self, hdw_id: u32, dev_id: Word) -> bool {
+            hdw_id == self.hdw_id() && dev_id <= self.version
+        }
+        fn restore_state(&mut self, dev_id: Word, data: &[u8]) -> Result<(), SystemError> {
+            // Version 1 only saved the low Word
+            let cycles = match (dev_id, data) {
+                (1, &[lo, hi]) => u16::from_le_bytes([lo, hi]) as u32,
+                (_, &[a, b, c, d]) => u32::from_le_bytes([a, b, c, d]),
+                _ => return Err(SystemError::IncompatibleHardware),
+            };
+            self.cycles = cycles;
+            Ok(())
+        }
+    }
+
+    fn system(version: Word) -> System {
+        let mut sys = System::new();
+        sys.attach(Box::new(Counter { version, cycles: 0 }));
+        sys
+    }
+
+    #[test]
+    pub fn test_device_state() {
+        let mut sys = system(2);
+        for _ in 0..10 {
+            sys.step().unwrap();
+        }
+        let snapshot = sys.snapshot();
+        assert_eq!(&[10, 0, 0, 0], &snapshot.devices[0].data[..]);
+
+        // Restoring resumes the device where it left off
+        let mut copy = system(2);
+        copy.restore(&snapshot).unwrap();
+        assert_eq!(snapshot.devices, copy.snapshot().devices);
+
+        // Newer devices accept older versions of their state
+        let mut old = snapshot.clone();
+        old.devices[0].dev_id = 1;
+        old.devices[0].data = vec![7, 0];
+        let mut newer = system(3);
+        newer.restore(&old).unwrap();
+        assert_eq!(&[7, 0, 0, 0], &newer.snapshot().devices[0].data[..]);
+
+        // Older devices reject newer state and leave the System untouched
+        let mut older = system(1);
+        older.registers_mut().a = 0x55;
+        assert_eq!(SystemError::IncompatibleHardware, older.restore(&snapshot).unwrap_err());
+        assert_eq!(0x55, older.registers().a);
+
+        // Invalid state for a later device leaves earlier devices untouched
+        let mut pair = system(2);
+        pair.attach(Box::new(Counter { version: 2, cycles: 0 }));
+        pair.step().unwrap();
+        let mut invalid = pair.snapshot();
+        invalid.devices[0].data = vec![9, 0, 0, 0];
+        invalid.devices[1].data = vec![9];
+        assert_eq!(SystemError::IncompatibleHardware, pair.restore(&invalid).unwrap_err());
+        assert_eq!(vec![1, 0, 0, 0], pair.snapshot().devices[0].data);
+    }
+}
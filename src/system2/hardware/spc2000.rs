@@ -0,0 +1,336 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! SPC2000 Suspension Chamber 2000
+//!
+//! Triggering fast-forwards emulated time: the System advances its Clock by the skip once
+//! the HWI completes, so every device timed by the Clock sees the time pass.
+//!
+//! The 64 bit skip is read from four words at B, most significant word first. A program
+//! storing it least significant word first, as happened to the vessel of the archive's
+//! backstory, skips 2^48 times as long. Skips stop at Clock::LIMIT, which leaves devices room
+//! to schedule deadlines and the Clock room to keep counting.
+//!
+//! GET_STATUS sets C to 1 when ready. The specification's TRIGGER_DEVICE says it triggers
+//! when C is 0, which contradicts this, so the chamber triggers when GET_STATUS is ready.
+
+use system2::{Clock, Memory, Queue, Registers, SystemError, Word};
+use super::{Hardware, CLOCK_RATE};
+
+/// Conditions around the chamber which the host controls
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Environment {
+    /// Vessel is in a near vacuum
+    pub vacuum: bool,
+    /// Enough fuel to trigger
+    pub fuel: bool,
+    /// Gravitational field is even
+    pub even_gravity: bool,
+    /// Vessel is rotating
+    pub rotating: bool,
+    /// Vessel is accelerating
+    pub accelerating: bool,
+    /// A cell door is open
+    pub doors_open: bool,
+    /// Chamber has a mechanical fault
+    pub mechanical_error: bool,
+}
+
+impl Default for Environment {
+    fn default() -> Environment {
+        Environment {
+            vacuum: true,
+            fuel: true,
+            even_gravity: true,
+            rotating: false,
+            accelerating: false,
+            doors_open: false,
+            mechanical_error: false,
+        }
+    }
+}
+
+impl Environment {
+    /// GET_STATUS code preventing a trigger, None when ready
+    pub fn status(&self) -> Option<Word> {
+        if self.accelerating {
+            Some(Spc2000::STATUS_EVACUATE)
+        } else if !self.vacuum {
+            Some(Spc2000::STATUS_NO_VACUUM)
+        } else if !self.fuel {
+            Some(Spc2000::STATUS_NO_FUEL)
+        } else if !self.even_gravity {
+            Some(Spc2000::STATUS_UNEVEN_GRAVITY)
+        } else if self.rotating {
+            Some(Spc2000::STATUS_ANGULAR_MOMENTUM)
+        } else if self.doors_open {
+            Some(Spc2000::STATUS_DOORS_OPEN)
+        } else if self.mechanical_error {
+            Some(Spc2000::STATUS_MECHANICAL_ERROR)
+        } else {
+            None
+        }
+    }
+}
+
+/// SPC2000 Suspension Chamber
+#[derive(Clone, Debug, Default)]
+pub struct Spc2000 {
+    /// Conditions around the chamber
+    pub environment: Environment,
+    /// Size of a unit, 0 to 3 for milliseconds, minutes, days and years
+    unit: Word,
+    /// Units to skip
+    skip: u64,
+    /// Cycles skipped by a trigger, not yet added to the Clock
+    pending: u64,
+    /// Cycles skipped by every trigger
+    skipped: u64,
+}
+
+impl Spc2000 {
+    /// Accelerating, evacuate vessel immediately
+    pub const STATUS_EVACUATE: Word = 0x0000;
+    /// Not in a vacuum
+    pub const STATUS_NO_VACUUM: Word = 0x0001;
+    /// Not enough fuel
+    pub const STATUS_NO_FUEL: Word = 0x0002;
+    /// Gravitational forces too uneven
+    pub const STATUS_UNEVEN_GRAVITY: Word = 0x0003;
+    /// Too much angular momentum
+    pub const STATUS_ANGULAR_MOMENTUM: Word = 0x0004;
+    /// One or more cell doors are open
+    pub const STATUS_DOORS_OPEN: Word = 0x0005;
+    /// Mechanical error
+    pub const STATUS_MECHANICAL_ERROR: Word = 0x0006;
+    /// Unknown error, evacuate vessel immediately
+    pub const STATUS_UNKNOWN: Word = 0xFFFF;
+    /// Skip milliseconds
+    pub const UNIT_MILLISECONDS: Word = 0x0000;
+    /// Skip minutes
+    pub const UNIT_MINUTES: Word = 0x0001;
+    /// Skip days
+    pub const UNIT_DAYS: Word = 0x0002;
+    /// Skip years
+    pub const UNIT_YEARS: Word = 0x0003;
+
+    /// Create an SPC2000 ready to skip 0 milliseconds
+    pub fn new() -> Spc2000 {
+        Spc2000::default()
+    }
+    /// Units to skip
+    pub fn skip(&self) -> u64 {
+        self.skip
+    }
+    /// Size of a unit
+    pub fn unit(&self) -> Word {
+        self.unit
+    }
+    /// Cycles skipped by every trigger
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+    /// Cycles in one unit
+    fn unit_cycles(&self) -> u64 {
+        match self.unit {
+            Spc2000::UNIT_MILLISECONDS => CLOCK_RATE / 1_000,
+            Spc2000::UNIT_MINUTES => CLOCK_RATE * 60,
+            Spc2000::UNIT_DAYS => CLOCK_RATE * 60 * 60 * 24,
+            _ => CLOCK_RATE * 60 * 60 * 24 * 365,
+        }
+    }
+    /// Perform GET_STATUS, returning whether the chamber is ready
+    fn status(&self, registers: &mut Registers) -> bool {
+        match self.environment.status() {
+            Some(status) => {
+                registers.b = status;
+                registers.c = 0;
+                false
+            }
+            None => {
+                registers.c = 1;
+                true
+            }
+        }
+    }
+}
+
+impl Hardware for Spc2000 {
+    fn mfg_id(&self) -> u32 {
+        0x1C6C_8B36
+    }
+    fn hdw_id(&self) -> u32 {
+        0x40E4_1D9D
+    }
+    fn dev_id(&self) -> Word {
+        0x005E
+    }
+    fn interrupt(&mut self, _: &Clock, registers: &mut Registers, memory: &mut Memory, _: &mut Queue) -> Result<u16, SystemError> {
+        let command = registers.a;
+        match command {
+            0 => {
+                self.status(registers);
+            }
+            1 => {
+                self.skip = (0..4).fold(0, |skip, offset| skip << 16 | memory.get(registers.b.wrapping_add(offset)) as u64);
+            }
+            2 if self.status(registers) => {
+                let cycles = self.skip.saturating_mul(self.unit_cycles());
+                self.pending = self.pending.saturating_add(cycles);
+                self.skipped = self.skipped.saturating_add(cycles);
+            }
+            3 if registers.b <= Spc2000::UNIT_YEARS => self.unit = registers.b,
+            _ => {}
+        }
+        Ok(0)
+    }
+    fn update(&mut self, _: &Clock, _: &mut Registers, _: &mut Memory, _: &mut Queue) -> Result<(), SystemError> {
+        Ok(())
+    }
+    fn take_skip(&mut self) -> u64 {
+        let pending = self.pending;
+        self.pending = 0;
+        pending
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let environment = &self.environment;
+        let flags = [environment.vacuum, environment.fuel, environment.even_gravity, environment.rotating,
+                     environment.accelerating, environment.doors_open, environment.mechanical_error];
+        let mut data = Vec::with_capacity(19);
+        data.extend_from_slice(&self.unit.to_le_bytes());
+        data.extend_from_slice(&self.skip.to_le_bytes());
+        data.extend_from_slice(&self.skipped.to_le_bytes());
+        data.push(flags.iter().enumerate().fold(0, |bits, (index, &flag)| bits | (flag as u8) << index));
+        data
+    }
+    fn restore_state(&mut self, _: Word, data: &[u8]) -> Result<(), SystemError> {
+        if data.len() != 19 {
+            return Err(SystemError::IncompatibleHardware);
+        }
+        let long = |index: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&data[index..index + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        let flag = |index: u8| data[18] & (1 << index) != 0;
+        self.unit = u16::from_le_bytes([data[0], data[1]]);
+        self.skip = long(2);
+        self.skipped = long(10);
+        self.pending = 0;
+        self.environment = Environment {
+            vacuum: flag(0),
+            fuel: flag(1),
+            even_gravity: flag(2),
+            rotating: flag(3),
+            accelerating: flag(4),
+            doors_open: flag(5),
+            mechanical_error: flag(6),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use assembler::assemble;
+    use system2::{Breakpoint, Clock, Event, Hardware, Queue, Registers, Stop, System, Trigger};
+    use super::super::{Disk, GenericClock, M35fd, CLOCK_RATE};
+    use super::Spc2000;
+
+    fn system(words: &[u16]) -> (System, Rc<RefCell<Spc2000>>, Rc<RefCell<GenericClock>>) {
+        let program = assemble(
+            "\x20       SET A, 0\n\
+             \x20       SET B, 1\n\
+             \x20       HWI 1\n\
+             \x20       SET A, 3\n\
+             \x20       SET B, 1\n\
+             \x20       HWI 0\n\
+             \x20       SET A, 1\n\
+             \x20       SET B, skip\n\
+             \x20       HWI 0\n\
+             \x20       SET A, 2\n\
+             \x20       HWI 0\n\
+             :halt   SET PC, halt\n\
+             :skip   DAT 0, 0, 0, 0\n",
+        ).unwrap();
+        let chamber = Rc::new(RefCell::new(Spc2000::new()));
+        let clock = Rc::new(RefCell::new(GenericClock::new()));
+        let mut sys = System::new();
+        sys.attach(Box::new(chamber.clone()));
+        sys.attach(Box::new(clock.clone()));
+        sys.memory_mut().write(0, &program.words).unwrap();
+        let skip = program.symbols.label("skip").unwrap();
+        sys.memory_mut().write(skip, words).unwrap();
+        (sys, chamber, clock)
+    }
+
+    #[test]
+    pub fn test_spc2000() {
+        // Two minutes pass for the Generic Clock in a single HWI
+        let (mut sys, chamber, clock) = system(&[0, 0, 0, 2]);
+        for _ in 0..12 {
+            sys.step_instruction().unwrap();
+        }
+        assert_eq!(1, sys.registers().c);
+        assert_eq!(2 * 60 * CLOCK_RATE, chamber.borrow().skipped());
+        assert!(sys.clock().cycles() > 2 * 60 * CLOCK_RATE);
+        assert_eq!(2 * 60 * 60, clock.borrow().ticks());
+
+        // Cycle Breakpoints skipped over stop the System
+        let (mut sys, _, _) = system(&[0, 0, 0, 2]);
+        let id = sys.add_breakpoint(Breakpoint::new(Trigger::Cycle(CLOCK_RATE)));
+        assert_eq!(Some(Stop { id, event: Event::Cycle(CLOCK_RATE) }), sys.run(100).unwrap());
+        assert!(sys.clock().cycles() > 2 * 60 * CLOCK_RATE);
+
+        // Least significant word first skips 2^48 minutes, as far as the Clock goes
+        let (mut sys, chamber, _) = system(&[2, 0, 0, 0]);
+        for _ in 0..12 {
+            sys.step_instruction().unwrap();
+        }
+        assert_eq!(2 << 48, chamber.borrow().skip());
+        let cycles = sys.clock().cycles();
+        assert!((Clock::LIMIT..Clock::LIMIT + 10).contains(&cycles));
+        sys.step_instruction().unwrap();
+        assert!(sys.clock().cycles() > cycles);
+
+        // Devices still complete commands after the skip
+        let mut clock = *sys.clock();
+        let mut drive = M35fd::new();
+        let mut registers = Registers::new();
+        drive.insert(Disk::blank());
+        registers.a = 2;
+        registers.y = 0x1000;
+        drive.interrupt(&clock, &mut registers, sys.memory_mut(), &mut Queue::new()).unwrap();
+        assert_eq!((1, M35fd::STATE_BUSY), (registers.b, drive.state()));
+        while drive.state() == M35fd::STATE_BUSY {
+            clock.step().unwrap();
+            drive.update(&clock, &mut registers, sys.memory_mut(), &mut Queue::new()).unwrap();
+        }
+        assert_eq!(M35fd::STATE_READY, drive.state());
+
+        // Nothing happens outside a vacuum
+        let (mut sys, chamber, _) = system(&[0, 0, 0, 2]);
+        chamber.borrow_mut().environment.vacuum = false;
+        for _ in 0..12 {
+            sys.step_instruction().unwrap();
+        }
+        assert_eq!((Spc2000::STATUS_NO_VACUUM, 0), (sys.registers().b, sys.registers().c));
+        assert_eq!(0, chamber.borrow().skipped());
+        assert!(sys.clock().cycles() < 100);
+    }
+}
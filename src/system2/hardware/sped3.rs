@@ -0,0 +1,283 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Mackapar Suspended Particle Exciter Display, Rev 3
+//!
+//! Lines join consecutive vertices mapped into DCPU-16 memory, each drawn in the color of
+//! the vertex it starts from. The emitters turn about the Z axis at 50 degrees per emulated
+//! second, taking the shorter way round to the target rotation.
+//!
+//! For inspection the model is projected as seen from 30 degrees above the horizon, looking
+//! along the Y axis with Z up, centered on the middle of the 256 unit cube. The projection
+//! can be drawn as SVG or into a Framebuffer.
+
+use std::f64::consts::PI;
+use std::fmt::Write;
+use system2::{Clock, Memory, Queue, Registers, SystemError, Word};
+use super::{Framebuffer, Hardware, CLOCK_RATE};
+
+/// Most lines projected
+const LINES: Word = 128;
+
+/// Rotation steps in a degree, one step a cycle at 50 degrees a second
+const STEPS: u32 = (CLOCK_RATE / 50) as u32;
+
+/// Rotation steps in a full turn
+const TURN: u32 = 360 * STEPS;
+
+/// Projected line in image coordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Line {
+    /// Start point
+    pub from: (f64, f64),
+    /// End point
+    pub to: (f64, f64),
+    /// Color as 0x00RRGGBB
+    pub color: u32,
+}
+
+/// SPED-3 Vector Display
+#[derive(Clone, Debug, Default)]
+pub struct Sped3 {
+    /// Vertex memory address
+    address: Word,
+    /// Vertices mapped
+    vertices: Word,
+    /// Target rotation in degrees
+    target: Word,
+    /// Current rotation in steps
+    angle: u32,
+    /// Cycle of the last update
+    last: u64,
+    error: Word,
+}
+
+impl Sped3 {
+    /// No vertices queued up, device is in stand-by
+    pub const STATE_NO_DATA: Word = 0x0000;
+    /// The device is projecting lines
+    pub const STATE_RUNNING: Word = 0x0001;
+    /// The device is projecting lines and turning
+    pub const STATE_TURNING: Word = 0x0002;
+    /// There's been no error since the last poll
+    pub const ERROR_NONE: Word = 0x0000;
+    /// There's been some major software or hardware problem
+    pub const ERROR_BROKEN: Word = 0xFFFF;
+
+    /// Create a SPED-3 in stand-by
+    pub fn new() -> Sped3 {
+        Sped3::default()
+    }
+    /// Current state code
+    pub fn state(&self) -> Word {
+        if self.vertices == 0 {
+            Sped3::STATE_NO_DATA
+        } else if self.angle != self.target as u32 * STEPS {
+            Sped3::STATE_TURNING
+        } else {
+            Sped3::STATE_RUNNING
+        }
+    }
+    /// Current rotation in degrees
+    pub fn rotation(&self) -> f64 {
+        self.angle as f64 / STEPS as f64
+    }
+    /// Target rotation in degrees
+    pub fn target(&self) -> Word {
+        self.target
+    }
+    /// Project the lines of the model into a square image of size pixels
+    pub fn project(&self, memory: &Memory, size: usize) -> Vec<Line> {
+        let (sin, cos) = (self.rotation() * PI / 180.0).sin_cos();
+        let (tilt_sin, tilt_cos) = (PI / 6.0).sin_cos();
+        let scale = size as f64 / 384.0;
+        let vertex = |index: Word| {
+            let address = self.address.wrapping_add(index * 2);
+            let (first, second) = (memory.get(address), memory.get(address.wrapping_add(1)));
+            let x = (first & 0xFF) as f64 - 128.0;
+            let y = (first >> 8) as f64 - 128.0;
+            let z = (second & 0xFF) as f64 - 128.0;
+            let (rx, ry) = (x * cos - y * sin, x * sin + y * cos);
+            let point = (size as f64 / 2.0 + rx * scale, size as f64 / 2.0 - (ry * tilt_sin + z * tilt_cos) * scale);
+            (point, color(second >> 8))
+        };
+        let count = self.vertices.saturating_sub(1).min(LINES);
+        (0..count)
+            .map(|index| {
+                let (from, color) = vertex(index);
+                let (to, _) = vertex(index + 1);
+                Line { from, to, color }
+            })
+            .collect()
+    }
+    /// Draw the projected model as an SVG document on black
+    pub fn svg(&self, memory: &Memory, size: usize) -> String {
+        let mut out = String::new();
+        writeln!(out, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{0}\" viewBox=\"0 0 {0} {0}\">", size).unwrap();
+        writeln!(out, "  <rect width=\"{0}\" height=\"{0}\" fill=\"#000000\"/>", size).unwrap();
+        for line in self.project(memory, size) {
+            writeln!(out, "  <line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"#{:06X}\"/>",
+                     line.from.0, line.from.1, line.to.0, line.to.1, line.color).unwrap();
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+    /// Draw the projected model into a square Framebuffer on black
+    pub fn render(&self, memory: &Memory, size: usize) -> Framebuffer {
+        let mut frame = Framebuffer::new(size, size, 0);
+        for line in self.project(memory, size) {
+            let steps = (line.to.0 - line.from.0).abs().max((line.to.1 - line.from.1).abs()).ceil().max(1.0) as usize;
+            for step in 0..=steps {
+                let t = step as f64 / steps as f64;
+                let x = (line.from.0 + (line.to.0 - line.from.0) * t).round();
+                let y = (line.from.1 + (line.to.1 - line.from.1) * t).round();
+                if x >= 0.0 && y >= 0.0 && (x as usize) < size && (y as usize) < size {
+                    frame.set(x as usize, y as usize, line.color);
+                }
+            }
+        }
+        frame
+    }
+}
+
+/// Color of the ICC bits of a vertex, dim unless intense
+fn color(bits: Word) -> u32 {
+    let level = if bits & 0x4 != 0 { 0xFF } else { 0x80 };
+    match bits & 0x3 {
+        0 => if bits & 0x4 != 0 { 0x606060 } else { 0x202020 },
+        1 => level << 16,
+        2 => level << 8,
+        _ => level,
+    }
+}
+
+impl Hardware for Sped3 {
+    fn mfg_id(&self) -> u32 {
+        0x1EB3_7E91
+    }
+    fn hdw_id(&self) -> u32 {
+        0x42BA_BF3C
+    }
+    fn dev_id(&self) -> Word {
+        0x0003
+    }
+    fn interrupt(&mut self, _: &Clock, registers: &mut Registers, _: &mut Memory, _: &mut Queue) -> Result<u16, SystemError> {
+        match registers.a {
+            0 => {
+                registers.b = self.state();
+                registers.c = self.error;
+                self.error = Sped3::ERROR_NONE;
+            }
+            1 => {
+                self.address = registers.x;
+                self.vertices = registers.y;
+            }
+            2 => self.target = registers.x % 360,
+            _ => {}
+        }
+        Ok(0)
+    }
+    fn update(&mut self, clock: &Clock, _: &mut Registers, _: &mut Memory, _: &mut Queue) -> Result<(), SystemError> {
+        // Turn a step for every cycle since the last update, which is many when time is skipped
+        let elapsed = clock.cycles().saturating_sub(self.last).min(TURN as u64) as u32;
+        self.last = clock.cycles();
+        let target = self.target as u32 * STEPS;
+        if self.angle != target {
+            let forward = (target + TURN - self.angle) % TURN;
+            self.angle = if forward <= TURN / 2 {
+                (self.angle + forward.min(elapsed)) % TURN
+            } else {
+                (self.angle + TURN - (TURN - forward).min(elapsed)) % TURN
+            };
+        }
+        Ok(())
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(20);
+        for word in &[self.address, self.vertices, self.target, self.error] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data.extend_from_slice(&self.angle.to_le_bytes());
+        data.extend_from_slice(&self.last.to_le_bytes());
+        data
+    }
+    fn restore_state(&mut self, _: Word, data: &[u8]) -> Result<(), SystemError> {
+        if data.len() != 20 {
+            return Err(SystemError::IncompatibleHardware);
+        }
+        let word = |index: usize| u16::from_le_bytes([data[index], data[index + 1]]);
+        self.address = word(0);
+        self.vertices = word(2);
+        self.target = word(4) % 360;
+        self.error = word(6);
+        self.angle = u32::from_le_bytes([data[8], data[9], data[10], data[11]]) % TURN;
+        let mut last = [0; 8];
+        last.copy_from_slice(&data[12..20]);
+        self.last = u64::from_le_bytes(last);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use system2::{Clock, Hardware, Memory, Queue, Registers, Word};
+    use super::super::CLOCK_RATE;
+    use super::Sped3;
+
+    /// Send a command, returning B and C
+    fn command(sped: &mut Sped3, a: Word, x: Word, y: Word) -> (Word, Word) {
+        let mut registers = Registers::new();
+        registers.a = a;
+        registers.x = x;
+        registers.y = y;
+        sped.interrupt(&Clock::new(), &mut registers, &mut Memory::new(), &mut Queue::new()).unwrap();
+        (registers.b, registers.c)
+    }
+
+    #[test]
+    pub fn test_sped3() {
+        let mut sped = Sped3::new();
+        let (mut clock, mut registers, mut memory, mut queue) = (Clock::new(), Registers::new(), Memory::new(), Queue::new());
+        assert_eq!((Sped3::STATE_NO_DATA, Sped3::ERROR_NONE), command(&mut sped, 0, 0, 0));
+
+        // Intense red line along X then a dim blue line along Y, at mid height
+        memory.write(0x1000, &[0x8000, 0x0580, 0x80FF, 0x0380, 0xFFFF, 0x0080]).unwrap();
+        command(&mut sped, 1, 0x1000, 3);
+        assert_eq!(Sped3::STATE_RUNNING, command(&mut sped, 0, 0, 0).0);
+        let lines = sped.project(&memory, 384);
+        assert_eq!(2, lines.len());
+        assert_eq!(((64.0, 192.0), (319.0, 192.0), 0xFF0000), (lines[0].from, lines[0].to, lines[0].color));
+        assert_eq!(0x000080, lines[1].color);
+        let frame = sped.render(&memory, 384);
+        assert_eq!(0xFF0000, frame.get(100, 192));
+        assert_eq!(2, sped.svg(&memory, 384).matches("<line ").count());
+
+        // Turning to 90 degrees takes 1.8 seconds, the short way round
+        command(&mut sped, 2, 450, 0);
+        assert_eq!(Sped3::STATE_TURNING, command(&mut sped, 0, 0, 0).0);
+        while clock.cycles() < CLOCK_RATE * 18 / 10 {
+            clock.step().unwrap();
+            sped.update(&clock, &mut registers, &mut memory, &mut queue).unwrap();
+        }
+        assert_eq!((90.0, Sped3::STATE_RUNNING), (sped.rotation(), sped.state()));
+        let lines = sped.project(&memory, 384);
+        assert!((lines[0].from.0 - 192.0).abs() < 1e-9 && (lines[0].to.0 - 192.0).abs() < 1e-9);
+        command(&mut sped, 2, 80, 0);
+        clock.step().unwrap();
+        sped.update(&clock, &mut registers, &mut memory, &mut queue).unwrap();
+        assert!(sped.rotation() < 90.0);
+    }
+}
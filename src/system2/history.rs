@@ -0,0 +1,98 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use std::collections::VecDeque;
+use super::CallStack;
+use super::Clock;
+use super::Queue;
+use super::Registers;
+use super::State;
+use super::Word;
+
+/// Changes made by a single clock cycle, recorded before it ran
+pub(super) struct Record {
+    /// Registers before the cycle
+    pub registers: Registers,
+    /// Clock before the cycle
+    pub clock: Clock,
+    /// CPU State before the cycle
+    pub state: State,
+    /// Interrupt Request Queue before the cycle, if the cycle changed it
+    pub queue: Option<Queue>,
+    /// Shadow call stack before the cycle, if the cycle changed it
+    pub calls: Option<CallStack>,
+    /// Address and previous value of each Memory write in order
+    pub writes: Vec<(Word, Word)>,
+    /// Address of each Memory read in order
+    pub reads: Vec<Word>,
+}
+
+/// Bounded undo log of the most recent clock cycles
+///
+/// Hardware keeps its own internal state when the System steps backwards, only
+/// the Registers, Memory, Clock and Interrupt Queue it touched are rewound.
+pub struct History {
+    records: VecDeque<Record>,
+    capacity: usize,
+}
+
+impl History {
+    /// Create an empty History holding at most capacity cycles
+    pub fn new(capacity: usize) -> History {
+        History {
+            records: VecDeque::new(),
+            capacity,
+        }
+    }
+    /// Maximum number of cycles held
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    /// Number of cycles which can be stepped back
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+    /// Is there nothing to step back
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+    /// Number of whole instructions which can be stepped back
+    pub fn instructions(&self) -> usize {
+        self.records.iter().filter(|record| record.state == State::Idle).count()
+    }
+    /// Forget all recorded cycles
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+    /// Record a cycle, dropping the oldest when full
+    pub(super) fn push(&mut self, record: Record) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+    /// Is there the start of an instruction to step back to
+    pub(super) fn has_instruction(&self) -> bool {
+        self.records.iter().rev().any(|record| record.state == State::Idle)
+    }
+    /// Remove the most recent cycle
+    pub(super) fn pop(&mut self) -> Option<Record> {
+        self.records.pop_back()
+    }
+}
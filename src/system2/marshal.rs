@@ -0,0 +1,215 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::Word;
+
+/// How characters of a string are stored in Memory
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Packing {
+    /// One character per Word
+    Unpacked,
+    /// Two characters per Word, high byte first
+    Packed,
+}
+
+/// Fixed layout value which can be exchanged with guest Memory
+///
+/// Implement for records with `impl_marshal!`.
+pub trait Marshal: Sized {
+    /// Number of Words occupied in Memory
+    fn size() -> usize;
+    /// Decode value from exactly `size()` Words
+    fn decode(words: &[Word]) -> Self;
+    /// Encode value into exactly `size()` Words
+    fn encode(&self, words: &mut [Word]);
+}
+
+impl Marshal for u16 {
+    fn size() -> usize { 1 }
+    fn decode(words: &[Word]) -> u16 { words[0] }
+    fn encode(&self, words: &mut [Word]) { words[0] = *self }
+}
+
+impl Marshal for i16 {
+    fn size() -> usize { 1 }
+    fn decode(words: &[Word]) -> i16 { words[0] as i16 }
+    fn encode(&self, words: &mut [Word]) { words[0] = *self as Word }
+}
+
+impl Marshal for bool {
+    fn size() -> usize { 1 }
+    fn decode(words: &[Word]) -> bool { words[0] != 0 }
+    fn encode(&self, words: &mut [Word]) { words[0] = *self as Word }
+}
+
+/// Little endian Word pair, low Word first
+impl Marshal for u32 {
+    fn size() -> usize { 2 }
+    fn decode(words: &[Word]) -> u32 { (words[0] as u32) | (words[1] as u32) << 16 }
+    fn encode(&self, words: &mut [Word]) {
+        words[0] = *self as Word;
+        words[1] = (*self >> 16) as Word;
+    }
+}
+
+/// Little endian Word pair, low Word first
+impl Marshal for i32 {
+    fn size() -> usize { 2 }
+    fn decode(words: &[Word]) -> i32 { u32::decode(words) as i32 }
+    fn encode(&self, words: &mut [Word]) { (*self as u32).encode(words) }
+}
+
+impl<T: Marshal + Copy + Default, const N: usize> Marshal for [T; N] {
+    fn size() -> usize { T::size() * N }
+    fn decode(words: &[Word]) -> [T; N] {
+        let mut values = [T::default(); N];
+        for (value, chunk) in values.iter_mut().zip(words.chunks(T::size())) {
+            *value = T::decode(chunk);
+        }
+        values
+    }
+    fn encode(&self, words: &mut [Word]) {
+        for (value, chunk) in self.iter().zip(words.chunks_mut(T::size())) {
+            value.encode(chunk);
+        }
+    }
+}
+
+/// Implement `Marshal` for a struct by laying out its fields in declaration order
+///
+/// ```
+/// #[macro_use]
+/// extern crate vcpu16;
+///
+/// use vcpu16::system2::{Marshal, Memory, Word};
+///
+/// #[derive(Debug, PartialEq)]
+/// struct Entry {
+///     kind: Word,
+///     length: u32,
+///     sectors: [Word; 2],
+/// }
+///
+/// impl_marshal!(Entry { kind: Word, length: u32, sectors: [Word; 2] });
+///
+/// fn main() {
+///     let mut mem = Memory::new();
+///     let entry = Entry { kind: 1, length: 0x0001_0002, sectors: [3, 4] };
+///     mem.write_value(0x1000, &entry).unwrap();
+///     assert_eq!(&[1, 2, 1, 3, 4], mem.read(0x1000, 5).unwrap());
+///     assert_eq!(entry, mem.read_value::<Entry>(0x1000).unwrap());
+/// }
+/// ```
+#[macro_export]
+macro_rules! impl_marshal {
+    ($name:ident { $($field:ident : $kind:ty),* $(,)* }) => {
+        impl $crate::system2::Marshal for $name {
+            fn size() -> usize {
+                0 $(+ <$kind as $crate::system2::Marshal>::size())*
+            }
+            fn decode(words: &[$crate::system2::Word]) -> $name {
+                let mut offset = 0;
+                $(
+                    let size = <$kind as $crate::system2::Marshal>::size();
+                    let $field = <$kind as $crate::system2::Marshal>::decode(&words[offset..offset + size]);
+                    offset += size;
+                )*
+                let _ = offset;
+                $name { $($field),* }
+            }
+            fn encode(&self, words: &mut [$crate::system2::Word]) {
+                let mut offset = 0;
+                $(
+                    let size = <$kind as $crate::system2::Marshal>::size();
+                    $crate::system2::Marshal::encode(&self.$field, &mut words[offset..offset + size]);
+                    offset += size;
+                )*
+                let _ = offset;
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Memory, SystemError, Word};
+    use super::{Marshal, Packing};
+
+    #[derive(Debug, PartialEq)]
+    struct Record {
+        id: Word,
+        offset: i16,
+        active: bool,
+        size: u32,
+        delta: i32,
+        table: [u32; 2],
+    }
+
+    impl_marshal!(Record { id: Word, offset: i16, active: bool, size: u32, delta: i32, table: [u32; 2] });
+
+    #[test]
+    pub fn test_u32() {
+        let mut mem = Memory::new();
+
+        mem.set_u32(0x0010, 0x1234_5678).unwrap();
+
+        assert_eq!(&[0x5678, 0x1234], mem.read(0x0010, 2).unwrap());
+        assert_eq!(0x1234_5678, mem.get_u32(0x0010).unwrap());
+        assert_eq!(SystemError::AddressOverflow, mem.set_u32(0xFFFF, 0).unwrap_err());
+    }
+
+    #[test]
+    pub fn test_strings() {
+        let mut mem = Memory::new();
+
+        assert_eq!(6, mem.write_string(0x0100, "Hello", Packing::Unpacked).unwrap());
+        assert_eq!(3, mem.write_string(0x0200, "Hello", Packing::Packed).unwrap());
+        assert_eq!(3, mem.write_string(0x0300, "Hey!", Packing::Packed).unwrap());
+
+        assert_eq!(&[0x48, 0x65, 0x6C, 0x6C, 0x6F, 0x00], mem.read(0x0100, 6).unwrap());
+        assert_eq!(&[0x4865, 0x6C6C, 0x6F00], mem.read(0x0200, 3).unwrap());
+        assert_eq!(&[0x4865, 0x7921, 0x0000], mem.read(0x0300, 3).unwrap());
+        assert_eq!("Hello", mem.read_string(0x0100, Packing::Unpacked).unwrap());
+        assert_eq!("Hello", mem.read_string(0x0200, Packing::Packed).unwrap());
+        assert_eq!("Hey!", mem.read_string(0x0300, Packing::Packed).unwrap());
+
+        mem.set(0xFFFF, 0x41);
+        assert_eq!(SystemError::AddressOverflow, mem.read_string(0xFFFF, Packing::Unpacked).unwrap_err());
+    }
+
+    #[test]
+    pub fn test_record() {
+        let mut mem = Memory::new();
+        let record = Record {
+            id: 0x0102,
+            offset: -2,
+            active: true,
+            size: 0x0003_0004,
+            delta: -1,
+            table: [0x0005_0006, 0x0007_0008],
+        };
+
+        assert_eq!(11, Record::size());
+        mem.write_value(0x2000, &record).unwrap();
+
+        assert_eq!(
+            &[0x0102, 0xFFFE, 0x0001, 0x0004, 0x0003, 0xFFFF, 0xFFFF, 0x0006, 0x0005, 0x0008, 0x0007],
+            mem.read(0x2000, 11).unwrap()
+        );
+        assert_eq!(record, mem.read_value::<Record>(0x2000).unwrap());
+        assert_eq!(SystemError::AddressOverflow, mem.read_value::<Record>(0xFFF8).unwrap_err());
+    }
+}
@@ -20,12 +20,81 @@ use std::io::{Read, Write};
 use std::mem;
 use std::slice;
 use super::Word;
+use super::{Marshal, Packing};
 use super::SystemError;
+use super::View;
+
+/// Number of Words in a Memory Page
+pub const PAGE_SIZE: usize = 64;
+
+/// Number of Pages in Memory
+pub const PAGE_COUNT: usize = 65536 / PAGE_SIZE;
+
+/// Point in time from which Memory writes are tracked
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Marker(u64);
+
+/// A single Word which differs between two Memory states
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Change {
+    /// Address of the Word
+    pub address: Word,
+    /// Value in the earlier state
+    pub old: Word,
+    /// Value in the later state
+    pub new: Word,
+}
+
+/// Word level difference between two Memory states
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Diff {
+    changes: Vec<Change>,
+}
+
+impl Diff {
+    /// Are both states identical
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+    /// Number of changed Words
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+    /// Changed Words in ascending address order
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+    /// Change at address if the Word differs
+    pub fn get(&self, address: Word) -> Option<Change> {
+        self.changes
+            .binary_search_by_key(&address, |change| change.address)
+            .ok()
+            .map(|index| self.changes[index])
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.changes.is_empty() {
+            return writeln!(f, "No Changes");
+        }
+        for change in &self.changes {
+            writeln!(f, "0x{:04X}: {:04X} -> {:04X}", change.address, change.old, change.new)?;
+        }
+        Ok(())
+    }
+}
 
 /// Memory Array
 #[derive(Clone)]
 pub struct Memory {
     buffer: [Word; 65536],
+    /// Current write generation
+    generation: u64,
+    /// Generation of the last write to each page
+    pages: [u64; PAGE_COUNT],
+    /// Previous value of each written Word while journaling
+    journal: Option<Vec<(Word, Word)>>,
 }
 
 impl Memory {
@@ -35,12 +104,16 @@ impl Memory {
     pub fn new() -> Memory {
         Memory {
             buffer: [0; 65536],
+            generation: 0,
+            pages: [0; PAGE_COUNT],
+            journal: None,
         }
     }
     ///
     /// Load Memory from Reader
     ///
-    pub fn load(&mut self, reader: &mut Read) {
+    pub fn load(&mut self, reader: &mut dyn Read) {
+        self.touch(0, self.buffer.len());
         unsafe {
             let memory_size = mem::size_of_val(&self.buffer);
             let memory_slice = slice::from_raw_parts_mut(
@@ -53,14 +126,14 @@ impl Memory {
     ///
     /// Save memory to writer
     ///
-    pub fn save(&mut self, writer: &mut Write) {
+    pub fn save(&mut self, writer: &mut dyn Write) {
         unsafe {
             let memory_size = mem::size_of_val(&self.buffer);
             let memory_slice = slice::from_raw_parts_mut(
                 &mut self.buffer as *mut _ as *mut u8,
                 memory_size,
             );
-            writer.write(memory_slice).unwrap();
+            writer.write_all(memory_slice).unwrap();
         }
     }
     ///
@@ -68,33 +141,37 @@ impl Memory {
     ///
     pub fn clear(&mut self) {
         self.buffer = [0; 65536];
+        self.touch(0, self.buffer.len());
     }
     ///
     /// Write a slice of memory from buffer
     ///
     pub fn write(&mut self, address: Word, buffer: &[Word]) -> Result<(), SystemError> {
-        if address as usize + buffer.len() > 65535 {
+        if address as usize + buffer.len() > 65536 {
             return Err(SystemError::AddressOverflow);
         }
         let start = address as usize;
         let end = start + buffer.len();
-        Ok(self.buffer[start..end].copy_from_slice(buffer))
+        self.touch(start, end);
+        self.buffer[start..end].copy_from_slice(buffer);
+        Ok(())
     }
     ///
     /// Read a slice length of memory at address
     ///
     pub fn read(&mut self, address: Word, length: Word) -> Result<&[Word], SystemError> {
-        if address as usize + length as usize > 65535 {
+        if address as usize + length as usize > 65536 {
             return Err(SystemError::AddressOverflow);
         }
         let start = address as usize;
         let end = start + length as usize;
-        Ok(&self.buffer[start..end as usize])
+        Ok(&self.buffer[start..end])
     }
     ///
     /// Set a single Cell of Memory at address
     ///
     pub fn set(&mut self, address: Word, value: Word) {
+        self.touch(address as usize, address as usize + 1);
         self.buffer[address as usize] = value
     }
     ///
@@ -103,64 +180,205 @@ impl Memory {
     pub fn get(&self, address: Word) -> Word {
         self.buffer[address as usize]
     }
+    ///
+    /// All of Memory as a slice
+    ///
+    pub fn as_slice(&self) -> &[Word] {
+        &self.buffer
+    }
+    ///
+    /// Get a little endian 32 bit value at address, low Word first
+    ///
+    pub fn get_u32(&self, address: Word) -> Result<u32, SystemError> {
+        self.read_value(address)
+    }
+    ///
+    /// Set a little endian 32 bit value at address, low Word first
+    ///
+    pub fn set_u32(&mut self, address: Word, value: u32) -> Result<(), SystemError> {
+        self.write_value(address, &value)
+    }
+    ///
+    /// Read a fixed layout value at address
+    ///
+    pub fn read_value<T: Marshal>(&self, address: Word) -> Result<T, SystemError> {
+        let start = address as usize;
+        let end = start + T::size();
+        if end > self.buffer.len() {
+            return Err(SystemError::AddressOverflow);
+        }
+        Ok(T::decode(&self.buffer[start..end]))
+    }
+    ///
+    /// Write a fixed layout value at address
+    ///
+    pub fn write_value<T: Marshal>(&mut self, address: Word, value: &T) -> Result<(), SystemError> {
+        let start = address as usize;
+        let end = start + T::size();
+        if end > self.buffer.len() {
+            return Err(SystemError::AddressOverflow);
+        }
+        self.touch(start, end);
+        value.encode(&mut self.buffer[start..end]);
+        Ok(())
+    }
+    ///
+    /// Read a zero terminated string at address
+    ///
+    pub fn read_string(&self, address: Word, packing: Packing) -> Result<String, SystemError> {
+        let mut value = String::new();
+        for &word in &self.buffer[address as usize..] {
+            let chars = match packing {
+                Packing::Unpacked => vec![word],
+                Packing::Packed => vec![word >> 8, word & 0xFF],
+            };
+            for ch in chars {
+                if ch == 0 {
+                    return Ok(value);
+                }
+                value.push(char::from_u32(ch as u32).unwrap_or('?'));
+            }
+        }
+        Err(SystemError::AddressOverflow)
+    }
+    ///
+    /// Write a zero terminated string at address, returning the number of Words written
+    ///
+    pub fn write_string(&mut self, address: Word, value: &str, packing: Packing) -> Result<Word, SystemError> {
+        let mut chars: Vec<Word> = value.chars()
+            .map(|ch| match packing {
+                Packing::Unpacked if (ch as u32) < 0x10000 => ch as Word,
+                Packing::Packed if (ch as u32) < 0x100 => ch as Word,
+                _ => '?' as Word,
+            })
+            .collect();
+        chars.push(0);
+        let words: Vec<Word> = match packing {
+            Packing::Unpacked => chars,
+            Packing::Packed => chars.chunks(2)
+                .map(|pair| pair[0] << 8 | pair.get(1).cloned().unwrap_or(0))
+                .collect(),
+        };
+        let start = address as usize;
+        let end = start + words.len();
+        if end > self.buffer.len() {
+            return Err(SystemError::AddressOverflow);
+        }
+        self.touch(start, end);
+        self.buffer[start..end].copy_from_slice(&words);
+        Ok(words.len() as Word)
+    }
+    ///
+    /// Formatted View over Memory
+    ///
+    pub fn view(&self) -> View<'_> {
+        View::new(self)
+    }
+    ///
+    /// Start tracking writes from this point
+    ///
+    pub fn mark(&mut self) -> Marker {
+        self.generation += 1;
+        Marker(self.generation)
+    }
+    ///
+    /// Has any Word in the range been written since marker
+    ///
+    pub fn is_dirty(&self, address: Word, length: Word, marker: Marker) -> bool {
+        if length == 0 {
+            return false;
+        }
+        let first = address as usize / PAGE_SIZE;
+        let last = (address as usize + length as usize - 1).min(65535) / PAGE_SIZE;
+        self.pages[first..last + 1].iter().any(|&page| page >= marker.0)
+    }
+    ///
+    /// Pages written since marker
+    ///
+    pub fn dirty_pages(&self, marker: Marker) -> Vec<usize> {
+        (0..PAGE_COUNT).filter(|&page| self.pages[page] >= marker.0).collect()
+    }
+    ///
+    /// Word level difference from this Memory to other
+    ///
+    pub fn diff(&self, other: &Memory) -> Diff {
+        let mut diff = Diff::default();
+        self.diff_range(other, 0, self.buffer.len(), &mut diff);
+        diff
+    }
+    ///
+    /// Word level difference from an earlier snapshot to this Memory, only
+    /// comparing pages written since marker
+    ///
+    pub fn diff_since(&self, snapshot: &Memory, marker: Marker) -> Diff {
+        let mut diff = Diff::default();
+        for page in self.dirty_pages(marker) {
+            let start = page * PAGE_SIZE;
+            snapshot.diff_range(self, start, start + PAGE_SIZE, &mut diff);
+        }
+        diff
+    }
+    ///
+    /// Start recording the previous value of every written Word
+    ///
+    pub fn start_journal(&mut self) {
+        self.journal = Some(Vec::new());
+    }
+    ///
+    /// Stop recording, returning address and previous value of each write in order
+    ///
+    pub fn take_journal(&mut self) -> Vec<(Word, Word)> {
+        self.journal.take().unwrap_or_default()
+    }
+    /// Append differences in range from this Memory to other
+    fn diff_range(&self, other: &Memory, start: usize, end: usize, diff: &mut Diff) {
+        for address in start..end {
+            if self.buffer[address] != other.buffer[address] {
+                diff.changes.push(Change {
+                    address: address as Word,
+                    old: self.buffer[address],
+                    new: other.buffer[address],
+                });
+            }
+        }
+    }
+    /// Record a write to the range of addresses, before it happens
+    fn touch(&mut self, start: usize, end: usize) {
+        if let Some(ref mut journal) = self.journal {
+            for address in start..end {
+                journal.push((address as Word, self.buffer[address]));
+            }
+        }
+        if start < end {
+            for page in start / PAGE_SIZE..(end - 1) / PAGE_SIZE + 1 {
+                self.pages[page] = self.generation;
+            }
+        }
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory::new()
+    }
 }
 
 impl fmt::Display for Memory {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "Memory    0    1    2    3    4    5    6    7    8    9    A    B    C    D    E    F")?;
-        for base in (0..4096usize).map(|o| o * 16) {  // for o in (0..65536).step_by(16) {
-            write!(f, "0x{:04X}", base)?;
-            for offset in 0..16usize {
-                write!(f, " {:04X}", self.buffer[base + offset])?;
-            }
-            write!(f, " ")?;
-            for offset in 0..16usize {
-                if let Some(ch) = char::from_u32(self.buffer[base + offset] as u32) {
-                    if ch.is_ascii_alphanumeric() {
-                        write!(f, "{}", ch)?;
-                    } else {
-                        write!(f, "{}", '.')?;
-                    }
-                } else {
-                    write!(f, "{}", '.')?;
-                }
-            }
-            writeln!(f, " ")?;
-        }
-        Ok(())
+        fmt::Display::fmt(&self.view(), f)
     }
 }
 
 impl fmt::Debug for Memory {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "Memory    0    1    2    3    4    5    6    7    8    9    A    B    C    D    E    F")?;
-        for base in (0..4096usize).map(|o| o * 16) {  // for o in (0..65536).step_by(16) {
-            write!(f, "0x{:04X}", base)?;
-            for offset in 0..16usize {
-                write!(f, " {:04X}", self.buffer[base + offset])?;
-            }
-            write!(f, " ")?;
-            for offset in 0..16usize {
-                if let Some(ch) = char::from_u32(self.buffer[base + offset] as u32) {
-                    if ch.is_ascii_alphanumeric() {
-                        write!(f, "{}", ch)?;
-                    } else {
-                        write!(f, "{}", '.')?;
-                    }
-                } else {
-                    write!(f, "{}", '.')?;
-                }
-            }
-            writeln!(f, " ")?;
-        }
-        Ok(())
+        fmt::Display::fmt(&self.view(), f)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::Word;
-    use super::Memory;
+    use super::{Change, Memory, PAGE_SIZE};
     use rand::{Rng, SeedableRng, XorShiftRng};
     use std::io::Cursor;
 
@@ -243,4 +461,54 @@ mod tests {
 
         println!("{}", mem);
     }
+
+    #[test]
+    pub fn test_dirty_pages() {
+        // Create our Memory and take a marker
+        let mut mem = Memory::new();
+        let marker = mem.mark();
+
+        // Assert nothing written yet
+        assert!(mem.dirty_pages(marker).is_empty());
+        assert!(!mem.is_dirty(0x8000, 384, marker));
+
+        // Write into video memory and a single word elsewhere
+        mem.write(0x8040, &[0xF041, 0xF042]).unwrap();
+        mem.set(0x0000, 0x1234);
+
+        // Assert only the touched pages are dirty
+        assert_eq!(vec![0x0000, 0x8040 / PAGE_SIZE], mem.dirty_pages(marker));
+        assert!(mem.is_dirty(0x8000, 384, marker));
+        assert!(!mem.is_dirty(0x9000, 16, marker));
+
+        // Assert a newer marker starts clean while the older one is unaffected
+        let later = mem.mark();
+        assert!(mem.dirty_pages(later).is_empty());
+        assert!(mem.is_dirty(0x8000, 384, marker));
+    }
+
+    #[test]
+    pub fn test_diff() {
+        // Create our Memory and a snapshot of it
+        let mut mem = Memory::new();
+        mem.write(0x0100, &[1, 2, 3, 4]).unwrap();
+        let snapshot = mem.clone();
+        let marker = mem.mark();
+
+        // Change some memory
+        mem.set(0x0101, 0x0022);
+        mem.set(0x0103, 0x0004);
+        mem.set(0xFFFF, 0xFFFF);
+
+        // Assert both diffs report the same changes
+        let expected = [
+            Change { address: 0x0101, old: 0x0002, new: 0x0022 },
+            Change { address: 0xFFFF, old: 0x0000, new: 0xFFFF },
+        ];
+        assert_eq!(&expected[..], snapshot.diff(&mem).changes());
+        assert_eq!(&expected[..], mem.diff_since(&snapshot, marker).changes());
+        assert_eq!(Some(expected[0]), snapshot.diff(&mem).get(0x0101));
+        assert_eq!(None, snapshot.diff(&mem).get(0x0103));
+        assert!(mem.diff(&mem.clone()).is_empty());
+    }
 }
\ No newline at end of file
@@ -14,23 +14,43 @@
 // limitations under the License.
 //
 
+mod breakpoint;
+mod callstack;
 mod clock;
+mod crash;
 mod error;
+mod history;
+mod marshal;
 mod memory;
+mod profile;
 mod queue;
 mod registers;
 mod decoder;
+mod divergence;
+mod snapshot;
 mod system;
+mod trace;
+mod view;
 
 pub mod hardware;
-pub use self::bus::Bus;
+pub use self::breakpoint::{Breakpoint, Comparison, Condition, Event, Stop, Trigger};
+pub use self::callstack::{Call, CallStack, Mismatch, StackFrame, Symbolizer};
 pub use self::clock::Clock;
+pub use self::crash::{CrashReport, Frame};
 pub use self::error::SystemError;
-pub use self::memory::Memory;
+pub use self::history::History;
+pub use self::marshal::{Marshal, Packing};
+pub use self::memory::{Change, Diff, Marker, Memory};
+pub use self::profile::{FunctionSample, Profile, Sample};
 pub use self::queue::Queue;
 pub use self::registers::Registers;
-pub use self::decoder::State;
+pub use self::decoder::{Instruction, OpCode, Operand, Register, State};
+pub use self::divergence::{Difference, Divergence, TraceDiff};
+pub use self::hardware::Hardware;
+pub use self::snapshot::{DeviceState, Snapshot};
 pub use self::system::System;
+pub use self::trace::{Trace, TraceFormat, TraceSink, TraceWriter};
+pub use self::view::{Decoding, Format, View};
 
 /// System Word
 pub type Word = u16;
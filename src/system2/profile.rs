@@ -0,0 +1,248 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Instruction Level Profiler
+//!
+//! Every instruction is charged its full cycle count, including skipped conditionals and
+//! cycles added by Hardware, at the address it executed from and against the shadow call
+//! stack as it was when the instruction started. Folded stacks are written one per line as
+//! `outer;inner cycles`, the format read by flamegraph tools, with the code outside any call
+//! named `root` and interrupt handlers suffixed with their message.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write;
+use super::Call;
+use super::OpCode;
+use super::Symbolizer;
+use super::Word;
+
+/// Executions and cycles of an address or operation
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Sample {
+    /// Times executed or entered
+    pub count: u64,
+    /// Cycles spent
+    pub cycles: u64,
+}
+
+/// Calls and cycles of a subroutine or interrupt handler
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct FunctionSample {
+    /// Times called
+    pub calls: u64,
+    /// Cycles spent in the function and everything it called
+    pub inclusive: u64,
+    /// Cycles spent in the function itself
+    pub exclusive: u64,
+}
+
+/// Frame of a profiled stack, the called address and any interrupt message
+type Frame = (Word, Option<Word>);
+
+/// Cycle counts collected while profiling a System
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    cycles: u64,
+    instructions: u64,
+    interrupt_cycles: u64,
+    addresses: HashMap<Word, Sample>,
+    opcodes: HashMap<OpCode, Sample>,
+    functions: HashMap<Option<Word>, FunctionSample>,
+    interrupts: HashMap<Word, Sample>,
+    stacks: HashMap<Vec<Frame>, u64>,
+}
+
+impl Profile {
+    /// Create an empty Profile
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+    /// Total cycles profiled
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+    /// Total instructions profiled
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+    /// Cycles spent inside interrupt handlers
+    pub fn interrupt_cycles(&self) -> u64 {
+        self.interrupt_cycles
+    }
+    /// Executed addresses, most cycles first
+    pub fn addresses(&self) -> Vec<(Word, Sample)> {
+        sorted(&self.addresses, |&(address, sample)| (Reverse(sample.cycles), address))
+    }
+    /// Instruction mix, most cycles first
+    pub fn opcodes(&self) -> Vec<(OpCode, Sample)> {
+        sorted(&self.opcodes, |&(opcode, sample)| (Reverse(sample.cycles), opcode.name()))
+    }
+    /// Subroutines and handlers by address, None for code outside any call, most inclusive cycles first
+    pub fn functions(&self) -> Vec<(Option<Word>, FunctionSample)> {
+        sorted(&self.functions, |&(function, sample)| (Reverse(sample.inclusive), function))
+    }
+    /// Cycles spent in the handler for each interrupt message, counting deliveries, by message
+    pub fn interrupts(&self) -> Vec<(Word, Sample)> {
+        sorted(&self.interrupts, |&(message, _)| message)
+    }
+    /// Folded stacks, one `outer;inner cycles` line per distinct stack
+    pub fn folded(&self, symbols: Option<&dyn Symbolizer>) -> String {
+        let mut lines: Vec<String> = self.stacks.iter()
+            .map(|(stack, cycles)| {
+                let mut names = vec![String::from("root")];
+                names.extend(stack.iter().map(|&(target, interrupt)| frame_name(target, interrupt, symbols)));
+                format!("{} {}", names.join(";"), cycles)
+            })
+            .collect();
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+    /// Text report of the limit hottest functions, addresses and operations
+    pub fn report(&self, symbols: Option<&dyn Symbolizer>, limit: usize) -> String {
+        let percent = |cycles: u64| if self.cycles == 0 { 0.0 } else { cycles as f64 * 100.0 / self.cycles as f64 };
+        let name = |address: Word| symbols.and_then(|symbols| symbols.symbolize(address))
+            .unwrap_or_else(|| format!("0x{:04X}", address));
+        let mut out = String::new();
+        writeln!(out, "{} cycles, {} instructions, {} cycles ({:.1}%) in interrupt handlers",
+                 self.cycles, self.instructions, self.interrupt_cycles, percent(self.interrupt_cycles)).unwrap();
+
+        writeln!(out, "\nFunctions:\n  {:>10} {:>6} {:>10} {:>6} {:>8}  name", "inclusive", "%", "exclusive", "%", "calls").unwrap();
+        for (function, sample) in self.functions().into_iter().take(limit) {
+            let label = function.map_or(String::from("root"), &name);
+            writeln!(out, "  {:>10} {:>5.1}% {:>10} {:>5.1}% {:>8}  {}", sample.inclusive, percent(sample.inclusive),
+                     sample.exclusive, percent(sample.exclusive), sample.calls, label).unwrap();
+        }
+
+        writeln!(out, "\nAddresses:\n  {:>10} {:>6} {:>10}  address", "cycles", "%", "count").unwrap();
+        for (address, sample) in self.addresses().into_iter().take(limit) {
+            writeln!(out, "  {:>10} {:>5.1}% {:>10}  0x{:04X} {}", sample.cycles, percent(sample.cycles),
+                     sample.count, address, name(address)).unwrap();
+        }
+
+        writeln!(out, "\nInstructions:\n  {:>10} {:>6} {:>10}  operation", "cycles", "%", "count").unwrap();
+        for (opcode, sample) in self.opcodes() {
+            writeln!(out, "  {:>10} {:>5.1}% {:>10}  {}", sample.cycles, percent(sample.cycles), sample.count, opcode).unwrap();
+        }
+
+        if !self.interrupts.is_empty() {
+            writeln!(out, "\nInterrupts:\n  {:>10} {:>6} {:>10}  message", "cycles", "%", "count").unwrap();
+            for (message, sample) in self.interrupts() {
+                writeln!(out, "  {:>10} {:>5.1}% {:>10}  0x{:04X}", sample.cycles, percent(sample.cycles),
+                         sample.count, message).unwrap();
+            }
+        }
+        out
+    }
+    /// Record entry to a subroutine or interrupt handler
+    pub(super) fn enter(&mut self, call: &Call) {
+        self.functions.entry(Some(call.target)).or_default().calls += 1;
+        if let Some(message) = call.interrupt {
+            self.interrupts.entry(message).or_default().count += 1;
+        }
+    }
+    /// Charge an instruction at address taking cycles, executed with calls outstanding
+    pub(super) fn record(&mut self, address: Word, opcode: OpCode, calls: &[Call], cycles: u64) {
+        self.cycles += cycles;
+        self.instructions += 1;
+        let sample = self.addresses.entry(address).or_default();
+        sample.count += 1;
+        sample.cycles += cycles;
+        let sample = self.opcodes.entry(opcode).or_default();
+        sample.count += 1;
+        sample.cycles += cycles;
+
+        // Inclusive once per distinct function, so recursion is not counted twice
+        let mut seen: Vec<Option<Word>> = Vec::with_capacity(calls.len() + 1);
+        for function in Some(None).into_iter().chain(calls.iter().map(|call| Some(call.target))) {
+            if !seen.contains(&function) {
+                seen.push(function);
+                self.functions.entry(function).or_default().inclusive += cycles;
+            }
+        }
+        self.functions.entry(calls.last().map(|call| call.target)).or_default().exclusive += cycles;
+        if let Some(message) = calls.iter().rev().filter_map(|call| call.interrupt).next() {
+            self.interrupt_cycles += cycles;
+            self.interrupts.entry(message).or_default().cycles += cycles;
+        }
+        let stack: Vec<Frame> = calls.iter().map(|call| (call.target, call.interrupt)).collect();
+        *self.stacks.entry(stack).or_insert(0) += cycles;
+    }
+}
+
+/// Name of a folded stack frame, without spaces or semicolons
+fn frame_name(target: Word, interrupt: Option<Word>, symbols: Option<&dyn Symbolizer>) -> String {
+    let name = symbols.and_then(|symbols| symbols.symbolize(target))
+        .map(|name| name.replace([' ', ';'], "_"))
+        .unwrap_or_else(|| format!("0x{:04X}", target));
+    match interrupt {
+        Some(message) => format!("{}[int_0x{:04X}]", name, message),
+        None => name,
+    }
+}
+
+/// Entries of map sorted by key
+fn sorted<K, V, O, F>(map: &HashMap<K, V>, key: F) -> Vec<(K, V)>
+    where K: Copy + Eq + ::std::hash::Hash, V: Copy, O: Ord, F: Fn(&(K, V)) -> O {
+    let mut entries: Vec<(K, V)> = map.iter().map(|(&k, &v)| (k, v)).collect();
+    entries.sort_by_key(key);
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{OpCode, System};
+    use super::{FunctionSample, Sample};
+
+    #[test]
+    pub fn test_profile() {
+        let mut sys = System::new();
+        sys.memory_mut().write(0x0000, &[
+            0x7C20, 0x0010,         // JSR 0x0010
+            0x7F81, 0x0000,         // SET PC, 0x0000
+        ]).unwrap();
+        sys.memory_mut().write(0x0010, &[
+            0x7C20, 0x0020,         // JSR 0x0020
+            0x6381,                 // SET PC, POP
+        ]).unwrap();
+        sys.memory_mut().write(0x0020, &[
+            0x8802,                 // ADD A, 1
+            0x6381,                 // SET PC, POP
+        ]).unwrap();
+        sys.start_profile();
+        for _ in 0..60 {
+            sys.step_instruction().unwrap();
+        }
+        let profile = sys.stop_profile().unwrap();
+        assert!(sys.profile().is_none());
+
+        // Each loop is JSR 4 + SET 2 outside, JSR 4 + SET 1 in 0x0010 and ADD 2 + SET 1 in 0x0020
+        assert_eq!((140, 60), (profile.cycles(), profile.instructions()));
+        let functions = profile.functions();
+        assert_eq!(None, functions[0].0);
+        assert_eq!((Some(0x0010), FunctionSample { calls: 10, inclusive: 80, exclusive: 50 }), functions[1]);
+        assert_eq!((Some(0x0020), FunctionSample { calls: 10, inclusive: 30, exclusive: 30 }), functions[2]);
+        assert_eq!((OpCode::JSR, Sample { count: 20, cycles: 80 }), profile.opcodes()[0]);
+        assert_eq!((0x0000, Sample { count: 10, cycles: 40 }), profile.addresses()[0]);
+
+        // Folded stacks sum to the total
+        let folded = profile.folded(None);
+        assert!(folded.contains("root;0x0010;0x0020 "));
+        let total: u64 = folded.lines().map(|line| line.rsplit(' ').next().unwrap().parse::<u64>().unwrap()).sum();
+        assert_eq!(140, total);
+        assert!(profile.report(None, 5).starts_with("140 cycles, 60 instructions"));
+    }
+}
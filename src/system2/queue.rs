@@ -19,35 +19,33 @@ use super::SystemError;
 use super::Word;
 
 /// Interrupt Request Queue
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Eq, PartialEq)]
 pub struct Queue {
     interrupts: [Word; 256],
+    enabled: bool,
     write: u8,
     read: u8,
 }
 
+impl Default for Queue {
+    fn default() -> Queue {
+        Queue::new()
+    }
+}
+
 impl fmt::Debug for Queue {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "IRQ ( Disabled: {} Queue: ", self.enabled)?;
-        if self.read < self.write {
+        write!(f, "IRQ ( Queueing: {} Queue: ", self.enabled)?;
+        if self.is_empty() {
+            write!(f, "empty")?;
+        } else {
             write!(f, "[")?;
-            for i in &self.interrupts[self.read as usize..self.write as usize] {
+            for i in self.iter() {
                 write!(f, " 0x{:04X}", i)?;
             }
             write!(f, " ]")?;
-        } else if self.read > self.write {
-            write!(f, "[")?;
-            for i in &self.interrupts[self.read as usize..] {
-                write!(f, " 0x{:04X}", i)?;
-            }
-            for i in &self.interrupts[..self.write as usize] {
-                write!(f, " 0x{:04X}", i)?;
-            }
-            write!(f, " ]")?;
-        } else {
-            write!(f, "empty")?;
         }
-        write!(f, " ] )")
+        write!(f, " )")
     }
 }
 
@@ -55,10 +53,35 @@ impl Queue {
     pub fn new() -> Queue {
         Queue {
             interrupts: [0; 256],
+            enabled: false,
             write: 0,
             read: 0,
         }
     }
+    /// Is Interrupt Queueing enabled, holding interrupts instead of triggering them
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+    /// Is Interrupt Queueing disabled, triggering interrupts as normal
+    pub fn is_disabled(&self) -> bool {
+        !self.enabled
+    }
+    /// Enable Interrupt Queueing
+    pub fn enable(&mut self) {
+        self.enabled = true;
+    }
+    /// Disable Interrupt Queueing
+    pub fn disable(&mut self) {
+        self.enabled = false;
+    }
+    /// Number of queued interrupts
+    pub fn len(&self) -> usize {
+        self.write.wrapping_sub(self.read) as usize
+    }
+    /// Queued interrupts in delivery order
+    pub fn iter(&self) -> impl Iterator<Item = Word> + '_ {
+        (0..self.len()).map(move |offset| self.interrupts[self.read.wrapping_add(offset as u8) as usize])
+    }
     pub fn is_empty(&self) -> bool {
         self.read == self.write
     }
@@ -114,6 +137,8 @@ mod tests {
             irq.enqueue(input).unwrap();
         }
 
-        assert_eq!(IRQError::QueueFull, irq.enqueue(0).unwrap_err());
+        assert_eq!(SystemError::InterruptOverflow, irq.enqueue(0).unwrap_err());
+        assert_eq!(255, irq.len());
+        assert_eq!((0..255u16).collect::<Vec<_>>(), irq.iter().collect::<Vec<_>>());
     }
 }
\ No newline at end of file
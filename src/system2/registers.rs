@@ -14,11 +14,12 @@
 // limitations under the License.
 //
 
+use super::Register;
 use super::Word;
 use std::fmt;
 
 /// VCPU16 Internal Registers
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Eq, PartialEq)]
 pub struct Registers {
     /// Stack Pointer
     pub sp: Word,
@@ -66,6 +67,46 @@ impl Registers {
             j: 0,
         }
     }
+    /// Get value of a Register
+    pub fn get(&self, register: Register) -> Word {
+        match register {
+            Register::PC => self.pc,
+            Register::SP => self.sp,
+            Register::PS => self.ps,
+            Register::IA => self.ia,
+            Register::A => self.a,
+            Register::B => self.b,
+            Register::C => self.c,
+            Register::X => self.x,
+            Register::Y => self.y,
+            Register::Z => self.z,
+            Register::I => self.i,
+            Register::J => self.j,
+        }
+    }
+    /// Set value of a Register
+    pub fn set(&mut self, register: Register, value: Word) {
+        match register {
+            Register::PC => self.pc = value,
+            Register::SP => self.sp = value,
+            Register::PS => self.ps = value,
+            Register::IA => self.ia = value,
+            Register::A => self.a = value,
+            Register::B => self.b = value,
+            Register::C => self.c = value,
+            Register::X => self.x = value,
+            Register::Y => self.y = value,
+            Register::Z => self.z = value,
+            Register::I => self.i = value,
+            Register::J => self.j = value,
+        }
+    }
+}
+
+impl Default for Registers {
+    fn default() -> Registers {
+        Registers::new()
+    }
 }
 
 impl fmt::Display for Registers {
@@ -0,0 +1,379 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Snapshot File Format
+//!
+//! A snapshot starts with the magic `VC16SNAP` followed by a little endian major and minor
+//! format version. The remainder is a sequence of sections, each a four byte tag, a little
+//! endian u32 payload length and the payload, terminated by an `END ` section. Readers skip
+//! sections they do not recognise and ignore trailing bytes of known sections, so minor
+//! versions may add both. Readers reject snapshots with a newer major version.
+//!
+//! | Tag    | Payload                                                              |
+//! |--------|----------------------------------------------------------------------|
+//! | `REGS` | PC, SP, PS, IA, SF, A, B, C, X, Y, Z, I, J as u16                    |
+//! | `CLCK` | halted as u8, cycles as u64                                          |
+//! | `STAT` | 0 for Idle, or 1 followed by address and remaining cycles as u16     |
+//! | `IRQQ` | queueing as u8, count as u16, queued messages as u16                 |
+//! | `MEMZ` | runs of zero count as u32, literal count as u32, literal words as u16 |
+//! | `DEVS` | count as u16, per device mfg_id u32, hdw_id u32, dev_id u16, state   |
+//! |        | length u32 and state bytes                                           |
+
+use std::io::{self, Read, Write};
+use super::Clock;
+use super::Memory;
+use super::Queue;
+use super::Registers;
+use super::State;
+use super::SystemError;
+use super::Word;
+
+/// Snapshot file magic
+const MAGIC: &[u8; 8] = b"VC16SNAP";
+
+/// Major format version, incremented on incompatible changes
+pub const FORMAT_MAJOR: u16 = 1;
+
+/// Minor format version, incremented when sections or fields are added
+pub const FORMAT_MINOR: u16 = 0;
+
+/// Saved state of an attached Hardware device
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DeviceState {
+    /// Manufacturer ID
+    pub mfg_id: u32,
+    /// Hardware ID
+    pub hdw_id: u32,
+    /// Device Version
+    pub dev_id: Word,
+    /// Opaque device state
+    pub data: Vec<u8>,
+}
+
+/// Complete state of a System
+#[derive(Clone)]
+pub struct Snapshot {
+    /// System Registers
+    pub registers: Registers,
+    /// System Memory
+    pub memory: Box<Memory>,
+    /// System Clock
+    pub clock: Clock,
+    /// CPU State
+    pub state: State,
+    /// Interrupt Request Queue
+    pub queue: Queue,
+    /// Attached Hardware in port order, then memory mapped devices
+    pub devices: Vec<DeviceState>,
+}
+
+impl Snapshot {
+    /// Save Snapshot to writer
+    pub fn save(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_MAJOR.to_le_bytes())?;
+        writer.write_all(&FORMAT_MINOR.to_le_bytes())?;
+
+        let mut regs = Vec::new();
+        let r = &self.registers;
+        for &value in &[r.pc, r.sp, r.ps, r.ia, r.sf, r.a, r.b, r.c, r.x, r.y, r.z, r.i, r.j] {
+            put_u16(&mut regs, value);
+        }
+        section(writer, b"REGS", &regs)?;
+
+        let mut clck = vec![self.clock.halted() as u8];
+        clck.extend_from_slice(&self.clock.cycles().to_le_bytes());
+        section(writer, b"CLCK", &clck)?;
+
+        let mut stat = Vec::new();
+        match self.state {
+            State::Idle => stat.push(0),
+            State::Execute { address, cycles } => {
+                stat.push(1);
+                put_u16(&mut stat, address);
+                put_u16(&mut stat, cycles);
+            }
+        }
+        section(writer, b"STAT", &stat)?;
+
+        let mut irqq = vec![self.queue.is_enabled() as u8];
+        put_u16(&mut irqq, self.queue.len() as u16);
+        for message in self.queue.iter() {
+            put_u16(&mut irqq, message);
+        }
+        section(writer, b"IRQQ", &irqq)?;
+
+        section(writer, b"MEMZ", &compress(self.memory.as_slice()))?;
+
+        let mut devs = Vec::new();
+        put_u16(&mut devs, self.devices.len() as u16);
+        for device in &self.devices {
+            devs.extend_from_slice(&device.mfg_id.to_le_bytes());
+            devs.extend_from_slice(&device.hdw_id.to_le_bytes());
+            put_u16(&mut devs, device.dev_id);
+            devs.extend_from_slice(&(device.data.len() as u32).to_le_bytes());
+            devs.extend_from_slice(&device.data);
+        }
+        section(writer, b"DEVS", &devs)?;
+
+        section(writer, b"END ", &[])
+    }
+    /// Load Snapshot from reader
+    pub fn load(reader: &mut dyn Read) -> Result<Snapshot, SystemError> {
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header).map_err(|_| SystemError::InvalidSnapshot)?;
+        if &header[0..8] != MAGIC {
+            return Err(SystemError::InvalidSnapshot);
+        }
+        if u16::from_le_bytes([header[8], header[9]]) > FORMAT_MAJOR {
+            return Err(SystemError::InvalidSnapshot);
+        }
+
+        let mut registers = None;
+        let mut clock = None;
+        let mut state = None;
+        let mut queue = None;
+        let mut memory = None;
+        let mut devices = None;
+        loop {
+            let mut tag = [0u8; 8];
+            reader.read_exact(&mut tag).map_err(|_| SystemError::InvalidSnapshot)?;
+            let length = u32::from_le_bytes([tag[4], tag[5], tag[6], tag[7]]) as u64;
+            let mut payload = Vec::new();
+            reader.take(length).read_to_end(&mut payload).map_err(|_| SystemError::InvalidSnapshot)?;
+            if payload.len() as u64 != length {
+                return Err(SystemError::InvalidSnapshot);
+            }
+            let mut cursor = Cursor { bytes: &payload, offset: 0 };
+            match &tag[0..4] {
+                b"REGS" => {
+                    let mut r = Registers::new();
+                    for value in &mut [
+                        &mut r.pc, &mut r.sp, &mut r.ps, &mut r.ia, &mut r.sf, &mut r.a, &mut r.b,
+                        &mut r.c, &mut r.x, &mut r.y, &mut r.z, &mut r.i, &mut r.j,
+                    ] {
+                        **value = cursor.u16()?;
+                    }
+                    registers = Some(r);
+                }
+                b"CLCK" => {
+                    let halted = cursor.u8()? != 0;
+                    let mut c = Clock::new();
+                    c.advance(cursor.u64()?);
+                    if halted {
+                        c.halt();
+                    }
+                    clock = Some(c);
+                }
+                b"STAT" => {
+                    state = Some(match cursor.u8()? {
+                        0 => State::Idle,
+                        1 => State::Execute { address: cursor.u16()?, cycles: cursor.u16()? },
+                        _ => return Err(SystemError::InvalidSnapshot),
+                    });
+                }
+                b"IRQQ" => {
+                    let mut q = Queue::new();
+                    if cursor.u8()? != 0 {
+                        q.enable();
+                    }
+                    for _ in 0..cursor.u16()? {
+                        q.enqueue(cursor.u16()?).map_err(|_| SystemError::InvalidSnapshot)?;
+                    }
+                    queue = Some(q);
+                }
+                b"MEMZ" => {
+                    let mut m = Box::new(Memory::new());
+                    m.write(0, &decompress(&mut cursor)?)?;
+                    memory = Some(m);
+                }
+                b"DEVS" => {
+                    let mut d = Vec::new();
+                    for _ in 0..cursor.u16()? {
+                        let mfg_id = cursor.u32()?;
+                        let hdw_id = cursor.u32()?;
+                        let dev_id = cursor.u16()?;
+                        let length = cursor.u32()? as usize;
+                        let data = cursor.bytes(length)?.to_vec();
+                        d.push(DeviceState { mfg_id, hdw_id, dev_id, data });
+                    }
+                    devices = Some(d);
+                }
+                b"END " => break,
+                _ => { /* Section from a newer minor version */ }
+            }
+        }
+        match (registers, clock, state, queue, memory, devices) {
+            (Some(registers), Some(clock), Some(state), Some(queue), Some(memory), Some(devices)) => {
+                Ok(Snapshot { registers, memory, clock, state, queue, devices })
+            }
+            _ => Err(SystemError::InvalidSnapshot),
+        }
+    }
+}
+
+/// Write a section with tag and payload
+fn section(writer: &mut dyn Write, tag: &[u8; 4], payload: &[u8]) -> io::Result<()> {
+    writer.write_all(tag)?;
+    writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+    writer.write_all(payload)
+}
+
+/// Append a little endian u16
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Compress words into runs of zeros followed by literal words
+fn compress(words: &[Word]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    let mut index = 0;
+    while index < words.len() {
+        let zeros = words[index..].iter().take_while(|&&word| word == 0).count();
+        index += zeros;
+        let literals = words[index..].iter().take_while(|&&word| word != 0).count();
+        buffer.extend_from_slice(&(zeros as u32).to_le_bytes());
+        buffer.extend_from_slice(&(literals as u32).to_le_bytes());
+        for &word in &words[index..index + literals] {
+            put_u16(&mut buffer, word);
+        }
+        index += literals;
+    }
+    buffer
+}
+
+/// Decompress runs produced by compress into a complete memory image
+fn decompress(cursor: &mut Cursor) -> Result<Vec<Word>, SystemError> {
+    let mut words = Vec::with_capacity(65536);
+    while words.len() < 65536 {
+        let zeros = cursor.u32()? as usize;
+        let literals = cursor.u32()? as usize;
+        if words.len() + zeros + literals > 65536 {
+            return Err(SystemError::InvalidSnapshot);
+        }
+        words.resize(words.len() + zeros, 0);
+        for _ in 0..literals {
+            words.push(cursor.u16()?);
+        }
+    }
+    Ok(words)
+}
+
+/// Little endian reader over a section payload
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], SystemError> {
+        if self.offset + length > self.bytes.len() {
+            return Err(SystemError::InvalidSnapshot);
+        }
+        let bytes = &self.bytes[self.offset..self.offset + length];
+        self.offset += length;
+        Ok(bytes)
+    }
+    fn u8(&mut self) -> Result<u8, SystemError> {
+        Ok(self.bytes(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16, SystemError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+    fn u32(&mut self) -> Result<u32, SystemError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+    fn u64(&mut self) -> Result<u64, SystemError> {
+        let b = self.bytes(8)?;
+        Ok(u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{State, System, SystemError};
+    use super::{Snapshot, MAGIC};
+    use std::io::Cursor;
+
+    /// Program which loops forever incrementing [0x1000] with a software interrupt handler
+    fn program() -> System {
+        let mut sys = System::new();
+        sys.memory_mut().write(0x0000, &[
+            0x7D40, 0x0020,         // IAS 0x0020
+            0x8BC2, 0x1000,         // ADD [0x1000], 0x0001
+            0x9900,                 // INT 0x0005
+            0x7F81, 0x0002,         // SET PC, 0x0002
+        ]).unwrap();
+        sys.memory_mut().write(0x0020, &[
+            0x0022,                 // ADD B, A
+            0x0160,                 // RFI 0
+        ]).unwrap();
+        sys
+    }
+
+    #[test]
+    pub fn test_round_trip() {
+        let mut sys = program();
+
+        // Run until part way through an instruction with an interrupt queued
+        while sys.queue().is_empty() || sys.state() == State::Idle {
+            sys.step().unwrap();
+        }
+        let snapshot = sys.snapshot();
+        let mut buffer = Vec::new();
+        snapshot.save(&mut buffer).unwrap();
+
+        // Zero runs compress the mostly empty memory image
+        assert!(buffer.len() < 256, "snapshot is {} bytes", buffer.len());
+
+        // Restore into a fresh System and run both in lockstep
+        let mut copy = program();
+        copy.memory_mut().clear();
+        copy.restore(&Snapshot::load(&mut Cursor::new(&buffer)).unwrap()).unwrap();
+        assert_eq!(sys.state(), copy.state());
+        for _ in 0..1000 {
+            sys.step().unwrap();
+            copy.step().unwrap();
+            assert_eq!(sys.registers(), copy.registers());
+            assert_eq!(sys.state(), copy.state());
+            assert_eq!(sys.clock().cycles(), copy.clock().cycles());
+        }
+        assert!(sys.memory().diff(copy.memory()).is_empty());
+    }
+
+    #[test]
+    pub fn test_versioning() {
+        let mut buffer = Vec::new();
+        program().snapshot().save(&mut buffer).unwrap();
+
+        // Unknown sections from newer minor versions are skipped
+        let mut extended = buffer[..12].to_vec();
+        extended.extend_from_slice(b"NEW!\x02\x00\x00\x00\xAA\xBB");
+        extended.extend_from_slice(&buffer[12..]);
+        assert!(Snapshot::load(&mut Cursor::new(&extended)).is_ok());
+
+        // Newer major versions are rejected
+        let mut major = buffer.clone();
+        major[MAGIC.len()] = 2;
+        assert_eq!(SystemError::InvalidSnapshot, Snapshot::load(&mut Cursor::new(&major)).err().unwrap());
+
+        // Truncated snapshots are rejected
+        let truncated = &buffer[..buffer.len() - 8];
+        assert_eq!(SystemError::InvalidSnapshot, Snapshot::load(&mut Cursor::new(truncated)).err().unwrap());
+    }
+}
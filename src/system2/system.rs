@@ -14,16 +14,47 @@
 // limitations under the License.
 //
 
-use super::Bus;
+use std::collections::{BTreeMap, VecDeque};
+use super::Breakpoint;
+use super::Call;
+use super::CallStack;
 use super::Clock;
+use super::CrashReport;
+use super::DeviceState;
+use super::Event;
 use super::Hardware;
+use super::History;
+use super::Instruction;
 use super::Memory;
+use super::Mismatch;
+use super::OpCode;
+use super::Profile;
+use super::Operand;
 use super::Queue;
-use super::PIC;
+use super::history::Record;
+use super::Register;
 use super::Registers;
+use super::Snapshot;
+use super::StackFrame;
 use super::State;
+use super::Stop;
+use super::Symbolizer;
 use super::SystemError;
-use std::fmt;
+use super::Trace;
+use super::TraceSink;
+use super::Trigger;
+use super::Word;
+
+/// Memory read by undone cycles, and their writes as address, old and new value, latest first
+type Undone = (Vec<Word>, Vec<(Word, Word, Word)>);
+
+/// Resolved Operand
+#[derive(Clone, Copy)]
+enum Location {
+    Register(Register),
+    Memory(Word),
+    Literal(Word),
+}
 
 /// A System is a container for all Hardware.
 /// A Primary CPU always exists in Hardware Slot 0.
@@ -31,7 +62,9 @@ pub struct System {
     /// System Registers
     registers: Registers,
     /// System Hardware
-    hardware: Vec<Box<Hardware>>,
+    hardware: Vec<Box<dyn Hardware>>,
+    /// Memory mapped devices, updated each cycle but not enumerated by HWN and HWQ
+    mapped: Vec<Box<dyn Hardware>>,
     /// System Memory
     memory: Memory,
     /// System Clock
@@ -40,6 +73,34 @@ pub struct System {
     state: State,
     /// Interrupt Request Queue
     irq: Queue,
+    /// Undo log of recent cycles, when recording
+    history: Option<History>,
+    /// Breakpoints and Watchpoints by id
+    breakpoints: BTreeMap<usize, Breakpoint>,
+    /// Next Breakpoint id
+    next_breakpoint: usize,
+    /// Memory read by the current cycle, while watching
+    reads: Option<Vec<Word>>,
+    /// Interrupt delivered by the current cycle
+    delivered: Option<Word>,
+    /// Interrupt delivered by a cycle which stopped at its handler, traced with the handler's first instruction
+    entered: Option<Word>,
+    /// Destination for instruction Traces, when tracing
+    tracer: Option<Box<dyn TraceSink>>,
+    /// Trace of the instruction executed by the current cycle
+    pending: Option<Trace>,
+    /// Traces of the most recent instructions, for crash reports
+    recent: VecDeque<Trace>,
+    /// Most recent instructions kept
+    recent_capacity: usize,
+    /// Shadow call stack
+    calls: CallStack,
+    /// Shadow call stack before the current cycle changed it, while recording History
+    calls_before: Option<CallStack>,
+    /// Mismatched return made by the current cycle
+    mismatch: Option<Mismatch>,
+    /// Cycle counts, when profiling
+    profile: Option<Profile>,
 }
 
 impl System {
@@ -48,35 +109,1291 @@ impl System {
         System {
             registers: Registers::new(),
             hardware: Vec::new(),
+            mapped: Vec::new(),
             memory: Memory::new(),
             clock: Clock::new(),
             state: State::Idle,
             irq: Queue::new(),
+            history: None,
+            breakpoints: BTreeMap::new(),
+            next_breakpoint: 1,
+            reads: None,
+            delivered: None,
+            entered: None,
+            tracer: None,
+            pending: None,
+            recent: VecDeque::new(),
+            recent_capacity: 0,
+            calls: CallStack::new(),
+            calls_before: None,
+            mismatch: None,
+            profile: None,
+        }
+    }
+    /// Attach a Hardware device, returning its port
+    pub fn attach(&mut self, device: Box<dyn Hardware>) -> Word {
+        self.hardware.push(device);
+        (self.hardware.len() - 1) as Word
+    }
+    /// Attached Hardware
+    pub fn hardware(&self) -> &[Box<dyn Hardware>] {
+        &self.hardware
+    }
+    /// Map a device into memory, updating it each cycle without a port for HWN, HWQ or HWI to see
+    pub fn map(&mut self, device: Box<dyn Hardware>) {
+        self.mapped.push(device);
+    }
+    /// Memory mapped devices
+    pub fn mapped(&self) -> &[Box<dyn Hardware>] {
+        &self.mapped
+    }
+    /// System Registers
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+    /// Mutable System Registers
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+    /// System Memory
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+    /// Mutable System Memory
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+    /// System Clock
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+    /// System State
+    pub fn state(&self) -> State {
+        self.state
+    }
+    /// Interrupt Request Queue
+    pub fn queue(&self) -> &Queue {
+        &self.irq
+    }
+    /// Trigger an interrupt with message, ignored while IA is 0
+    pub fn interrupt(&mut self, message: Word) -> Result<(), SystemError> {
+        if self.registers.ia == 0 {
+            return Ok(());
+        }
+        self.irq.enqueue(message).inspect_err(|_| {
+            // Queue overflowed, halt and catch fire
+            self.clock.halt();
+        })
+    }
+    /// Record up to capacity cycles of History for stepping backwards, 0 to stop recording
+    pub fn record_history(&mut self, capacity: usize) {
+        self.history = if capacity == 0 { None } else { Some(History::new(capacity)) };
+    }
+    /// Recorded History
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+    /// Add a Breakpoint or Watchpoint, returning its id
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        let id = self.next_breakpoint;
+        self.next_breakpoint += 1;
+        self.breakpoints.insert(id, breakpoint);
+        id
+    }
+    /// Remove a Breakpoint by id
+    pub fn remove_breakpoint(&mut self, id: usize) -> Option<Breakpoint> {
+        self.breakpoints.remove(&id)
+    }
+    /// Breakpoints and Watchpoints by id
+    pub fn breakpoints(&self) -> &BTreeMap<usize, Breakpoint> {
+        &self.breakpoints
+    }
+    /// Mutable Breakpoint by id
+    pub fn breakpoint_mut(&mut self, id: usize) -> Option<&mut Breakpoint> {
+        self.breakpoints.get_mut(&id)
+    }
+    /// Send a Trace of every executed instruction to sink
+    pub fn trace(&mut self, sink: Box<dyn TraceSink>) {
+        self.tracer = Some(sink);
+    }
+    /// Stop tracing, returning the sink
+    pub fn stop_trace(&mut self) -> Option<Box<dyn TraceSink>> {
+        self.tracer.take()
+    }
+    /// Keep Traces of the last count instructions for crash reports, 0 to stop keeping them
+    pub fn keep_recent(&mut self, count: usize) {
+        self.recent_capacity = count;
+        while self.recent.len() > count {
+            self.recent.pop_front();
+        }
+    }
+    /// Traces of the most recent instructions, oldest first
+    pub fn recent(&self) -> impl Iterator<Item = &Trace> + '_ {
+        self.recent.iter()
+    }
+    /// Report the state of the System after step failed with reason
+    pub fn crash_report(&self, reason: SystemError) -> CrashReport {
+        CrashReport::new(reason, self.snapshot(), self.recent.iter().cloned().collect())
+    }
+    /// Shadow call stack of outstanding JSR calls and interrupts
+    pub fn call_stack(&self) -> &CallStack {
+        &self.calls
+    }
+    /// Frames from PC out to the outermost caller, named by symbols
+    pub fn backtrace(&self, symbols: Option<&dyn Symbolizer>) -> Vec<StackFrame> {
+        self.calls.backtrace(self.registers.pc, symbols)
+    }
+    /// Step one instruction, running any subroutine it calls until it returns, for at most cycles
+    ///
+    /// Fails with StepLimit when the subroutine is still running after cycles.
+    pub fn step_over(&mut self, cycles: u64) -> Result<Option<Stop>, SystemError> {
+        let depth = self.calls.depth();
+        let limit = self.clock.cycles().saturating_add(cycles);
+        match self.step_instruction()? {
+            Some(stop) => Ok(Some(stop)),
+            None => self.run_to_depth(depth, limit),
+        }
+    }
+    /// Run until the current subroutine or interrupt handler returns, for at most cycles
+    ///
+    /// Does nothing outside of any call. Fails with StepLimit when the call is still running
+    /// after cycles.
+    pub fn step_out(&mut self, cycles: u64) -> Result<Option<Stop>, SystemError> {
+        let limit = self.clock.cycles().saturating_add(cycles);
+        match self.calls.depth() {
+            0 => Ok(None),
+            depth => self.run_to_depth(depth - 1, limit),
+        }
+    }
+    /// Step instructions until the shadow call stack is no deeper than depth or the clock reaches limit
+    fn run_to_depth(&mut self, depth: usize, limit: u64) -> Result<Option<Stop>, SystemError> {
+        while self.calls.depth() > depth {
+            if self.clock.cycles() >= limit {
+                return Err(SystemError::StepLimit);
+            }
+            if let Some(stop) = self.step_instruction()? {
+                return Ok(Some(stop));
+            }
+        }
+        Ok(None)
+    }
+    /// Start collecting a new Profile of executed instructions
+    pub fn start_profile(&mut self) {
+        self.profile = Some(Profile::new());
+    }
+    /// Profile collected so far
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+    /// Stop profiling, returning the Profile collected
+    pub fn stop_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+    /// Is a Trace needed for each instruction
+    fn tracing(&self) -> bool {
+        self.tracer.is_some() || self.recent_capacity > 0
+    }
+    /// Step the System forward one clock cycle, returning the first Breakpoint hit
+    pub fn step(&mut self) -> Result<Option<Stop>, SystemError> {
+        let watching = !self.breakpoints.is_empty();
+        if self.history.is_none() && !watching && !self.tracing() {
+            return self.cycle().map(|_| None);
+        }
+        let (registers, clock, state, queue) = (self.registers, self.clock, self.state, self.irq);
+        self.memory.start_journal();
+        self.reads = if watching || self.history.is_some() { Some(Vec::new()) } else { None };
+        self.delivered = None;
+        self.mismatch = None;
+        let result = self.cycle();
+        let writes = self.memory.take_journal();
+        let reads = self.reads.take().unwrap_or_default();
+        let stop = if watching && result.is_ok() { self.check(&registers, clock.cycles(), &reads, &writes) } else { None };
+        let traced = match self.pending.take() {
+            Some(trace) => self.emit(trace, &registers, &writes),
+            None => Ok(()),
+        };
+        let queue = if queue != self.irq { Some(queue) } else { None };
+        let calls = self.calls_before.take();
+        if let Some(ref mut history) = self.history {
+            history.push(Record { registers, clock, state, queue, calls, writes, reads });
         }
+        result.and(traced).map(|_| stop)
     }
-    /// Step the System forward one clock cycle
-    pub fn step(&mut self) -> Result<(), SystemError> {
+    /// Run the System for at most cycles, stopping at the first Breakpoint hit
+    pub fn run(&mut self, cycles: u64) -> Result<Option<Stop>, SystemError> {
+        for _ in 0..cycles {
+            if let Some(stop) = self.step()? {
+                return Ok(Some(stop));
+            }
+        }
+        Ok(None)
+    }
+    /// Step the System back one clock cycle
+    pub fn step_back(&mut self) -> Result<(), SystemError> {
+        self.undo().map(|_| ())
+    }
+    /// Step the System back to the start of the previous instruction
+    ///
+    /// Nothing is undone when History does not reach back to the start of an instruction.
+    pub fn step_back_instruction(&mut self) -> Result<(), SystemError> {
+        self.undo_instruction().map(|_| ())
+    }
+    /// Run the System backwards to the previous instruction which is at an address Breakpoint,
+    /// or which read, wrote or changed what a watchpoint watches
+    ///
+    /// Hit counts are left untouched and ignore counts do not apply.
+    pub fn run_back(&mut self) -> Result<Stop, SystemError> {
+        loop {
+            let after = self.registers;
+            let (reads, writes) = self.undo_instruction()?;
+            if let Some(stop) = self.check_back(&after, &reads, &writes) {
+                return Ok(stop);
+            }
+        }
+    }
+    /// Undo the most recent cycle, returning the Memory it read and wrote
+    fn undo(&mut self) -> Result<Undone, SystemError> {
+        let record = match self.history.as_mut().and_then(History::pop) {
+            Some(record) => record,
+            None => return Err(SystemError::HistoryExhausted),
+        };
+        let mut writes = Vec::with_capacity(record.writes.len());
+        for &(address, value) in record.writes.iter().rev() {
+            writes.push((address, value, self.memory.get(address)));
+            self.memory.set(address, value);
+        }
+        self.registers = record.registers;
+        self.entered = None;
+        self.clock = record.clock;
+        self.state = record.state;
+        if let Some(queue) = record.queue {
+            self.irq = queue;
+        }
+        if let Some(calls) = record.calls {
+            self.calls = calls;
+        }
+        Ok((record.reads, writes))
+    }
+    /// Undo cycles back to the start of the previous instruction, returning the Memory they
+    /// read and wrote
+    fn undo_instruction(&mut self) -> Result<Undone, SystemError> {
+        if !self.history.as_ref().is_some_and(History::has_instruction) {
+            return Err(SystemError::HistoryExhausted);
+        }
+        let (mut reads, mut writes) = self.undo()?;
+        while self.state != State::Idle {
+            let (earlier_reads, earlier_writes) = self.undo()?;
+            reads.extend(earlier_reads);
+            writes.extend(earlier_writes);
+        }
+        Ok((reads, writes))
+    }
+    /// Check Breakpoints against an instruction just undone, with the Registers it left behind
+    fn check_back(&self, after: &Registers, reads: &[Word], writes: &[(Word, Word, Word)]) -> Option<Stop> {
+        let within = |address: Word, start: Word, length: Word| address.wrapping_sub(start) < length;
+        let (registers, memory) = (&self.registers, &self.memory);
+        for (&id, breakpoint) in &self.breakpoints {
+            if !breakpoint.enabled {
+                continue;
+            }
+            let event = match breakpoint.trigger {
+                Trigger::Address(address) if registers.pc == address => Some(Event::Address(address)),
+                Trigger::Read(start, length) => reads.iter()
+                    .find(|&&address| within(address, start, length))
+                    .map(|&address| Event::Read(address)),
+                Trigger::Write(start, length) => writes.iter()
+                    .find(|&&(address, _, _)| within(address, start, length))
+                    .map(|&(address, old, new)| Event::Write { address, old, new }),
+                Trigger::Register(register) if registers.get(register) != after.get(register) => {
+                    Some(Event::Register { register, old: registers.get(register), new: after.get(register) })
+                }
+                _ => None,
+            };
+            if event.is_some() && breakpoint.condition.is_none_or(|condition| condition.evaluate(registers, memory)) {
+                return event.map(|event| Stop { id, event });
+            }
+        }
+        None
+    }
+    /// Check Breakpoints after a cycle, counting hits and returning the first to stop
+    ///
+    /// Cycle Breakpoints stop once the Clock reaches or passes their cycle, which a device
+    /// fast-forwarding the Clock can jump over.
+    fn check(&mut self, before: &Registers, since: u64, reads: &[Word], writes: &[(Word, Word)]) -> Option<Stop> {
+        let within = |address: Word, start: Word, length: Word| address.wrapping_sub(start) < length;
+        let (registers, memory) = (&self.registers, &self.memory);
+        let (state, cycles, delivered, mismatch) = (self.state, self.clock.cycles(), self.delivered, self.mismatch);
+        let mut stop = None;
+        for (&id, breakpoint) in self.breakpoints.iter_mut() {
+            if !breakpoint.enabled {
+                continue;
+            }
+            let event = match breakpoint.trigger {
+                Trigger::Address(address) if state == State::Idle && registers.pc == address => {
+                    Some(Event::Address(address))
+                }
+                Trigger::Cycle(cycle) if since < cycle && cycle <= cycles => Some(Event::Cycle(cycle)),
+                Trigger::Interrupt(message) => delivered
+                    .filter(|&delivered| message.is_none_or(|message| message == delivered))
+                    .map(Event::Interrupt),
+                Trigger::Read(start, length) => reads.iter()
+                    .find(|&&address| within(address, start, length))
+                    .map(|&address| Event::Read(address)),
+                Trigger::Write(start, length) => writes.iter()
+                    .find(|&&(address, _)| within(address, start, length))
+                    .map(|&(address, old)| Event::Write { address, old, new: memory.get(address) }),
+                Trigger::Register(register) if before.get(register) != registers.get(register) => {
+                    Some(Event::Register { register, old: before.get(register), new: registers.get(register) })
+                }
+                Trigger::Return => mismatch.map(Event::Return),
+                _ => None,
+            };
+            let event = match event {
+                Some(event) => event,
+                None => continue,
+            };
+            if breakpoint.condition.is_none_or(|condition| condition.evaluate(registers, memory)) {
+                breakpoint.hits += 1;
+                if breakpoint.hits > breakpoint.ignore && stop.is_none() {
+                    stop = Some(Stop { id, event });
+                }
+            }
+        }
+        stop
+    }
+    /// Complete a Trace with the changes made by its cycle and send it to the sink
+    fn emit(&mut self, mut trace: Trace, before: &Registers, writes: &[(Word, Word)]) -> Result<(), SystemError> {
+        trace.registers = Register::ALL.iter()
+            .filter(|&&register| before.get(register) != self.registers.get(register))
+            .map(|&register| (register, before.get(register), self.registers.get(register)))
+            .collect();
+        trace.writes = writes.iter().enumerate()
+            .map(|(index, &(address, old))| {
+                let new = writes[index + 1..].iter()
+                    .find(|&&(later, _)| later == address)
+                    .map_or(self.memory.get(address), |&(_, value)| value);
+                (address, old, new)
+            })
+            .collect();
+        let result = match self.tracer {
+            Some(ref mut tracer) => tracer.record(&trace).map_err(|_| SystemError::TraceFailure),
+            None => Ok(()),
+        };
+        if self.recent_capacity > 0 {
+            if self.recent.len() == self.recent_capacity {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(trace);
+        }
+        result
+    }
+    /// Advance the System one clock cycle, halting when a device overflows the Interrupt Queue
+    fn cycle(&mut self) -> Result<(), SystemError> {
+        let result = self.advance();
+        if result == Err(SystemError::InterruptOverflow) {
+            // Queue overflowed, halt and catch fire
+            self.clock.halt();
+        }
+        result
+    }
+    /// Advance the CPU and Hardware one clock cycle
+    fn advance(&mut self) -> Result<(), SystemError> {
         // Advance the clock
         self.clock.step()?;
-        match self.state {
+        self.state = match self.state {
             State::Idle => {
-                /// Fetch
-                let base_address = self.registers.pc;
-                let opcode_word = self.memory.get(base_address);
-                self.registers.pc += 1;
+                self.trigger()?;
+                if self.delivered.is_some() && self.breaks_at(self.registers.pc) {
+                    // Leave the handler's first instruction to the next cycle, stopping at it first
+                    self.entered = self.delivered;
+                    State::Idle
+                } else {
+                    self.start()?
+                }
+            }
+            State::Execute { address, cycles } => match cycles {
+                0 | 1 => State::Idle,
+                cycles => State::Execute { address, cycles: cycles - 1 },
             },
         };
-        // Fetch
-        let base = self.registers.pc;
-        let word = self.memory.get(base);
-        // Decode
+        // Iterate through Hardware and memory mapped devices
+        for device in self.hardware.iter_mut().chain(self.mapped.iter_mut()) {
+            device.update(&self.clock, &mut self.registers, &mut self.memory, &mut self.irq)?;
+        }
+        Ok(())
+    }
+    /// Start the instruction at PC, returning the State it leaves the CPU in
+    fn start(&mut self) -> Result<State, SystemError> {
+        let (address, interrupt) = (self.registers.pc, self.delivered.or(self.entered.take()));
+        if self.tracing() {
+            let instruction = Instruction::decode(&self.memory, address);
+            self.pending = Some(Trace {
+                cycle: self.clock.cycles(),
+                pc: address,
+                words: (0..instruction.size).map(|offset| self.memory.get(address.wrapping_add(offset))).collect(),
+                instruction,
+                u: None,
+                m: None,
+                registers: Vec::new(),
+                writes: Vec::new(),
+                interrupt,
+            });
+        }
+        // Charged against the calls outstanding when the instruction started
+        let sample = match self.profile {
+            Some(_) => Some((Instruction::decode(&self.memory, address).opcode, self.calls.calls().to_vec())),
+            None => None,
+        };
+        let cycles = self.execute()?;
+        if let (Some((opcode, calls)), Some(ref mut profile)) = (sample, self.profile.as_mut()) {
+            profile.record(address, opcode, &calls, cycles.max(1) as u64);
+        }
+        Ok(match cycles {
+            0 | 1 => State::Idle,
+            cycles => State::Execute { address, cycles: cycles - 1 },
+        })
+    }
+    /// Step the System until the current instruction completes, returning the first Breakpoint hit
+    pub fn step_instruction(&mut self) -> Result<Option<Stop>, SystemError> {
+        let mut stop = self.step()?;
+        while self.state != State::Idle {
+            stop = stop.or(self.step()?);
+        }
+        Ok(stop)
+    }
+    /// Capture the complete state of the System
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers: self.registers,
+            memory: Box::new(self.memory.clone()),
+            clock: self.clock,
+            state: self.state,
+            queue: self.irq,
+            devices: self.hardware.iter().chain(&self.mapped)
+                .map(|device| DeviceState {
+                    mfg_id: device.mfg_id(),
+                    hdw_id: device.hdw_id(),
+                    dev_id: device.dev_id(),
+                    data: device.save_state(),
+                })
+                .collect(),
+        }
+    }
+    /// Restore the System to a Snapshot taken with compatible Hardware attached and mapped
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SystemError> {
+        let mut devices: Vec<&mut Box<dyn Hardware>> = self.hardware.iter_mut().chain(self.mapped.iter_mut()).collect();
+        if snapshot.devices.len() != devices.len() {
+            return Err(SystemError::IncompatibleHardware);
+        }
+        for (state, device) in snapshot.devices.iter().zip(&devices) {
+            if state.mfg_id != device.mfg_id() || !device.compatible(state.hdw_id, state.dev_id) {
+                return Err(SystemError::IncompatibleHardware);
+            }
+        }
+        // Devices restored before one rejects its state are put back as they were
+        let saved: Vec<(Word, Vec<u8>)> = devices.iter().map(|device| (device.dev_id(), device.save_state())).collect();
+        for (index, state) in snapshot.devices.iter().enumerate() {
+            if let Err(error) = devices[index].restore_state(state.dev_id, &state.data) {
+                for (device, &(dev_id, ref data)) in devices.iter_mut().zip(&saved).take(index) {
+                    device.restore_state(dev_id, data)?;
+                }
+                return Err(error);
+            }
+        }
+        self.restore_core(snapshot)
+    }
+    /// Restore the CPU, Memory and Interrupt Queue of a Snapshot, ignoring its Hardware
+    ///
+    /// Used to inspect core files without the Hardware they were taken with.
+    pub fn restore_core(&mut self, snapshot: &Snapshot) -> Result<(), SystemError> {
+        self.registers = snapshot.registers;
+        self.memory.write(0, snapshot.memory.as_slice())?;
+        self.clock = snapshot.clock;
+        self.state = snapshot.state;
+        self.irq = snapshot.queue;
+        self.entered = None;
+        self.calls.clear();
+        if let Some(ref mut history) = self.history {
+            history.clear();
+        }
+        Ok(())
+    }
+    /// Is an enabled Address Breakpoint set at address
+    fn breaks_at(&self, address: Word) -> bool {
+        self.breakpoints.values().any(|breakpoint| breakpoint.enabled && breakpoint.trigger == Trigger::Address(address))
+    }
+    /// Trigger the next queued interrupt unless queueing is enabled
+    fn trigger(&mut self) -> Result<(), SystemError> {
+        if self.irq.is_disabled() && !self.irq.is_empty() {
+            let message = self.irq.dequeue()?;
+            if self.registers.ia != 0 {
+                self.irq.enable();
+                let (pc, a) = (self.registers.pc, self.registers.a);
+                self.push(pc);
+                let call = Call {
+                    call: pc,
+                    target: self.registers.ia,
+                    ret: pc,
+                    slot: self.registers.sp,
+                    interrupt: Some(message),
+                    cycle: self.clock.cycles(),
+                };
+                self.enter(call);
+                self.push(a);
+                self.registers.pc = self.registers.ia;
+                self.registers.a = message;
+                self.delivered = Some(message);
+            }
+        }
+        Ok(())
+    }
+    /// Execute the instruction at PC, returning the cycles taken
+    fn execute(&mut self) -> Result<u16, SystemError> {
+        let address = self.registers.pc;
+        let instruction = Instruction::decode(&self.memory, address);
+        self.registers.pc = self.registers.pc.wrapping_add(instruction.size);
+        let mut cycles = instruction.cycles();
+        let u = match instruction.u {
+            Some(operand) => self.resolve(operand),
+            None => Location::Literal(0),
+        };
+        let m = match instruction.m {
+            Some(operand) => self.resolve(operand),
+            None => Location::Literal(0),
+        };
+        if self.pending.is_some() {
+            let (u_val, m_val) = (instruction.u.map(|_| self.peek(u)), instruction.m.map(|_| self.peek(m)));
+            if let Some(ref mut trace) = self.pending {
+                trace.u = u_val;
+                trace.m = m_val;
+            }
+        }
+        match instruction.opcode {
+            OpCode::NOP => {}
+            OpCode::CLK => {
+                let time = self.clock.cycles();
+                self.registers.i = (time >> 16) as Word;
+                self.registers.j = time as Word;
+                self.registers.ps = if time > 0xFFFF_FFFF { 0x0001 } else { 0x0000 };
+            }
+            OpCode::ERR => {
+                self.clock.halt();
+                return Err(SystemError::InvalidInstruction);
+            }
+            OpCode::JSR => {
+                let target = self.load(u);
+                let pc = self.registers.pc;
+                self.push(pc);
+                self.registers.pc = target;
+                let call = Call { call: address, target, ret: pc, slot: self.registers.sp, interrupt: None, cycle: self.clock.cycles() };
+                self.enter(call);
+            }
+            OpCode::INT => {
+                let message = self.load(u);
+                self.interrupt(message)?;
+            }
+            OpCode::IAG => {
+                let ia = self.registers.ia;
+                self.store(u, ia);
+            }
+            OpCode::IAS => self.registers.ia = self.load(u),
+            OpCode::RFI => {
+                self.irq.disable();
+                self.registers.a = self.pop();
+                let slot = self.registers.sp;
+                self.registers.pc = self.pop();
+                self.leave(address, slot);
+            }
+            OpCode::IAQ => {
+                if self.load(u) != 0 {
+                    self.irq.enable();
+                } else {
+                    self.irq.disable();
+                }
+            }
+            OpCode::HWN => {
+                let count = self.hardware.len() as Word;
+                self.store(u, count);
+            }
+            OpCode::HWQ => {
+                // VCPU16 ids are a Word each, so the low word of DCPU-16 ids is reported
+                let port = self.load(u) as usize;
+                let (mfg_id, hdw_id, dev_id) = match self.hardware.get(port) {
+                    Some(device) => (device.mfg_id(), device.hdw_id(), device.dev_id()),
+                    None => (0, 0, 0),
+                };
+                self.registers.x = mfg_id as Word;
+                self.registers.y = hdw_id as Word;
+                self.registers.z = dev_id;
+            }
+            OpCode::HWI => {
+                let port = self.load(u) as usize;
+                if let Some(device) = self.hardware.get_mut(port) {
+                    let taken = device.interrupt(&self.clock, &mut self.registers, &mut self.memory, &mut self.irq)?;
+                    cycles = cycles.saturating_add(taken);
+                    self.clock.advance(device.take_skip());
+                }
+            }
+            OpCode::SET => {
+                let value = self.load(u);
+                self.store(m, value);
+                if let (Some(Operand::Register(Register::PC)), Location::Memory(slot)) = (instruction.m, u) {
+                    if instruction.u == Some(Operand::Pop) {
+                        self.leave(address, slot);
+                    }
+                }
+            }
+            OpCode::ADD => {
+                let (value, overflow) = self.load(m).overflowing_add(self.load(u));
+                self.store(m, value);
+                self.registers.ps = if overflow { 0x0001 } else { 0x0000 };
+            }
+            OpCode::SUB => {
+                let (value, underflow) = self.load(m).overflowing_sub(self.load(u));
+                self.store(m, value);
+                self.registers.ps = if underflow { 0xFFFF } else { 0x0000 };
+            }
+            OpCode::MUL => {
+                let value = self.load(m) as u32 * self.load(u) as u32;
+                self.store(m, value as Word);
+                self.registers.ps = (value >> 16) as Word;
+            }
+            OpCode::MLI => {
+                let value = self.load(m) as i16 as i32 * self.load(u) as i16 as i32;
+                self.store(m, value as Word);
+                self.registers.ps = (value >> 16) as Word;
+            }
+            OpCode::DIV => {
+                let (m_val, u_val) = (self.load(m) as u32, self.load(u) as u32);
+                let value = m_val.checked_div(u_val).unwrap_or(0);
+                let ps = (m_val << 16).checked_div(u_val).unwrap_or(0);
+                self.store(m, value as Word);
+                self.registers.ps = ps as Word;
+            }
+            OpCode::DVI => {
+                let (m_val, u_val) = (self.load(m) as i16 as i64, self.load(u) as i16 as i64);
+                let value = m_val.checked_div(u_val).unwrap_or(0);
+                let ps = (m_val << 16).checked_div(u_val).unwrap_or(0);
+                self.store(m, value as Word);
+                self.registers.ps = ps as Word;
+            }
+            OpCode::MOD => {
+                let (m_val, u_val) = (self.load(m), self.load(u));
+                self.store(m, if u_val == 0 { 0 } else { m_val % u_val });
+            }
+            OpCode::MDI => {
+                let (m_val, u_val) = (self.load(m) as i16, self.load(u) as i16);
+                self.store(m, if u_val == 0 { 0 } else { m_val.wrapping_rem(u_val) as Word });
+            }
+            OpCode::AND => {
+                let value = self.load(m) & self.load(u);
+                self.store(m, value);
+            }
+            OpCode::BOR => {
+                let value = self.load(m) | self.load(u);
+                self.store(m, value);
+            }
+            OpCode::XOR => {
+                let value = self.load(m) ^ self.load(u);
+                self.store(m, value);
+            }
+            OpCode::LLS => {
+                let value = (self.load(m) as u32).checked_shl(self.load(u) as u32).unwrap_or(0);
+                self.store(m, value as Word);
+                self.registers.ps = (value >> 16) as Word;
+            }
+            OpCode::LRS => {
+                let value = ((self.load(m) as u32) << 16).checked_shr(self.load(u) as u32).unwrap_or(0);
+                self.store(m, (value >> 16) as Word);
+                self.registers.ps = value as Word;
+            }
+            OpCode::ARS => {
+                let value = ((self.load(m) as i16 as i32) << 16) >> self.load(u).min(31);
+                self.store(m, (value >> 16) as Word);
+                self.registers.ps = value as Word;
+            }
+            OpCode::IFB | OpCode::IFC | OpCode::IFE | OpCode::IFN |
+            OpCode::IFG | OpCode::IFA | OpCode::IFL | OpCode::IFU => {
+                let (m_val, u_val) = (self.load(m), self.load(u));
+                let passed = match instruction.opcode {
+                    OpCode::IFB => m_val & u_val != 0,
+                    OpCode::IFC => m_val & u_val == 0,
+                    OpCode::IFE => m_val == u_val,
+                    OpCode::IFN => m_val != u_val,
+                    OpCode::IFG => m_val > u_val,
+                    OpCode::IFA => (m_val as i16) > (u_val as i16),
+                    OpCode::IFL => m_val < u_val,
+                    _ => (m_val as i16) < (u_val as i16),
+                };
+                if !passed {
+                    cycles = cycles.saturating_add(self.skip());
+                }
+            }
+            OpCode::ADX => {
+                let value = self.load(m) as u32 + self.load(u) as u32 + self.registers.ps as u32;
+                self.store(m, value as Word);
+                self.registers.ps = if value > 0xFFFF { 0x0001 } else { 0x0000 };
+            }
+            OpCode::SBX => {
+                // PS holds 0xFFFF after a borrow, which carries as -1
+                let value = self.load(m) as i32 - self.load(u) as i32 + self.registers.ps as i16 as i32;
+                self.store(m, value as Word);
+                self.registers.ps = if value < 0 { 0xFFFF } else { 0x0000 };
+            }
+            OpCode::STI | OpCode::STD => {
+                let value = self.load(u);
+                self.store(m, value);
+                let delta = if instruction.opcode == OpCode::STI { 0x0001 } else { 0xFFFF };
+                self.registers.i = self.registers.i.wrapping_add(delta);
+                self.registers.j = self.registers.j.wrapping_add(delta);
+            }
+        }
+        Ok(cycles)
+    }
+    /// Push a call onto the shadow call stack
+    fn enter(&mut self, call: Call) {
+        if self.history.is_some() && self.calls_before.is_none() {
+            self.calls_before = Some(self.calls.clone());
+        }
+        if let Some(ref mut profile) = self.profile {
+            profile.enter(&call);
+        }
+        self.calls.call(call);
+    }
+    /// Pop the shadow call stack for a return from address through the stack at slot
+    fn leave(&mut self, address: Word, slot: Word) {
+        if self.history.is_some() && self.calls_before.is_none() {
+            self.calls_before = Some(self.calls.clone());
+        }
+        self.mismatch = self.calls.ret(address, self.registers.pc, slot);
+    }
+    /// Skip the next instruction and any chained conditionals, returning the cycles taken
+    fn skip(&mut self) -> u16 {
+        let mut cycles = 0;
+        while cycles < 0xFFFF {
+            let next = Instruction::decode(&self.memory, self.registers.pc);
+            self.registers.pc = self.registers.pc.wrapping_add(next.size);
+            cycles += 1;
+            if !next.opcode.is_conditional() {
+                break;
+            }
+        }
+        cycles
+    }
+    /// Resolve an Operand, applying any stack side effects
+    fn resolve(&mut self, operand: Operand) -> Location {
+        match operand {
+            Operand::Register(register) => Location::Register(register),
+            Operand::Indirect(register) => Location::Memory(self.registers.get(register)),
+            Operand::Offset(register, offset) => Location::Memory(self.registers.get(register).wrapping_add(offset)),
+            Operand::Push => {
+                self.registers.sp = self.registers.sp.wrapping_sub(1);
+                Location::Memory(self.registers.sp)
+            }
+            Operand::Pop => {
+                let sp = self.registers.sp;
+                self.registers.sp = sp.wrapping_add(1);
+                Location::Memory(sp)
+            }
+            Operand::Direct(address) => Location::Memory(address),
+            Operand::Literal(value) => Location::Literal(value),
+        }
+    }
+    /// Read a resolved Operand without recording the read
+    fn peek(&self, location: Location) -> Word {
+        match location {
+            Location::Register(register) => self.registers.get(register),
+            Location::Memory(address) => self.memory.get(address),
+            Location::Literal(value) => value,
+        }
+    }
+    /// Read a resolved Operand
+    fn load(&mut self, location: Location) -> Word {
+        match location {
+            Location::Register(register) => self.registers.get(register),
+            Location::Memory(address) => {
+                if let Some(ref mut reads) = self.reads {
+                    reads.push(address);
+                }
+                self.memory.get(address)
+            }
+            Location::Literal(value) => value,
+        }
+    }
+    /// Write a resolved Operand, silently ignoring literals
+    fn store(&mut self, location: Location, value: Word) {
+        match location {
+            Location::Register(register) => self.registers.set(register, value),
+            Location::Memory(address) => self.memory.set(address, value),
+            Location::Literal(_) => {}
+        }
+    }
+    /// Push value onto the Stack
+    fn push(&mut self, value: Word) {
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.memory.set(self.registers.sp, value);
+    }
+    /// Pop value from the Stack
+    fn pop(&mut self) -> Word {
+        let value = self.load(Location::Memory(self.registers.sp));
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        value
+    }
+}
+
+impl Default for System {
+    fn default() -> System {
+        System::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use super::super::{Breakpoint, Clock, Event, Hardware, Memory, OpCode, Queue, Register, Registers, State, Stop,
+                       SystemError, Trace, TraceFormat, TraceWriter, Trigger, Word};
+    use assembler::assemble;
+    use super::super::hardware::Lem1802;
+    use super::System;
 
+    /// Device halting the CPU for as long as a Word of cycles allows
+    struct Stall;
 
-        // Iterate through Hardware
-        for device in &self.hardware {
-            //registers: &Registers, memory: &Memory, clock: &Clock, pic: &PIC
-            device.update(&self.clock, &mut self.registers, &mut self.memory, &mut PIC)?;
+    impl Hardware for Stall {
+        fn mfg_id(&self) -> u32 { 0 }
+        fn hdw_id(&self) -> u32 { 0 }
+        fn dev_id(&self) -> Word { 0 }
+        fn interrupt(&mut self, _: &Clock, _: &mut Registers, _: &mut Memory, _: &mut Queue) -> Result<u16, SystemError> {
+            Ok(0xFFFF)
+        }
+        fn update(&mut self, _: &Clock, _: &mut Registers, _: &mut Memory, _: &mut Queue) -> Result<(), SystemError> {
+            Ok(())
         }
     }
-}
 
+    /// Program calling a subroutine which returns onto ERR
+    fn program() -> System {
+        let mut sys = System::new();
+        sys.memory_mut().write(0x0000, &[
+            0x7C01, 0x0030,         // SET A, 0x0030
+            0x7FC1, 0x0020, 0x1000, // SET [0x1000], 0x0020
+            0x7803, 0x1000,         // SUB A, [0x1000]
+            0x7C10, 0x0010,         // IFB A, 0x0010
+            0x7C20, 0x000C,         // JSR 0x000C
+            0xFC00,                 // ERR
+            0x0400,                 // CLK (0x000C)
+            0x6381,                 // SET PC, POP
+        ]).unwrap();
+        sys
+    }
+
+    #[test]
+    pub fn test_program() {
+        let mut sys = program();
+
+        // SET A, 0x0030 takes 2 cycles
+        sys.step().unwrap();
+        assert_eq!(State::Execute { address: 0x0000, cycles: 1 }, sys.state());
+        sys.step().unwrap();
+        assert_eq!(State::Idle, sys.state());
+        assert_eq!(0x0030, sys.registers().a);
+
+        // SET, SUB, IFB then JSR into the subroutine
+        for _ in 0..4 {
+            sys.step_instruction().unwrap();
+        }
+        assert_eq!(0x0010, sys.registers().a);
+        assert_eq!(0x000C, sys.registers().pc);
+        assert_eq!(0xFFFF, sys.registers().sp);
+        assert_eq!(0x000B, sys.memory().get(0xFFFF));
+
+        // CLK then return onto ERR
+        sys.step_instruction().unwrap();
+        assert_eq!(sys.clock().cycles() as u16, sys.registers().j);
+        sys.step_instruction().unwrap();
+        assert_eq!(0x000B, sys.registers().pc);
+        assert_eq!(SystemError::InvalidInstruction, sys.step().unwrap_err());
+        assert!(sys.clock().halted());
+    }
+
+    #[test]
+    pub fn test_interrupts() {
+        let mut sys = System::new();
+        sys.memory_mut().write(0x0000, &[
+            0x7D40, 0x0010,         // IAS 0x0010
+            0x0000,                 // NOP (0x0002)
+            0x0000,                 // NOP
+        ]).unwrap();
+        sys.memory_mut().write(0x0010, &[
+            0x0021,                 // SET B, A
+            0x0160,                 // RFI 0
+        ]).unwrap();
+
+        // Interrupts are ignored until IA is set
+        sys.interrupt(0x0001).unwrap();
+        assert!(sys.queue().is_empty());
+        sys.step_instruction().unwrap();
+        sys.registers_mut().a = 0x1234;
+        sys.interrupt(0x0042).unwrap();
+
+        // Interrupt is triggered before the next fetch
+        sys.step_instruction().unwrap();
+        assert_eq!(0x0042, sys.registers().b);
+        assert!(sys.queue().is_enabled());
+
+        // Return restores A and PC
+        sys.step_instruction().unwrap();
+        assert_eq!(0x1234, sys.registers().a);
+        assert_eq!(0x0002, sys.registers().pc);
+        assert!(sys.queue().is_disabled());
+    }
+
+    #[test]
+    pub fn test_extended() {
+        let mut sys = System::new();
+        sys.attach(Box::new(Lem1802::new()));
+        sys.memory_mut().write(0x0000, &[
+            0x8401,                 // SET A, 0
+            0x8803,                 // SUB A, 1
+            0x8421,                 // SET B, 0
+            0x843B,                 // SBX B, 0
+            0x8620,                 // HWQ 0
+        ]).unwrap();
+
+        // Borrowing through both words of a 32 bit subtraction borrows again
+        for _ in 0..4 {
+            sys.step_instruction().unwrap();
+        }
+        assert_eq!((0xFFFF, 0xFFFF, 0xFFFF), (sys.registers().a, sys.registers().b, sys.registers().ps));
+
+        // Manufacturer, hardware id and version in X, Y and Z
+        sys.step_instruction().unwrap();
+        assert_eq!((0x8B36, 0xF615, 0x1802), (sys.registers().x, sys.registers().y, sys.registers().z));
+    }
+
+    /// Device raising an interrupt every cycle
+    struct Flood;
+
+    impl Hardware for Flood {
+        fn mfg_id(&self) -> u32 { 0 }
+        fn hdw_id(&self) -> u32 { 0 }
+        fn dev_id(&self) -> Word { 0 }
+        fn interrupt(&mut self, _: &Clock, _: &mut Registers, _: &mut Memory, _: &mut Queue) -> Result<u16, SystemError> {
+            Ok(0)
+        }
+        fn update(&mut self, _: &Clock, _: &mut Registers, _: &mut Memory, queue: &mut Queue) -> Result<(), SystemError> {
+            queue.enqueue(0x0001)
+        }
+    }
+
+    /// Assemble and run source until it runs off its end
+    fn run(source: &str) -> System {
+        let program = assemble(source).unwrap();
+        let mut sys = System::new();
+        sys.memory_mut().write(0x0000, &program.words).unwrap();
+        while (sys.registers().pc as usize) < program.words.len() {
+            sys.step_instruction().unwrap();
+        }
+        sys
+    }
+
+    #[test]
+    pub fn test_opcodes() {
+        let cases: Vec<(&str, Vec<(Register, Word)>)> = vec![
+            // Arithmetic carries and borrows through PS
+            ("SET A, 0xFFFF\nADD A, 2", vec![(Register::A, 0x0001), (Register::PS, 0x0001)]),
+            ("SET A, 1\nSUB A, 2", vec![(Register::A, 0xFFFF), (Register::PS, 0xFFFF)]),
+            ("SET A, 0x8000\nMUL A, 4", vec![(Register::A, 0x0000), (Register::PS, 0x0002)]),
+            ("SET A, 0xFFFE\nMLI A, 3", vec![(Register::A, 0xFFFA), (Register::PS, 0xFFFF)]),
+            ("SET A, 0xFFFF\nADD A, 1\nSET B, 0xFFFF\nADX B, 0", vec![(Register::B, 0x0000), (Register::PS, 0x0001)]),
+            ("SET A, 0xFFFF\nADD A, 1\nSET B, 2\nADX B, 3", vec![(Register::B, 0x0006), (Register::PS, 0x0000)]),
+            ("SET A, 0\nSUB A, 1\nSET B, 5\nSBX B, 2", vec![(Register::B, 0x0002), (Register::PS, 0x0000)]),
+            ("SET B, 1\nSBX B, 2", vec![(Register::B, 0xFFFF), (Register::PS, 0xFFFF)]),
+
+            // Division keeps the fraction in PS and yields 0 when dividing by zero
+            ("SET A, 7\nDIV A, 2", vec![(Register::A, 0x0003), (Register::PS, 0x8000)]),
+            ("SET A, 7\nSET PS, 0x1234\nDIV A, 0", vec![(Register::A, 0x0000), (Register::PS, 0x0000)]),
+            ("SET A, 0xFFF9\nDVI A, 2", vec![(Register::A, 0xFFFD), (Register::PS, 0x8000)]),
+            ("SET A, 0xFFF9\nSET PS, 0x1234\nDVI A, 0", vec![(Register::A, 0x0000), (Register::PS, 0x0000)]),
+            ("SET A, 7\nMOD A, 3\nSET B, 7\nMOD B, 0", vec![(Register::A, 0x0001), (Register::B, 0x0000)]),
+            ("SET A, 0xFFF9\nMDI A, 16\nSET B, 7\nMDI B, 0", vec![(Register::A, 0xFFF9), (Register::B, 0x0000)]),
+
+            // Shifts of 16 or more move everything into PS, then out of both
+            ("SET A, 0x8001\nLLS A, 1", vec![(Register::A, 0x0002), (Register::PS, 0x0001)]),
+            ("SET A, 0x1234\nLLS A, 16", vec![(Register::A, 0x0000), (Register::PS, 0x1234)]),
+            ("SET A, 0x1234\nLLS A, 32", vec![(Register::A, 0x0000), (Register::PS, 0x0000)]),
+            ("SET A, 0x8001\nLRS A, 1", vec![(Register::A, 0x4000), (Register::PS, 0x8000)]),
+            ("SET A, 0x8001\nLRS A, 16", vec![(Register::A, 0x0000), (Register::PS, 0x8001)]),
+            ("SET A, 0x8001\nLRS A, 40", vec![(Register::A, 0x0000), (Register::PS, 0x0000)]),
+            ("SET A, 0x8000\nARS A, 4", vec![(Register::A, 0xF800), (Register::PS, 0x0000)]),
+            ("SET A, 0x8000\nARS A, 20", vec![(Register::A, 0xFFFF), (Register::PS, 0xF800)]),
+            ("SET A, 0x8000\nARS A, 40", vec![(Register::A, 0xFFFF), (Register::PS, 0xFFFF)]),
+
+            // Bitwise
+            ("SET A, 0x0FF0\nAND A, 0x3C3C\nSET B, 0x0FF0\nBOR B, 0x3C3C\nSET C, 0x0FF0\nXOR C, 0x3C3C",
+             vec![(Register::A, 0x0C30), (Register::B, 0x3FFC), (Register::C, 0x33CC)]),
+
+            // Each comparison, unsigned and signed
+            ("SET A, 0xFFFF\nIFG A, 0\nBOR B, 1\nIFA A, 0\nBOR B, 2\nIFL A, 0\nBOR B, 4\nIFU A, 0\nBOR B, 8\n\
+              IFB A, 0x10\nBOR B, 16\nIFC A, 0x10\nBOR B, 32\nIFE A, 0xFFFF\nBOR B, 64\nIFN A, 0xFFFF\nBOR B, 128",
+             vec![(Register::B, 0x0059)]),
+
+            // Failing conditionals skip the whole chain after them
+            ("SET A, 1\nIFE A, 2\nIFN A, 3\nSET B, 1\nSET C, 1", vec![(Register::B, 0x0000), (Register::C, 0x0001)]),
+            ("SET A, 1\nIFE A, 1\nIFE A, 3\nSET B, 1\nSET C, 1", vec![(Register::B, 0x0000), (Register::C, 0x0001)]),
+            ("SET A, 1\nIFE A, 1\nIFN A, 3\nSET B, 1\nSET C, 1", vec![(Register::B, 0x0001), (Register::C, 0x0001)]),
+
+            // Stream copies step I and J
+            ("SET I, 1\nSET J, 2\nSTI A, 5", vec![(Register::A, 0x0005), (Register::I, 0x0002), (Register::J, 0x0003)]),
+            ("SET I, 1\nSET J, 2\nSTD A, 5", vec![(Register::A, 0x0005), (Register::I, 0x0000), (Register::J, 0x0001)]),
+
+            // Interrupt address
+            ("IAS 0x0010\nIAG A", vec![(Register::A, 0x0010), (Register::IA, 0x0010)]),
+        ];
+        for (source, expected) in cases {
+            let sys = run(source);
+            for (register, value) in expected {
+                assert_eq!(value, sys.registers().get(register), "{} after {:?}", register, source);
+            }
+        }
+
+        // Skipping a chain takes a cycle for each instruction skipped
+        let sys = run("IFE A, 1\nIFN A, 3\nSET B, 1");
+        assert_eq!(2 + 1 + 1, sys.clock().cycles());
+    }
+
+    #[test]
+    pub fn test_stall() {
+        let mut sys = System::new();
+        sys.attach(Box::new(Stall));
+        sys.memory_mut().write(0x0000, &[
+            0x8640,                 // HWI 0
+            0x8401,                 // SET A, 0
+        ]).unwrap();
+
+        // Cycles taken by the device saturate rather than overflow
+        sys.step_instruction().unwrap();
+        assert_eq!(0xFFFF, sys.clock().cycles());
+        assert_eq!(0x0001, sys.registers().pc);
+    }
+
+    #[test]
+    pub fn test_overflow() {
+        let mut sys = System::new();
+        sys.attach(Box::new(Flood));
+        sys.memory_mut().write(0x0000, &[
+            0x7D40, 0x0100,         // IAS 0x0100
+        ]).unwrap();
+
+        // A device overflowing the queue while the handler never returns halts and catches fire
+        assert_eq!(SystemError::InterruptOverflow, sys.run(1000).unwrap_err());
+        assert!(sys.clock().halted());
+        assert_eq!(SystemError::ClockHalted, sys.step().unwrap_err());
+    }
+
+    #[test]
+    pub fn test_history() {
+        let mut sys = program();
+        sys.record_history(1000);
+        let start = sys.memory().clone();
+        while sys.step().is_ok() {}
+        assert!(sys.clock().halted());
+        assert_eq!(8, sys.history().unwrap().instructions());
+
+        // Run back to the subroutine before the clock halted
+        let id = sys.add_breakpoint(Breakpoint::new(Trigger::Address(0x000C)));
+        assert_eq!(Stop { id, event: Event::Address(0x000C) }, sys.run_back().unwrap());
+        assert_eq!(0x000C, sys.registers().pc);
+        assert_eq!(State::Idle, sys.state());
+        assert!(!sys.clock().halted());
+
+        // Stepping back over JSR undoes the push
+        sys.step_back_instruction().unwrap();
+        assert_eq!(0x0009, sys.registers().pc);
+        assert_eq!(0x0000, sys.registers().sp);
+        assert_eq!(0x0000, sys.memory().get(0xFFFF));
+
+        // Stepping back a cycle lands part way through IFB
+        let cycles = sys.clock().cycles();
+        sys.step_back().unwrap();
+        assert_eq!(cycles - 1, sys.clock().cycles());
+        assert_eq!(State::Execute { address: 0x0007, cycles: 1 }, sys.state());
+
+        // No earlier breakpoint rewinds to the start of History
+        assert_eq!(SystemError::HistoryExhausted, sys.run_back().unwrap_err());
+        assert_eq!(&Registers::new(), sys.registers());
+        assert_eq!(0, sys.clock().cycles());
+        assert!(sys.memory().diff(&start).is_empty());
+
+        // Running forward again replays the same instructions
+        for _ in 0..5 {
+            sys.step_instruction().unwrap();
+        }
+        assert_eq!(0x000C, sys.registers().pc);
+        assert_eq!(0x000B, sys.memory().get(0xFFFF));
+
+        // Watchpoints stop at the instruction which wrote, read or changed what they watch
+        while sys.step().is_ok() {}
+        sys.remove_breakpoint(id);
+        let id = sys.add_breakpoint(Breakpoint::new(Trigger::Write(0xFFFF, 1)));
+        let event = Event::Write { address: 0xFFFF, old: 0x0000, new: 0x000B };
+        assert_eq!(Stop { id, event }, sys.run_back().unwrap());
+        assert_eq!(0x0009, sys.registers().pc);
+        sys.remove_breakpoint(id);
+        let id = sys.add_breakpoint(Breakpoint::new(Trigger::Read(0x1000, 1)));
+        assert_eq!(Stop { id, event: Event::Read(0x1000) }, sys.run_back().unwrap());
+        assert_eq!(0x0005, sys.registers().pc);
+        sys.remove_breakpoint(id);
+        let id = sys.add_breakpoint(Breakpoint::new(Trigger::Register(Register::A)));
+        let event = Event::Register { register: Register::A, old: 0x0000, new: 0x0030 };
+        assert_eq!(Stop { id, event }, sys.run_back().unwrap());
+        assert_eq!(0x0000, sys.registers().pc);
+
+        // Too little History to reach the start of an instruction leaves the System as it was
+        let mut sys = program();
+        sys.record_history(1);
+        sys.step_instruction().unwrap();
+        sys.step_instruction().unwrap();
+        let (registers, cycles) = (*sys.registers(), sys.clock().cycles());
+        assert_eq!(SystemError::HistoryExhausted, sys.step_back_instruction().unwrap_err());
+        assert_eq!((&registers, cycles), (sys.registers(), sys.clock().cycles()));
+    }
+
+    #[test]
+    pub fn test_breakpoints() {
+        let mut sys = System::new();
+        sys.memory_mut().write(0x0000, &[
+            0x8401,                 // SET A, 0
+            0x8802,                 // ADD A, 1 (0x0001)
+            0x03C1, 0x1000,         // SET [0x1000], A
+            0x7821, 0x1000,         // SET B, [0x1000]
+            0x8B81,                 // SET PC, 1
+        ]).unwrap();
+        sys.memory_mut().write(0x0100, &[
+            0x0021,                 // SET B, A
+            0x0160,                 // RFI 0
+        ]).unwrap();
+
+        // Address with a condition
+        let id = sys.add_breakpoint(Breakpoint::new(Trigger::Address(0x0002)).when("A == 3".parse().unwrap()));
+        assert_eq!(Some(Stop { id, event: Event::Address(0x0002) }), sys.run(1000).unwrap());
+        assert_eq!(3, sys.registers().a);
+        assert_eq!(1, sys.breakpoints()[&id].hits);
+        sys.remove_breakpoint(id);
+
+        // Memory write passing over the first hit
+        let id = sys.add_breakpoint(Breakpoint::new(Trigger::Write(0x1000, 1)).ignore(1));
+        let event = Event::Write { address: 0x1000, old: 0x0003, new: 0x0004 };
+        assert_eq!(Some(Stop { id, event }), sys.run(1000).unwrap());
+        assert_eq!(2, sys.breakpoints()[&id].hits);
+        sys.breakpoint_mut(id).unwrap().enabled = false;
+
+        // Memory read and register change
+        let id = sys.add_breakpoint(Breakpoint::new(Trigger::Read(0x0FFF, 2)));
+        assert_eq!(Some(Stop { id, event: Event::Read(0x1000) }), sys.run(1000).unwrap());
+        assert_eq!(0x0004, sys.registers().b);
+        sys.remove_breakpoint(id);
+        let id = sys.add_breakpoint(Breakpoint::new(Trigger::Register(Register::A)));
+        let event = Event::Register { register: Register::A, old: 0x0004, new: 0x0005 };
+        assert_eq!(Some(Stop { id, event }), sys.run(1000).unwrap());
+        sys.remove_breakpoint(id);
+
+        // Cycle count
+        let cycle = sys.clock().cycles() + 5;
+        let id = sys.add_breakpoint(Breakpoint::new(Trigger::Cycle(cycle)));
+        assert_eq!(Some(Stop { id, event: Event::Cycle(cycle) }), sys.run(1000).unwrap());
+        assert_eq!(cycle, sys.clock().cycles());
+
+        // Interrupt delivery
+        let id = sys.add_breakpoint(Breakpoint::new(Trigger::Interrupt(Some(0x0042))));
+        sys.registers_mut().ia = 0x0100;
+        sys.interrupt(0x0042).unwrap();
+        assert_eq!(Some(Stop { id, event: Event::Interrupt(0x0042) }), sys.run(1000).unwrap());
+        assert_eq!(0x0042, sys.registers().b);
+        sys.remove_breakpoint(id);
+
+        // Interrupt handler address stops before its first instruction
+        let id = sys.add_breakpoint(Breakpoint::new(Trigger::Address(0x0100)));
+        sys.interrupt(0x0043).unwrap();
+        assert_eq!(Some(Stop { id, event: Event::Address(0x0100) }), sys.run(1000).unwrap());
+        assert_eq!((0x0043, 0x0042), (sys.registers().a, sys.registers().b));
+        sys.step_instruction().unwrap();
+        assert_eq!(0x0043, sys.registers().b);
+        sys.remove_breakpoint(id);
+
+        // Nothing left to hit
+        assert_eq!(None, sys.run(1000).unwrap());
+    }
+
+    #[test]
+    pub fn test_trace() {
+        let mut sys = program();
+        let traces = Rc::new(RefCell::new(Vec::<Trace>::new()));
+        sys.trace(Box::new(traces.clone()));
+        while sys.step().is_ok() {}
+
+        // One record per instruction including the faulting ERR
+        let traces = traces.borrow();
+        assert_eq!(8, traces.len());
+        assert_eq!(OpCode::ERR, traces[7].instruction.opcode);
+        assert_eq!(vec![0x7FC1, 0x0020, 0x1000], traces[1].words);
+        assert_eq!(vec![(0x1000, 0x0000, 0x0020)], traces[1].writes);
+        assert_eq!((Some(0x0020), Some(0x0030)), (traces[2].u, traces[2].m));
+
+        // JSR pushes the return address
+        let jsr = &traces[4];
+        assert_eq!(0x0009, jsr.pc);
+        assert_eq!(vec![(Register::PC, 0x0009, 0x000C), (Register::SP, 0x0000, 0xFFFF)], jsr.registers);
+        assert_eq!(vec![(0xFFFF, 0x0000, 0x000B)], jsr.writes);
+        assert_eq!("0000000012 0009 | 7C20 000C | JSR 0x000C | u=000C | PC=0009>000C SP=0000>FFFF | \
+                    [FFFF]=0000>000B | -", jsr.to_string());
+
+        // Text lines written to a sink
+        let mut sys = program();
+        sys.trace(Box::new(TraceWriter::new(Vec::new(), TraceFormat::Text)));
+        sys.run(3).unwrap();
+        assert!(sys.stop_trace().is_some());
+        sys.run(3).unwrap();
+        assert!(sys.stop_trace().is_none());
+    }
+
+    #[test]
+    pub fn test_calls() {
+        let mut sys = program();
+        sys.record_history(1000);
+
+        // Step over runs the subroutine through to its return
+        for _ in 0..4 {
+            sys.step_instruction().unwrap();
+        }
+        assert_eq!(0x0009, sys.registers().pc);
+        assert_eq!(None, sys.step_over(1000).unwrap());
+        assert_eq!(0x000B, sys.registers().pc);
+        assert_eq!(0, sys.call_stack().depth());
+
+        // Step back into the subroutine and out again
+        sys.step_back_instruction().unwrap();
+        assert_eq!(1, sys.call_stack().depth());
+        let frames: Vec<String> = sys.backtrace(None).iter().map(|frame| frame.to_string()).collect();
+        assert_eq!(vec!["0x000D in 0x000C", "0x0009"], frames);
+        assert_eq!(None, sys.step_out(1000).unwrap());
+        assert_eq!(0x000B, sys.registers().pc);
+
+        // Running out of cycles inside the subroutine is distinct from returning
+        sys.step_back_instruction().unwrap();
+        assert_eq!(Err(SystemError::StepLimit), sys.step_out(0));
+        assert_eq!(1, sys.call_stack().depth());
+        assert_eq!(None, sys.step_out(1000).unwrap());
+
+        // Corrupting the return address is flagged
+        sys.step_back_instruction().unwrap();
+        sys.memory_mut().set(0xFFFF, 0x0000);
+        let id = sys.add_breakpoint(Breakpoint::new(Trigger::Return));
+        let stop = sys.step_instruction().unwrap().unwrap();
+        assert_eq!(id, stop.id);
+        assert_eq!("Breakpoint 1 on mismatched return: 0x000D returned to 0x0000 from stack 0xFFFF, \
+                    expected 0x000B from stack 0xFFFF", stop.to_string());
+        assert_eq!(0, sys.call_stack().depth());
+    }
+}
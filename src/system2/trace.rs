@@ -0,0 +1,260 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Execution Trace Formats
+//!
+//! Each executed instruction produces one line. The text format is six fields separated by
+//! ` | ` after the cycle and address, with `-` marking an empty field. Text lines parse back
+//! into a Trace, decoding the instruction from its raw words:
+//!
+//! ```text
+//! 0000000001 0000 | 7C01 0030 | SET A, 0x0030 | u=0030 | A=0000>0030 PC=0000>0002 | - | -
+//! ```
+//!
+//! The fields are the raw words, the decoded instruction, the upper and middle operand values
+//! before execution, changed registers, memory writes as `[address]=old>new` and the message
+//! of an interrupt delivered immediately before the instruction. The JSON-lines format holds
+//! the same fields with decimal numbers and a fixed key order:
+//!
+//! ```text
+//! {"cycle":1,"pc":0,"words":[31745,48],"instruction":"SET A, 0x0030","u":48,"m":null,
+//!  "registers":{"A":[0,48],"PC":[0,2]},"writes":[],"interrupt":null}
+//! ```
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::str::FromStr;
+use super::Instruction;
+use super::Register;
+use super::SystemError;
+use super::Word;
+
+/// Record of a single executed instruction
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Trace {
+    /// Clock cycle the instruction executed on
+    pub cycle: u64,
+    /// Address of the instruction
+    pub pc: Word,
+    /// Raw instruction words
+    pub words: Vec<Word>,
+    /// Decoded Instruction
+    pub instruction: Instruction,
+    /// Upper Operand value before execution
+    pub u: Option<Word>,
+    /// Middle Operand value before execution
+    pub m: Option<Word>,
+    /// Changed registers as register, old and new value
+    pub registers: Vec<(Register, Word, Word)>,
+    /// Memory writes as address, old and new value
+    pub writes: Vec<(Word, Word, Word)>,
+    /// Interrupt message delivered before the instruction
+    pub interrupt: Option<Word>,
+}
+
+impl Trace {
+    /// Format as a single JSON object without a trailing newline
+    pub fn to_json(&self) -> String {
+        let option = |value: Option<Word>| value.map_or("null".to_string(), |value| value.to_string());
+        let words: Vec<String> = self.words.iter().map(|word| word.to_string()).collect();
+        let registers: Vec<String> = self.registers.iter()
+            .map(|&(register, old, new)| format!("\"{}\":[{},{}]", register, old, new))
+            .collect();
+        let writes: Vec<String> = self.writes.iter()
+            .map(|&(address, old, new)| format!("[{},{},{}]", address, old, new))
+            .collect();
+        format!("{{\"cycle\":{},\"pc\":{},\"words\":[{}],\"instruction\":\"{}\",\"u\":{},\"m\":{},\
+                 \"registers\":{{{}}},\"writes\":[{}],\"interrupt\":{}}}",
+                self.cycle, self.pc, words.join(","), self.instruction, option(self.u), option(self.m),
+                registers.join(","), writes.join(","), option(self.interrupt))
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let field = |items: Vec<String>| if items.is_empty() { "-".to_string() } else { items.join(" ") };
+        let words = field(self.words.iter().map(|word| format!("{:04X}", word)).collect());
+        let operands = field(self.u.iter().map(|u| format!("u={:04X}", u))
+            .chain(self.m.iter().map(|m| format!("m={:04X}", m)))
+            .collect());
+        let registers = field(self.registers.iter()
+            .map(|&(register, old, new)| format!("{}={:04X}>{:04X}", register, old, new))
+            .collect());
+        let writes = field(self.writes.iter()
+            .map(|&(address, old, new)| format!("[{:04X}]={:04X}>{:04X}", address, old, new))
+            .collect());
+        let interrupt = field(self.interrupt.iter().map(|message| format!("{:04X}", message)).collect());
+        write!(f, "{:010} {:04X} | {} | {} | {} | {} | {} | {}", self.cycle, self.pc, words,
+               self.instruction, operands, registers, writes, interrupt)
+    }
+}
+
+impl FromStr for Trace {
+    type Err = SystemError;
+    /// Parse a line in the text format, decoding the instruction from its words
+    fn from_str(line: &str) -> Result<Trace, SystemError> {
+        let hex = |text: &str| Word::from_str_radix(text, 16).map_err(|_| SystemError::InvalidTrace);
+        let change = |text: &str| match text.find('>') {
+            Some(split) => Ok((hex(&text[..split])?, hex(&text[split + 1..])?)),
+            None => Err(SystemError::InvalidTrace),
+        };
+        let fields: Vec<&str> = line.trim().split(" | ").collect();
+        if fields.len() != 7 {
+            return Err(SystemError::InvalidTrace);
+        }
+        let items = |field: &str| -> Vec<String> {
+            if field == "-" { Vec::new() } else { field.split(' ').map(str::to_string).collect() }
+        };
+        let mut head = fields[0].split(' ');
+        let cycle = head.next().and_then(|cycle| cycle.parse().ok()).ok_or(SystemError::InvalidTrace)?;
+        let pc = hex(head.next().ok_or(SystemError::InvalidTrace)?)?;
+        let words = items(fields[1]).iter().map(|word| hex(word)).collect::<Result<Vec<Word>, _>>()?;
+        let (mut u, mut m) = (None, None);
+        for operand in items(fields[3]) {
+            match operand.split_at(operand.find('=').ok_or(SystemError::InvalidTrace)? + 1) {
+                ("u=", value) => u = Some(hex(value)?),
+                ("m=", value) => m = Some(hex(value)?),
+                _ => return Err(SystemError::InvalidTrace),
+            }
+        }
+        let mut registers = Vec::new();
+        for item in items(fields[4]) {
+            let split = item.find('=').ok_or(SystemError::InvalidTrace)?;
+            let register = Register::from_name(&item[..split]).ok_or(SystemError::InvalidTrace)?;
+            let (old, new) = change(&item[split + 1..])?;
+            registers.push((register, old, new));
+        }
+        let mut writes = Vec::new();
+        for item in items(fields[5]) {
+            let split = item.find("]=").ok_or(SystemError::InvalidTrace)?;
+            let address = hex(item[..split].trim_start_matches('['))?;
+            let (old, new) = change(&item[split + 2..])?;
+            writes.push((address, old, new));
+        }
+        let interrupt = match fields[6] {
+            "-" => None,
+            message => Some(hex(message)?),
+        };
+        Ok(Trace {
+            cycle,
+            pc,
+            instruction: Instruction::from_words(&words),
+            words,
+            u,
+            m,
+            registers,
+            writes,
+            interrupt,
+        })
+    }
+}
+
+/// Destination for Trace records
+pub trait TraceSink {
+    /// Accept the record of an executed instruction
+    fn record(&mut self, trace: &Trace) -> io::Result<()>;
+}
+
+impl TraceSink for Vec<Trace> {
+    fn record(&mut self, trace: &Trace) -> io::Result<()> {
+        self.push(trace.clone());
+        Ok(())
+    }
+}
+
+/// Shared sinks let the caller keep a handle while the System records into it
+impl<T: TraceSink> TraceSink for Rc<RefCell<T>> {
+    fn record(&mut self, trace: &Trace) -> io::Result<()> {
+        self.borrow_mut().record(trace)
+    }
+}
+
+/// Trace line format
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TraceFormat {
+    /// Fixed column text
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Sink writing one formatted line per Trace record
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    format: TraceFormat,
+}
+
+impl<W: Write> TraceWriter<W> {
+    /// Create a TraceWriter in format
+    pub fn new(writer: W, format: TraceFormat) -> TraceWriter<W> {
+        TraceWriter { writer, format }
+    }
+    /// Line format
+    pub fn format(&self) -> TraceFormat {
+        self.format
+    }
+    /// Unwrap the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> TraceSink for TraceWriter<W> {
+    fn record(&mut self, trace: &Trace) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => writeln!(self.writer, "{}", trace),
+            TraceFormat::Json => writeln!(self.writer, "{}", trace.to_json()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Instruction, Memory, Register};
+    use super::Trace;
+
+    #[test]
+    pub fn test_formats() {
+        let mut mem = Memory::new();
+        mem.write(0x0000, &[0x7FC1, 0x0020, 0x1000]).unwrap(); // SET [0x1000], 0x0020
+        let trace = Trace {
+            cycle: 3,
+            pc: 0x0000,
+            words: vec![0x7FC1, 0x0020, 0x1000],
+            instruction: Instruction::decode(&mem, 0x0000),
+            u: Some(0x0020),
+            m: Some(0x0000),
+            registers: vec![(Register::PC, 0x0000, 0x0003)],
+            writes: vec![(0x1000, 0x0000, 0x0020)],
+            interrupt: Some(0x0042),
+        };
+
+        // Text
+        assert_eq!("0000000003 0000 | 7FC1 0020 1000 | SET [0x1000], 0x0020 | u=0020 m=0000 | \
+                    PC=0000>0003 | [1000]=0000>0020 | 0042", trace.to_string());
+
+        // JSON
+        assert_eq!("{\"cycle\":3,\"pc\":0,\"words\":[32705,32,4096],\"instruction\":\"SET [0x1000], 0x0020\",\
+                    \"u\":32,\"m\":0,\"registers\":{\"PC\":[0,3]},\"writes\":[[4096,0,32]],\"interrupt\":66}",
+                   trace.to_json());
+
+        // Text parses back
+        assert_eq!(trace, trace.to_string().parse::<Trace>().unwrap());
+        assert!("0000000003 0000 | 7FC1 | NOP".parse::<Trace>().is_err());
+    }
+}
@@ -0,0 +1,254 @@
+//
+// Copyright 2017 Hans W. Uhlig.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use std::char;
+use std::fmt;
+use super::Memory;
+use super::Word;
+
+/// How each Word of a View is decoded
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Decoding {
+    /// One value and one character per Word
+    Word,
+    /// Two values and two characters per Word, high byte first
+    Byte,
+    /// One value and two characters per Word, high byte first
+    Packed,
+    /// LEM1802 video cell shown as foreground, background, blink and character
+    Cell,
+}
+
+/// How values of a View are printed
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// Hexadecimal
+    Hex,
+    /// Unsigned Decimal
+    Unsigned,
+    /// Signed Decimal
+    Signed,
+}
+
+/// Formatted view over a range of Memory
+#[derive(Clone, Copy)]
+pub struct View<'mem> {
+    memory: &'mem Memory,
+    start: usize,
+    end: usize,
+    columns: usize,
+    decoding: Decoding,
+    format: Format,
+    elide: bool,
+}
+
+impl<'mem> View<'mem> {
+    /// Create a View over all of memory, eliding rows of zeros
+    pub fn new(memory: &'mem Memory) -> View<'mem> {
+        View {
+            memory,
+            start: 0,
+            end: 65536,
+            columns: 16,
+            decoding: Decoding::Word,
+            format: Format::Hex,
+            elide: true,
+        }
+    }
+    /// Restrict View to length Words starting at address
+    pub fn range(mut self, address: Word, length: usize) -> View<'mem> {
+        self.start = address as usize;
+        self.end = (self.start + length).min(65536);
+        self
+    }
+    /// Set number of Words per row
+    pub fn columns(mut self, columns: usize) -> View<'mem> {
+        self.columns = columns.max(1);
+        self
+    }
+    /// Set how Words are decoded
+    pub fn decoding(mut self, decoding: Decoding) -> View<'mem> {
+        self.decoding = decoding;
+        self
+    }
+    /// Set how values are printed
+    pub fn format(mut self, format: Format) -> View<'mem> {
+        self.format = format;
+        self
+    }
+    /// Collapse runs of rows containing only zeros
+    pub fn elide_zeros(mut self, elide: bool) -> View<'mem> {
+        self.elide = elide;
+        self
+    }
+    /// Width of a single value
+    fn value_width(&self) -> usize {
+        match (self.decoding, self.format) {
+            (Decoding::Byte, Format::Hex) => 2,
+            (Decoding::Byte, Format::Unsigned) => 3,
+            (Decoding::Byte, Format::Signed) => 4,
+            (_, Format::Hex) => 4,
+            (_, Format::Unsigned) => 5,
+            (_, Format::Signed) => 6,
+        }
+    }
+    /// Width of the values of a single Word
+    fn cell_width(&self) -> usize {
+        match self.decoding {
+            Decoding::Byte => self.value_width() * 2 + 1,
+            Decoding::Cell => 4,
+            _ => self.value_width(),
+        }
+    }
+    /// Write a single value
+    fn write_value(&self, f: &mut fmt::Formatter, value: Word, bits: u32) -> fmt::Result {
+        let width = self.value_width();
+        match self.format {
+            Format::Hex => write!(f, "{:0width$X}", value, width = width),
+            Format::Unsigned => write!(f, "{:>width$}", value, width = width),
+            Format::Signed if bits == 8 => write!(f, "{:>width$}", value as u8 as i8, width = width),
+            Format::Signed => write!(f, "{:>width$}", value as i16, width = width),
+        }
+    }
+    /// Write the values of a single Word
+    fn write_cell(&self, f: &mut fmt::Formatter, word: Word) -> fmt::Result {
+        match self.decoding {
+            Decoding::Word | Decoding::Packed => self.write_value(f, word, 16),
+            Decoding::Byte => {
+                self.write_value(f, word >> 8, 8)?;
+                write!(f, " ")?;
+                self.write_value(f, word & 0xFF, 8)
+            }
+            Decoding::Cell => {
+                let blink = if word & 0x0080 != 0 { '*' } else { ' ' };
+                write!(f, "{:X}{:X}{}{}", word >> 12, (word >> 8) & 0xF, blink, printable(word & 0x7F))
+            }
+        }
+    }
+    /// Write the characters of a single Word
+    fn write_text(&self, f: &mut fmt::Formatter, word: Word) -> fmt::Result {
+        match self.decoding {
+            Decoding::Word => write!(f, "{}", printable(word)),
+            Decoding::Byte | Decoding::Packed => write!(f, "{}{}", printable(word >> 8), printable(word & 0xFF)),
+            Decoding::Cell => write!(f, "{}", printable(word & 0x7F)),
+        }
+    }
+}
+
+/// Printable character for value or '.'
+fn printable(value: Word) -> char {
+    match char::from_u32(value as u32) {
+        Some(ch) if ch.is_ascii_graphic() || ch == ' ' => ch,
+        _ => '.',
+    }
+}
+
+impl<'mem> fmt::Display for View<'mem> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self.cell_width();
+        write!(f, "Memory")?;
+        for offset in 0..self.columns {
+            write!(f, " {:>width$X}", offset, width = width)?;
+        }
+        writeln!(f)?;
+        let mut elided = false;
+        let mut base = self.start;
+        while base < self.end {
+            let end = (base + self.columns).min(self.end);
+            if self.elide && (base..end).all(|address| self.memory.get(address as Word) == 0) {
+                if !elided {
+                    writeln!(f, "*")?;
+                    elided = true;
+                }
+                base = end;
+                continue;
+            }
+            elided = false;
+            write!(f, "0x{:04X}", base)?;
+            for address in base..end {
+                write!(f, " ")?;
+                self.write_cell(f, self.memory.get(address as Word))?;
+            }
+            for _ in end..base + self.columns {
+                write!(f, " {:width$}", "", width = width)?;
+            }
+            write!(f, " ")?;
+            for address in base..end {
+                self.write_text(f, self.memory.get(address as Word))?;
+            }
+            writeln!(f)?;
+            base = end;
+        }
+        Ok(())
+    }
+}
+
+impl<'mem> fmt::Debug for View<'mem> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decoding, Format};
+    use super::super::Memory;
+
+    #[test]
+    pub fn test_range_elision() {
+        let mut mem = Memory::new();
+        mem.write(0x0100, &[0x0048, 0x0069]).unwrap();
+        mem.set(0x0130, 0xFFFF);
+
+        let view = format!("{}", mem.view().range(0x0100, 0x40).columns(8));
+        assert_eq!(
+            "Memory    0    1    2    3    4    5    6    7\n\
+             0x0100 0048 0069 0000 0000 0000 0000 0000 0000 Hi......\n\
+             *\n\
+             0x0130 FFFF 0000 0000 0000 0000 0000 0000 0000 ........\n\
+             *\n",
+            view
+        );
+    }
+
+    #[test]
+    pub fn test_decodings() {
+        let mut mem = Memory::new();
+        mem.write(0x8000, &[0x4869, 0xF0C1, 0xFFFF]).unwrap();
+
+        let view = mem.view().range(0x8000, 3).columns(3).elide_zeros(false);
+        assert_eq!(
+            "Memory     0     1     2\n0x8000 48 69 F0 C1 FF FF Hi....\n",
+            format!("{}", view.decoding(Decoding::Byte))
+        );
+        assert_eq!(
+            "Memory    0    1    2\n0x8000 4869 F0C1 FFFF Hi....\n",
+            format!("{}", view.decoding(Decoding::Packed))
+        );
+        assert_eq!(
+            "Memory    0    1    2\n0x8000 48 i F0*A FF*. iA.\n",
+            format!("{}", view.decoding(Decoding::Cell))
+        );
+        assert_eq!(
+            "Memory      0      1      2\n0x8000  18537  -3903     -1 ...\n",
+            format!("{}", view.format(Format::Signed))
+        );
+        assert_eq!(
+            "Memory     0     1     2\n0x8000 18537 61633 65535 ...\n",
+            format!("{}", view.format(Format::Unsigned))
+        );
+    }
+}